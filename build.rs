@@ -0,0 +1,37 @@
+use std::{env, fs, process::Command};
+
+fn main() {
+    // The commit the server has been built from, can be overridden for builds which happen outside of the git repository
+    let git_commit = env::var("GIT_COMMIT").ok().unwrap_or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|commit| commit.trim().to_string())
+            .unwrap_or_else(|| String::from("unknown"))
+    });
+
+    // whatssock-lib is a path dependency, so its version can be read straight from its manifest
+    let lib_version = fs::read_to_string("../whatssock-lib/Cargo.toml")
+        .ok()
+        .and_then(|manifest| {
+            manifest.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+
+                (key.trim() == "version").then(|| value.trim().trim_matches('"').to_string())
+            })
+        })
+        .unwrap_or_else(|| String::from("unknown"));
+
+    println!("cargo:rustc-env=WHATSSOCK_GIT_COMMIT={git_commit}");
+    println!("cargo:rustc-env=WHATSSOCK_LIB_VERSION={lib_version}");
+
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=../whatssock-lib/Cargo.toml");
+    // The migrations are embedded into the binary, the paths above would otherwise keep cargo from noticing new ones
+    println!("cargo:rerun-if-changed=migrations");
+}
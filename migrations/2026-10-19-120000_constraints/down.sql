@@ -0,0 +1,11 @@
+DROP INDEX user_signin_tokens_user_id_idx;
+DROP INDEX messages_parent_chatroom_id_id_idx;
+
+DROP INDEX chatrooms_chatroom_id_key;
+DROP INDEX users_email_key;
+DROP INDEX users_username_key;
+
+ALTER TABLE chatrooms DROP CONSTRAINT chatrooms_last_message_id_fkey;
+ALTER TABLE messages DROP CONSTRAINT messages_owner_user_id_fkey;
+ALTER TABLE messages DROP CONSTRAINT messages_parent_chatroom_id_fkey;
+ALTER TABLE user_signin_tokens DROP CONSTRAINT user_signin_tokens_user_id_fkey;
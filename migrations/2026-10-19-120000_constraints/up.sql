@@ -0,0 +1,25 @@
+-- Sessions and messages are removed together with whatever they belong to
+ALTER TABLE user_signin_tokens
+    ADD CONSTRAINT user_signin_tokens_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+ALTER TABLE messages
+    ADD CONSTRAINT messages_parent_chatroom_id_fkey
+    FOREIGN KEY (parent_chatroom_id) REFERENCES chatrooms (id) ON DELETE CASCADE;
+
+-- A user's messages have to be reassigned before the user can be deleted, otherwise they would vanish from every chatroom
+ALTER TABLE messages
+    ADD CONSTRAINT messages_owner_user_id_fkey
+    FOREIGN KEY (owner_user_id) REFERENCES users (id) ON DELETE RESTRICT;
+
+ALTER TABLE chatrooms
+    ADD CONSTRAINT chatrooms_last_message_id_fkey
+    FOREIGN KEY (last_message_id) REFERENCES messages (id) ON DELETE SET NULL;
+
+-- Existing duplicates have to be resolved by hand before this migration can be applied
+CREATE UNIQUE INDEX users_username_key ON users (username);
+CREATE UNIQUE INDEX users_email_key ON users (email);
+CREATE UNIQUE INDEX chatrooms_chatroom_id_key ON chatrooms (chatroom_id);
+
+CREATE INDEX messages_parent_chatroom_id_id_idx ON messages (parent_chatroom_id, id);
+CREATE INDEX user_signin_tokens_user_id_idx ON user_signin_tokens (user_id);
@@ -0,0 +1,2 @@
+-- Left over from the diesel tutorial, nothing in the server has ever used it
+DROP TABLE IF EXISTS posts;
@@ -0,0 +1,4 @@
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP;
+
+-- Accounts created before verification existed are trusted, only new accounts start unverified
+UPDATE users SET email_verified_at = created_at;
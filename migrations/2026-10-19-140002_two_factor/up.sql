@@ -0,0 +1,21 @@
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE user_totp (
+    user_id INT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    -- The raw secret shared with the authenticator, it is needed to check the codes
+    secret BYTEA NOT NULL,
+    -- NULL until the user has entered a code from their authenticator, two-factor authentication is only enforced after that
+    confirmed_at TIMESTAMP,
+    -- The time step of the last accepted code, so that a code can not be used twice
+    last_used_step BIGINT
+);
+
+CREATE TABLE recovery_codes (
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- Only the SHA-256 hash of the code is stored, like the password reset tokens
+    code_hash BYTEA NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
@@ -0,0 +1,2 @@
+-- Fails while messages are still attributed to the tombstone, they have to be deleted or reassigned first
+DELETE FROM users WHERE username = '[deleted]';
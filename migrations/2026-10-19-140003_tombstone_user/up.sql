@@ -0,0 +1,3 @@
+-- The messages of deleted accounts are attributed to this user, it can not be logged into as nobody knows its password
+INSERT INTO users (username, passw, email, chatrooms_joined)
+VALUES ('[deleted]', gen_random_uuid()::text || gen_random_uuid()::text, 'deleted@invalid', '{}');
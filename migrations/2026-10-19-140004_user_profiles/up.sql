@@ -0,0 +1,11 @@
+CREATE TABLE user_profiles (
+    user_id INT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    display_name VARCHAR,
+    bio TEXT,
+    status_text VARCHAR,
+    status_emoji VARCHAR,
+    -- The image is small enough to be kept in the database, the content type is detected from its bytes when uploading
+    avatar BYTEA,
+    avatar_content_type VARCHAR,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
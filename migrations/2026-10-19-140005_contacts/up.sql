@@ -0,0 +1,24 @@
+-- A contact request from the requester to the addressee, the two are contacts of each other once it has been accepted
+CREATE TABLE contacts (
+    requester_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    addressee_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    -- NULL while the request is pending
+    accepted_at TIMESTAMP,
+    PRIMARY KEY (requester_id, addressee_id),
+    CHECK (requester_id <> addressee_id)
+);
+
+-- A pair of users is related at most once, whoever has sent the request
+CREATE UNIQUE INDEX contacts_pair_key ON contacts (LEAST(requester_id, addressee_id), GREATEST(requester_id, addressee_id));
+CREATE INDEX contacts_addressee_id_idx ON contacts (addressee_id);
+
+CREATE TABLE user_blocks (
+    blocker_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    blocked_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (blocker_id, blocked_id),
+    CHECK (blocker_id <> blocked_id)
+);
+
+CREATE INDEX user_blocks_blocked_id_idx ON user_blocks (blocked_id);
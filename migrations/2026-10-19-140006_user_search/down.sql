@@ -0,0 +1,3 @@
+DROP INDEX user_profiles_display_name_trgm_idx;
+DROP INDEX users_username_trgm_idx;
+ALTER TABLE users DROP COLUMN discoverability;
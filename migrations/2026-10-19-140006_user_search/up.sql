@@ -0,0 +1,11 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Who can find the user in the directory: everyone, only their contacts, or nobody
+ALTER TABLE users ADD COLUMN discoverability VARCHAR NOT NULL DEFAULT 'everyone'
+    CHECK (discoverability IN ('everyone', 'contacts', 'hidden'));
+
+UPDATE users SET discoverability = 'hidden' WHERE username = '[deleted]';
+
+-- Trigram indexes serve both the fuzzy matches and the case insensitive prefix matches of the search
+CREATE INDEX users_username_trgm_idx ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX user_profiles_display_name_trgm_idx ON user_profiles USING GIN (display_name gin_trgm_ops);
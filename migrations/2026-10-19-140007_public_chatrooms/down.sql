@@ -0,0 +1,9 @@
+DROP TABLE chatroom_icons;
+DROP INDEX chatrooms_chatroom_name_trgm_idx;
+DROP INDEX chatrooms_public_idx;
+ALTER TABLE chatrooms
+    DROP CONSTRAINT chatrooms_public_check,
+    DROP COLUMN topic_tags,
+    DROP COLUMN description,
+    DROP COLUMN is_public,
+    DROP COLUMN owner_user_id;
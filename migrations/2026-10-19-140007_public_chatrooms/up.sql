@@ -0,0 +1,20 @@
+ALTER TABLE chatrooms
+    ADD COLUMN owner_user_id INT REFERENCES users (id) ON DELETE SET NULL,
+    ADD COLUMN is_public BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN description TEXT,
+    ADD COLUMN topic_tags TEXT[] NOT NULL DEFAULT '{}',
+    -- The directory lists public chatrooms for anyone to join, so they can not be protected by a password or be direct messages
+    ADD CONSTRAINT chatrooms_public_check CHECK (NOT is_public OR (chatroom_password IS NULL AND NOT is_direct_message));
+
+-- Chatrooms have been created with their creator as the only participant
+UPDATE chatrooms SET owner_user_id = participants[1]
+    WHERE NOT is_direct_message AND participants[1] IN (SELECT id FROM users);
+
+CREATE INDEX chatrooms_public_idx ON chatrooms (id) WHERE is_public;
+CREATE INDEX chatrooms_chatroom_name_trgm_idx ON chatrooms USING GIN (chatroom_name gin_trgm_ops);
+
+CREATE TABLE chatroom_icons (
+    chatroom_uid INT PRIMARY KEY REFERENCES chatrooms (id) ON DELETE CASCADE,
+    icon BYTEA NOT NULL,
+    content_type VARCHAR NOT NULL
+);
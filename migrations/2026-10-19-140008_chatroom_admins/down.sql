@@ -0,0 +1,7 @@
+DELETE FROM messages WHERE event_kind IS NOT NULL;
+ALTER TABLE messages
+    DROP CONSTRAINT messages_kind_check,
+    DROP COLUMN event_payload,
+    DROP COLUMN event_kind,
+    ALTER COLUMN raw_message SET NOT NULL;
+ALTER TABLE chatrooms DROP COLUMN admin_user_ids;
@@ -0,0 +1,12 @@
+-- The owner is an admin of the chatroom without being listed here
+ALTER TABLE chatrooms ADD COLUMN admin_user_ids INT[] NOT NULL DEFAULT '{}';
+
+-- System messages record the changes made to a chatroom, they carry an event instead of a serialized ChatMessage
+ALTER TABLE messages
+    ALTER COLUMN raw_message DROP NOT NULL,
+    ADD COLUMN event_kind VARCHAR,
+    ADD COLUMN event_payload JSONB,
+    ADD CONSTRAINT messages_kind_check CHECK (
+        (raw_message IS NULL) = (event_kind IS NOT NULL)
+        AND (event_kind IS NULL) = (event_payload IS NULL)
+    );
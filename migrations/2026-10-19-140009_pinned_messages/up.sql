@@ -0,0 +1,9 @@
+CREATE TABLE pinned_messages (
+    message_id INT PRIMARY KEY REFERENCES messages (id) ON DELETE CASCADE,
+    chatroom_uid INT NOT NULL REFERENCES chatrooms (id) ON DELETE CASCADE,
+    -- NULL once the user who pinned the message has deleted their account
+    pinned_by INT REFERENCES users (id) ON DELETE SET NULL,
+    pinned_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX pinned_messages_chatroom_uid_idx ON pinned_messages (chatroom_uid, pinned_at);
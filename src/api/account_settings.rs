@@ -0,0 +1,301 @@
+use anyhow::Context;
+use axum::{Json, extract::State, http::StatusCode};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info_span};
+use utoipa::ToSchema;
+use whatssock_lib::UserSession;
+
+use crate::{
+    ServerState,
+    api::{
+        email_verification::{check_email_address, send_verification_email},
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        user_account_control::verify_user_session,
+    },
+    models::UserAccountEntry,
+    monitoring::RATE_LIMITED_REQUESTS_TOTAL,
+    rate_limit::LoginThrottle,
+    schema::{user_signin_tokens, users},
+};
+
+const USERNAME_MAX_CHARS: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    pub current_password: String,
+    pub new_password: String,
+    /// Logs the user out everywhere else, the session of the request stays valid.
+    #[serde(default)]
+    pub revoke_other_sessions: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangeUsernameRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// Failed logins are counted per username, thus changing it requires the password.
+    pub current_password: String,
+    /// Surrounding whitespace is trimmed, at most 32 characters.
+    pub new_username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangeEmailRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// The email address is where password resets are sent, thus changing it requires the password.
+    pub current_password: String,
+    pub new_email: String,
+    /// Logs the user out everywhere else, the session of the request stays valid.
+    #[serde(default)]
+    pub revoke_other_sessions: bool,
+}
+
+/// Changes the password of the user.
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/password",
+    tag = "accounts",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 204, description = "The password has been changed."),
+        (status = 401, description = "The session or the current password is invalid.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn change_password(
+    State(state): State<ServerState>,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<StatusCode, ApiError> {
+    let auth_limits = state.auth_limits.clone();
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_account = find_user_account(pg_connection, request.user_session.user_id)?;
+
+            check_current_password(
+                &auth_limits.failed_logins,
+                &user_account,
+                &request.current_password,
+            )?;
+
+            info_span!("db", query = "update_password")
+                .in_scope(|| {
+                    diesel::update(users::table.find(user_account.id))
+                        .set(users::passw.eq(request.new_password))
+                        .execute(pg_connection)
+                })
+                .context("An error occured while updating the user's password")?;
+
+            if request.revoke_other_sessions {
+                revoke_other_sessions(pg_connection, &request.user_session)?;
+            }
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Trims the username, and checks that it is between 1 and 32 characters long.
+pub fn validate_username(username: &str) -> Result<String, ApiError> {
+    let username = username.trim();
+
+    if username.is_empty() || username.chars().count() > USERNAME_MAX_CHARS {
+        return Err(ApiError::InvalidInput {
+            message: "A username has to be between 1 and 32 characters long.",
+        });
+    }
+
+    Ok(username.to_string())
+}
+
+/// Changes the username of the user.
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/username",
+    tag = "accounts",
+    request_body = ChangeUsernameRequest,
+    responses(
+        (status = 204, description = "The username has been changed."),
+        (status = 400, description = "The username is empty or too long.", body = ApiErrorResponse),
+        (status = 401, description = "The session or the current password is invalid.", body = ApiErrorResponse),
+        (status = 409, description = "The username is already taken.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn change_username(
+    State(state): State<ServerState>,
+    Json(request): Json<ChangeUsernameRequest>,
+) -> Result<StatusCode, ApiError> {
+    let new_username = validate_username(&request.new_username)?;
+
+    let auth_limits = state.auth_limits.clone();
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_account = find_user_account(pg_connection, request.user_session.user_id)?;
+
+            // Otherwise a stolen session could rename the account to escape the failed logins of its username
+            check_current_password(
+                &auth_limits.failed_logins,
+                &user_account,
+                &request.current_password,
+            )?;
+
+            info_span!("db", query = "update_username")
+                .in_scope(|| {
+                    diesel::update(users::table.find(user_account.id))
+                        .set(users::username.eq(new_username))
+                        .execute(pg_connection)
+                })
+                // The unique index on the username turns a taken one into a conflict
+                .map_err(|err| {
+                    ApiError::from_query_error(
+                        err,
+                        "An error occured while updating the user's username",
+                    )
+                })?;
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Changes the email address of the user, which has to be verified again.
+/// A verification email is sent to the new address.
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/email",
+    tag = "accounts",
+    request_body = ChangeEmailRequest,
+    responses(
+        (status = 204, description = "The email address has been changed, and a verification email has been sent to it."),
+        (status = 400, description = "The email address is invalid.", body = ApiErrorResponse),
+        (status = 401, description = "The session or the current password is invalid.", body = ApiErrorResponse),
+        (status = 409, description = "The email address is already in use.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn change_email(
+    State(state): State<ServerState>,
+    Json(request): Json<ChangeEmailRequest>,
+) -> Result<StatusCode, ApiError> {
+    check_email_address(&request.new_email)?;
+
+    let auth_limits = state.auth_limits.clone();
+    let user_id = request.user_session.user_id;
+    let new_email = request.new_email.clone();
+
+    let changed = state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_account = find_user_account(pg_connection, request.user_session.user_id)?;
+
+            check_current_password(
+                &auth_limits.failed_logins,
+                &user_account,
+                &request.current_password,
+            )?;
+
+            // Setting the same address again must not throw away its verification
+            if user_account.email == request.new_email {
+                return Ok(false);
+            }
+
+            info_span!("db", query = "update_email")
+                .in_scope(|| {
+                    diesel::update(users::table.find(user_account.id))
+                        .set((
+                            users::email.eq(request.new_email),
+                            users::email_verified_at.eq(None::<chrono::NaiveDateTime>),
+                        ))
+                        .execute(pg_connection)
+                })
+                // The unique index on the email turns a taken one into a conflict
+                .map_err(|err| {
+                    ApiError::from_query_error(
+                        err,
+                        "An error occured while updating the user's email",
+                    )
+                })?;
+
+            if request.revoke_other_sessions {
+                revoke_other_sessions(pg_connection, &request.user_session)?;
+            }
+
+            Ok(true)
+        })
+        .await?;
+
+    // The links sent to the previous address are bound to it, so they stop working by themselves
+    if changed && let Err(err) = send_verification_email(&state, user_id, new_email).await {
+        error!("An error occured while sending the verification email: {err:#}");
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn find_user_account(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+) -> Result<UserAccountEntry, ApiError> {
+    Ok(info_span!("db", query = "find_user_account")
+        .in_scope(|| {
+            users::table
+                .find(user_id)
+                .select(UserAccountEntry::as_select())
+                .first(pg_connection)
+        })
+        .context("An error occured while fetching the user's account")?)
+}
+
+/// Checks the password the user has entered to confirm a change.
+/// Incorrect passwords count as failed logins, so that a stolen session can not be used to guess it.
+pub fn check_current_password(
+    failed_logins: &LoginThrottle,
+    user_account: &UserAccountEntry,
+    password: &str,
+) -> Result<(), ApiError> {
+    if let Err(retry_after) = failed_logins.check(&user_account.username) {
+        counter!(RATE_LIMITED_REQUESTS_TOTAL, "limit" => "login").increment(1);
+
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
+    if user_account.passw != password {
+        failed_logins.record_failure(&user_account.username);
+
+        return Err(ApiError::InvalidCredentials);
+    }
+
+    Ok(())
+}
+
+/// Deletes every session of the user apart from `user_session`.
+fn revoke_other_sessions(
+    pg_connection: &mut PgConnection,
+    user_session: &UserSession,
+) -> Result<(), ApiError> {
+    info_span!("db", query = "delete_other_user_sessions")
+        .in_scope(|| {
+            diesel::delete(
+                user_signin_tokens::table
+                    .filter(user_signin_tokens::user_id.eq(user_session.user_id))
+                    .filter(user_signin_tokens::session_token.ne(&user_session.session_token)),
+            )
+            .execute(pg_connection)
+        })
+        .context("An error occured while revoking the user's other sessions")?;
+
+    Ok(())
+}
@@ -0,0 +1,564 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, JoinOnDsl, NullableExpressionMethods,
+    OptionalExtension, PgArrayExpressionMethods, PgConnection, PgSortExpressionMethods,
+    PgTextExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper,
+    sql_types::{Array, Int4, Nullable},
+    upsert::excluded,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::UserSession;
+
+use crate::{
+    ServerState,
+    api::{
+        account_settings::find_user_account,
+        chatroom_settings::find_administered_chatroom,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        profiles::{bounded_text, decode_image},
+        system_messages::{SystemEvent, emit_system_event},
+        user_account_control::{find_chatroom, verify_user_session},
+        user_search::escape_like,
+    },
+    models::ChatroomEntry,
+    schema::{chatroom_icons, chatrooms, messages},
+};
+
+pub const DESCRIPTION_MAX_CHARS: usize = 300;
+
+const TOPIC_TAGS_MAX: usize = 5;
+
+const TOPIC_TAG_MAX_CHARS: usize = 24;
+
+const QUERY_MAX_CHARS: usize = 64;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+
+const MAX_PAGE_SIZE: i64 = 50;
+
+diesel::define_sql_function! {
+    /// The number of elements of an array, 0 for empty ones (where `array_length` returns `NULL`).
+    fn cardinality(array: Array<Nullable<Int4>>) -> Int4;
+}
+
+/// The listing of a chatroom in the directory, replacing the previous one.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateListingRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// Only chatrooms without a password can be public.
+    pub is_public: bool,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// At most 5 tags made of letters, digits and dashes, they are stored in lowercase.
+    #[serde(default)]
+    pub topic_tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UploadChatroomIconRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// The base64 encoded image, a PNG, JPEG, GIF or WebP file.
+    pub image: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectorySort {
+    /// The chatrooms with the most participants first.
+    #[default]
+    Members,
+    /// The chatrooms with the most recent message first.
+    Activity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatroomDirectoryRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// Searched for in the names and the descriptions of the chatrooms.
+    #[serde(default)]
+    pub query: Option<String>,
+    /// Only lists chatrooms with this topic tag.
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub sort: DirectorySort,
+    /// The number of chatrooms to skip, pass the `next_offset` of the previous page.
+    #[serde(default)]
+    pub offset: i64,
+    /// At most 50, 20 if not set.
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PublicChatroom {
+    pub chatroom_uid: i32,
+    /// The public id, anyone can look the chatroom up with it.
+    pub chatroom_id: String,
+    pub chatroom_name: String,
+    pub description: Option<String>,
+    pub topic_tags: Vec<String>,
+    pub member_count: usize,
+    /// The icon is fetched from `/api/v1/chatrooms/{chatroom_uid}/icon`.
+    pub has_icon: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatroomDirectoryResponse {
+    pub chatrooms: Vec<PublicChatroom>,
+    /// The offset of the next page, `None` if this is the last one.
+    pub next_offset: Option<i64>,
+}
+
+/// Lists a chatroom in the directory, or removes it from there.
+/// Only the owner of the chatroom can change its listing, and making it public requires a verified email address.
+#[utoipa::path(
+    put,
+    path = "/api/v1/chatrooms/{chatroom_uid}/listing",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = UpdateListingRequest,
+    responses(
+        (status = 200, body = PublicChatroom),
+        (status = 400, description = "The chatroom has a password, or the description or the tags are out of bounds.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user does not own the chatroom, or has not verified their email address.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn update_listing(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(request): Json<UpdateListingRequest>,
+) -> Result<Json<PublicChatroom>, ApiError> {
+    let description = bounded_text(
+        request.description,
+        DESCRIPTION_MAX_CHARS,
+        "The description can be at most 300 characters long.",
+    )?;
+    let topic_tags = normalize_topic_tags(request.topic_tags)?;
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+
+            let chatroom_entry = pg_connection.transaction(|pg_connection| {
+                let chatroom_entry = find_owned_chatroom(pg_connection, chatroom_uid, user_id)?;
+
+                if request.is_public {
+                    if chatroom_entry.chatroom_password.is_some() {
+                        return Err(ApiError::InvalidInput {
+                            message: "Chatrooms protected by a password can not be public.",
+                        });
+                    }
+
+                    // Like creating a chatroom without a password, advertising one is not for throwaway accounts
+                    let user_account = find_user_account(pg_connection, user_id)?;
+
+                    if user_account.email_verified_at.is_none() {
+                        return Err(ApiError::EmailNotVerified);
+                    }
+                }
+
+                let previous_description = chatroom_entry.description;
+
+                let chatroom_entry: ChatroomEntry =
+                    info_span!("db", query = "update_chatroom_listing")
+                        .in_scope(|| {
+                            diesel::update(chatrooms::table.find(chatroom_uid))
+                                .set((
+                                    chatrooms::is_public.eq(request.is_public),
+                                    chatrooms::description.eq(description),
+                                    chatrooms::topic_tags.eq(topic_tags),
+                                ))
+                                .returning(ChatroomEntry::as_returning())
+                                .get_result(pg_connection)
+                        })
+                        .context("An error occured while updating the chatroom's listing")?;
+
+                if chatroom_entry.description != previous_description {
+                    emit_system_event(
+                        pg_connection,
+                        chatroom_uid,
+                        user_id,
+                        SystemEvent::DescriptionChanged {
+                            description: chatroom_entry.description.clone(),
+                        },
+                    )?;
+                }
+
+                Ok::<_, ApiError>(chatroom_entry)
+            })?;
+
+            let icon_uids = chatrooms_with_icon(pg_connection, &[chatroom_uid])?;
+
+            Ok(Json(public_chatroom_of(chatroom_entry, &icon_uids)))
+        })
+        .await
+}
+
+/// Replaces the icon of a chatroom, only its admins can do this.
+#[utoipa::path(
+    put,
+    path = "/api/v1/chatrooms/{chatroom_uid}/icon",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = UploadChatroomIconRequest,
+    responses(
+        (status = 204, description = "The icon has been replaced."),
+        (status = 400, description = "The image is malformed, too big, or not in a supported format.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not an admin of the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn upload_chatroom_icon(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(request): Json<UploadChatroomIconRequest>,
+) -> Result<StatusCode, ApiError> {
+    let (icon, content_type) = decode_image(&request.image, state.config.avatar_max_size)?;
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                find_administered_chatroom(pg_connection, chatroom_uid, user_id)?;
+
+                store_chatroom_icon(pg_connection, chatroom_uid, icon, content_type)?;
+
+                emit_system_event(
+                    pg_connection,
+                    chatroom_uid,
+                    user_id,
+                    SystemEvent::IconChanged,
+                )?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Removes the icon of a chatroom, only its admins can do this.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/chatrooms/{chatroom_uid}/icon",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The icon has been removed."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not an admin of the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn delete_chatroom_icon(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            pg_connection.transaction(|pg_connection| {
+                find_administered_chatroom(pg_connection, chatroom_uid, user_session.user_id)?;
+
+                // Removing a missing icon is not a change worth recording
+                if delete_icon(pg_connection, chatroom_uid)? {
+                    emit_system_event(
+                        pg_connection,
+                        chatroom_uid,
+                        user_session.user_id,
+                        SystemEvent::IconRemoved,
+                    )?;
+                }
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Returns the icon of a chatroom, to its participants or to anyone if the chatroom is public.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/{chatroom_uid}/icon",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, description = "The image, with its content type.", content_type = "image/*"),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist, is not visible, or has no icon.", body = ApiErrorResponse),
+    )
+)]
+pub async fn fetch_chatroom_icon(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (content_type, icon) = state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+            if !chatroom_entry.is_public
+                && !chatroom_entry
+                    .participants
+                    .contains(&Some(user_session.user_id))
+            {
+                return Err(ApiError::ChatroomNotFound);
+            }
+
+            info_span!("db", query = "find_chatroom_icon")
+                .in_scope(|| {
+                    chatroom_icons::table
+                        .find(chatroom_uid)
+                        .select((chatroom_icons::content_type, chatroom_icons::icon))
+                        .first::<(String, Vec<u8>)>(pg_connection)
+                        .optional()
+                })
+                .context("An error occured while fetching the chatroom's icon")?
+                .ok_or(ApiError::ChatroomNotFound)
+        })
+        .await?;
+
+    Ok(([(header::CONTENT_TYPE, content_type)], icon))
+}
+
+/// Lists and searches the public chatrooms.
+/// Chatrooms protected by a password and direct messages are never listed.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/directory",
+    tag = "chatrooms",
+    request_body = ChatroomDirectoryRequest,
+    responses(
+        (status = 200, body = ChatroomDirectoryResponse),
+        (status = 400, description = "The query is too long, or the page is out of bounds.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn fetch_chatroom_directory(
+    State(state): State<ServerState>,
+    Json(request): Json<ChatroomDirectoryRequest>,
+) -> Result<Json<ChatroomDirectoryResponse>, ApiError> {
+    let query = bounded_text(
+        request.query,
+        QUERY_MAX_CHARS,
+        "The search query can be at most 64 characters long.",
+    )?;
+    let tag = request.tag.map(|tag| tag.trim().to_lowercase());
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) || request.offset < 0 {
+        return Err(ApiError::InvalidInput {
+            message: "The limit has to be between 1 and 50, and the offset can not be negative.",
+        });
+    }
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            // The conditions of the listing are enforced by a constraint as well, checking them here keeps the directory safe from a dropped constraint
+            let mut directory_query = chatrooms::table
+                .left_join(
+                    messages::table.on(chatrooms::last_message_id.eq(messages::id.nullable())),
+                )
+                .filter(chatrooms::is_public.eq(true))
+                .filter(chatrooms::chatroom_password.is_null())
+                .filter(chatrooms::is_direct_message.eq(false))
+                .select(ChatroomEntry::as_select())
+                .into_boxed();
+
+            if let Some(query) = query {
+                let pattern = format!("%{}%", escape_like(&query));
+
+                directory_query = directory_query.filter(
+                    chatrooms::chatroom_name
+                        .ilike(pattern.clone())
+                        .nullable()
+                        .or(chatrooms::description.ilike(pattern)),
+                );
+            }
+
+            if let Some(tag) = tag {
+                directory_query =
+                    directory_query.filter(chatrooms::topic_tags.contains(vec![Some(tag)]));
+            }
+
+            directory_query = match request.sort {
+                DirectorySort::Members => directory_query.order((
+                    cardinality(chatrooms::participants).desc(),
+                    chatrooms::id.desc(),
+                )),
+                DirectorySort::Activity => directory_query.order((
+                    messages::send_date.nullable().desc().nulls_last(),
+                    chatrooms::id.desc(),
+                )),
+            };
+
+            // One more row than requested tells whether there is a next page
+            let mut chatroom_entries = info_span!("db", query = "find_public_chatrooms")
+                .in_scope(|| {
+                    directory_query
+                        .offset(request.offset)
+                        .limit(limit + 1)
+                        .load::<ChatroomEntry>(pg_connection)
+                })
+                .context("An error occured while fetching the public chatrooms")?;
+
+            let next_offset =
+                (chatroom_entries.len() as i64 > limit).then(|| request.offset + limit);
+
+            chatroom_entries.truncate(limit as usize);
+
+            let chatroom_uids: Vec<i32> = chatroom_entries.iter().map(|entry| entry.id).collect();
+            let icon_uids = chatrooms_with_icon(pg_connection, &chatroom_uids)?;
+
+            Ok(Json(ChatroomDirectoryResponse {
+                chatrooms: chatroom_entries
+                    .into_iter()
+                    .map(|entry| public_chatroom_of(entry, &icon_uids))
+                    .collect(),
+                next_offset,
+            }))
+        })
+        .await
+}
+
+/// Fetches a chatroom, which the user has to own.
+pub fn find_owned_chatroom(
+    pg_connection: &mut PgConnection,
+    chatroom_uid: i32,
+    user_id: i32,
+) -> Result<ChatroomEntry, ApiError> {
+    let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+    if chatroom_entry.owner_user_id != Some(user_id) {
+        return Err(ApiError::NotChatroomOwner);
+    }
+
+    Ok(chatroom_entry)
+}
+
+pub fn store_chatroom_icon(
+    pg_connection: &mut PgConnection,
+    chatroom_uid: i32,
+    icon: Vec<u8>,
+    content_type: &str,
+) -> Result<(), ApiError> {
+    info_span!("db", query = "upsert_chatroom_icon")
+        .in_scope(|| {
+            diesel::insert_into(chatroom_icons::table)
+                .values((
+                    chatroom_icons::chatroom_uid.eq(chatroom_uid),
+                    chatroom_icons::icon.eq(icon),
+                    chatroom_icons::content_type.eq(content_type),
+                ))
+                .on_conflict(chatroom_icons::chatroom_uid)
+                .do_update()
+                .set((
+                    chatroom_icons::icon.eq(excluded(chatroom_icons::icon)),
+                    chatroom_icons::content_type.eq(excluded(chatroom_icons::content_type)),
+                ))
+                .execute(pg_connection)
+        })
+        .context("An error occured while storing the chatroom's icon")?;
+
+    Ok(())
+}
+
+/// Returns whether the chatroom had an icon.
+pub fn delete_icon(pg_connection: &mut PgConnection, chatroom_uid: i32) -> Result<bool, ApiError> {
+    let deleted_rows = info_span!("db", query = "delete_chatroom_icon")
+        .in_scope(|| {
+            diesel::delete(chatroom_icons::table.find(chatroom_uid)).execute(pg_connection)
+        })
+        .context("An error occured while deleting the chatroom's icon")?;
+
+    Ok(deleted_rows > 0)
+}
+
+/// Lowercases and deduplicates the tags, and checks that they are within bounds.
+fn normalize_topic_tags(topic_tags: Vec<String>) -> Result<Vec<String>, ApiError> {
+    let mut normalized: Vec<String> = Vec::new();
+
+    for tag in topic_tags {
+        let tag = tag.trim().to_lowercase();
+
+        if tag.is_empty()
+            || tag.chars().count() > TOPIC_TAG_MAX_CHARS
+            || !tag.chars().all(|c| c.is_alphanumeric() || c == '-')
+        {
+            return Err(ApiError::InvalidInput {
+                message: "Tags are made of at most 24 letters, digits and dashes.",
+            });
+        }
+
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+
+    if normalized.len() > TOPIC_TAGS_MAX {
+        return Err(ApiError::InvalidInput {
+            message: "A chatroom can have at most 5 tags.",
+        });
+    }
+
+    Ok(normalized)
+}
+
+/// The ids of the chatrooms among `chatroom_uids` which have an icon.
+fn chatrooms_with_icon(
+    pg_connection: &mut PgConnection,
+    chatroom_uids: &[i32],
+) -> Result<HashSet<i32>, ApiError> {
+    Ok(info_span!("db", query = "find_chatroom_icons")
+        .in_scope(|| {
+            chatroom_icons::table
+                .filter(chatroom_icons::chatroom_uid.eq_any(chatroom_uids))
+                .select(chatroom_icons::chatroom_uid)
+                .load::<i32>(pg_connection)
+        })
+        .context("An error occured while fetching the chatrooms' icons")?
+        .into_iter()
+        .collect())
+}
+
+fn public_chatroom_of(chatroom_entry: ChatroomEntry, icon_uids: &HashSet<i32>) -> PublicChatroom {
+    PublicChatroom {
+        chatroom_uid: chatroom_entry.id,
+        chatroom_id: chatroom_entry.chatroom_id,
+        chatroom_name: chatroom_entry.chatroom_name,
+        description: chatroom_entry.description,
+        topic_tags: chatroom_entry.topic_tags.into_iter().flatten().collect(),
+        member_count: chatroom_entry.participants.iter().flatten().count(),
+        has_icon: icon_uids.contains(&chatroom_entry.id),
+    }
+}
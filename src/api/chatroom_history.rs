@@ -0,0 +1,187 @@
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{ChatMessage, UserSession};
+
+use crate::{
+    ServerState,
+    api::{
+        contacts::blocked_user_ids,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        system_messages::SystemEvent,
+        user_account_control::{find_chatroom, verify_user_session},
+    },
+    models::MessageEntry,
+    schema::messages,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatroomHistoryRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// Only returns the messages sent before this one, pass the `next_before_message_id` of the previous page.
+    /// The most recent messages are returned if not set.
+    #[serde(default)]
+    pub before_message_id: Option<i32>,
+    /// At most 200, 50 if not set.
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+/// What an entry of the history holds.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimelineContent {
+    /// A message sent by a participant.
+    Message {
+        /// `None` if the stored message could not be decoded.
+        #[schema(value_type = Option<Object>)]
+        message: Option<ChatMessage>,
+    },
+    /// A change made to the chatroom, clients show it as a line of its own.
+    System { event: SystemEvent },
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TimelineEntry {
+    pub message_id: i32,
+    /// The sender of the message, or the user who made the change.
+    pub user_id: i32,
+    #[schema(value_type = String, format = DateTime)]
+    pub send_date: chrono::NaiveDateTime,
+    pub content: TimelineContent,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChatroomHistoryResponse {
+    /// The oldest entry first.
+    pub entries: Vec<TimelineEntry>,
+    /// The id to pass as `before_message_id` to fetch older entries, `None` if there are none.
+    pub next_before_message_id: Option<i32>,
+}
+
+/// Returns the messages and the system messages of a chatroom in the order they were sent, one page at a time from the most recent.
+/// Messages of users the requester has blocked are left out, the changes they have made to the chatroom are not.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/{chatroom_uid}/history",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = ChatroomHistoryRequest,
+    responses(
+        (status = 200, body = ChatroomHistoryResponse),
+        (status = 400, description = "The limit is out of bounds.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not a participant of the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn fetch_chatroom_history(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(request): Json<ChatroomHistoryRequest>,
+) -> Result<Json<ChatroomHistoryResponse>, ApiError> {
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::InvalidInput {
+            message: "The limit has to be between 1 and 200.",
+        });
+    }
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+            let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+            if !chatroom_entry.participants.contains(&Some(user_id)) {
+                return Err(ApiError::NotChatroomMember);
+            }
+
+            let blocked_ids = blocked_user_ids(pg_connection, user_id)?;
+
+            let mut history_query = messages::table
+                .filter(messages::parent_chatroom_id.eq(chatroom_uid))
+                .filter(
+                    messages::event_kind
+                        .is_not_null()
+                        .or(messages::owner_user_id.ne_all(blocked_ids)),
+                )
+                .select(MessageEntry::as_select())
+                .into_boxed();
+
+            if let Some(before_message_id) = request.before_message_id {
+                history_query = history_query.filter(messages::id.lt(before_message_id));
+            }
+
+            // Ids grow with every message, so they order the history as it was sent
+            // One more row than requested tells whether there are older entries
+            let mut message_entries = info_span!("db", query = "find_chatroom_history")
+                .in_scope(|| {
+                    history_query
+                        .order(messages::id.desc())
+                        .limit(limit + 1)
+                        .load::<MessageEntry>(pg_connection)
+                })
+                .context("An error occured while fetching the chatroom's history")?;
+
+            let has_older_entries = message_entries.len() as i64 > limit;
+
+            message_entries.truncate(limit as usize);
+            message_entries.reverse();
+
+            let next_before_message_id = message_entries
+                .first()
+                .filter(|_| has_older_entries)
+                .map(|message_entry| message_entry.id);
+
+            Ok(Json(ChatroomHistoryResponse {
+                entries: message_entries
+                    .into_iter()
+                    .map(timeline_entry_of)
+                    .collect::<Result<_, ApiError>>()?,
+                next_before_message_id,
+            }))
+        })
+        .await
+}
+
+pub fn timeline_entry_of(message_entry: MessageEntry) -> Result<TimelineEntry, ApiError> {
+    let content = match message_entry.event_payload {
+        Some(event_payload) => TimelineContent::System {
+            event: serde_json::from_value(event_payload).with_context(|| {
+                format!(
+                    "An error occured while decoding the event of system message {}",
+                    message_entry.id
+                )
+            })?,
+        },
+        // The messages are stored serialized with rmp_serde
+        None => TimelineContent::Message {
+            message: message_entry
+                .raw_message
+                .as_deref()
+                .and_then(|raw_message| rmp_serde::from_slice(raw_message).ok()),
+        },
+    };
+
+    Ok(TimelineEntry {
+        message_id: message_entry.id,
+        user_id: message_entry.owner_user_id,
+        send_date: message_entry.send_date,
+        content,
+    })
+}
@@ -0,0 +1,450 @@
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+    SelectableHelper,
+    dsl::{array_append, array_remove},
+};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{FetchChatroomResponse, UserSession};
+
+use crate::{
+    ServerState,
+    api::{
+        chatroom_settings::find_administered_chatroom,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        system_messages::{ChatroomRole, SystemEvent, emit_system_event},
+        user_account_control::{find_chatroom, verify_user_session},
+    },
+    models::ChatroomEntry,
+    schema::{chatrooms, users},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JoinChatroomRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// The public id of the chatroom.
+    pub chatroom_id: String,
+    /// Required if the chatroom is protected by a password.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// Adds the user to the participants of a chatroom, joining a chatroom the user is already in does nothing.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/join",
+    tag = "chatrooms",
+    request_body = JoinChatroomRequest,
+    responses(
+        (status = 200, body = schemas::FetchChatroomResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist, or its password is incorrect.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn join_chatroom(
+    State(state): State<ServerState>,
+    Json(request): Json<JoinChatroomRequest>,
+) -> Result<Json<FetchChatroomResponse>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                // Direct messages are only reachable by their two participants
+                // The row stays locked until the user has been added, so that concurrent joins of the same user add them once
+                let chatroom_entry = info_span!("db", query = "lock_chatroom_by_id")
+                    .in_scope(|| {
+                        chatrooms::table
+                            .filter(chatrooms::chatroom_id.eq(&request.chatroom_id))
+                            .filter(chatrooms::is_direct_message.eq(false))
+                            .select(ChatroomEntry::as_select())
+                            .for_update()
+                            .first(pg_connection)
+                            .optional()
+                    })
+                    .context("An error occured while fetching the chatroom")?
+                    .ok_or(ApiError::ChatroomNotFound)?;
+
+                if chatroom_entry.chatroom_password.is_some()
+                    && chatroom_entry.chatroom_password != request.password
+                {
+                    return Err(ApiError::ChatroomNotFound);
+                }
+
+                if !chatroom_entry.participants.contains(&Some(user_id)) {
+                    add_participant(pg_connection, chatroom_entry.id, user_id)?;
+
+                    emit_system_event(
+                        pg_connection,
+                        chatroom_entry.id,
+                        user_id,
+                        SystemEvent::MemberJoined,
+                    )?;
+                }
+
+                let chatroom_entry = find_chatroom(pg_connection, chatroom_entry.id)?;
+
+                Ok(Json(FetchChatroomResponse {
+                    chatroom_uid: chatroom_entry.id,
+                    chatroom_id: chatroom_entry.chatroom_id,
+                    chatroom_name: chatroom_entry.chatroom_name,
+                    participants: chatroom_entry.participants,
+                    is_direct_message: chatroom_entry.is_direct_message,
+                    last_message_id: chatroom_entry.last_message_id,
+                }))
+            })
+        })
+        .await
+}
+
+/// Removes the user from the participants of a chatroom.
+/// The owner has to hand the chatroom over first, unless they are its last participant.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/{chatroom_uid}/leave",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The user has left the chatroom."),
+        (status = 400, description = "The chatroom is a direct message, or the user owns it and is not its last participant.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not a participant of the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn leave_chatroom(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let user_id = user_session.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+                if !chatroom_entry.participants.contains(&Some(user_id)) {
+                    return Err(ApiError::NotChatroomMember);
+                }
+
+                // The direct message of two users is reused whenever they open it again
+                if chatroom_entry.is_direct_message {
+                    return Err(ApiError::InvalidInput {
+                        message: "Direct messages can not be left.",
+                    });
+                }
+
+                if chatroom_entry.owner_user_id == Some(user_id) {
+                    if chatroom_entry.participants.iter().flatten().count() > 1 {
+                        return Err(ApiError::InvalidInput {
+                            message: "The owner has to hand the chatroom over before leaving it.",
+                        });
+                    }
+
+                    info_span!("db", query = "clear_chatroom_owner")
+                        .in_scope(|| {
+                            diesel::update(chatrooms::table.find(chatroom_uid))
+                                .set(chatrooms::owner_user_id.eq(None::<i32>))
+                                .execute(pg_connection)
+                        })
+                        .context("An error occured while clearing the chatroom's owner")?;
+                }
+
+                remove_participant(pg_connection, chatroom_uid, user_id)?;
+
+                emit_system_event(
+                    pg_connection,
+                    chatroom_uid,
+                    user_id,
+                    SystemEvent::MemberLeft,
+                )?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Removes a participant from a chatroom, only its admins can do this.
+/// Admins can only be removed by the owner, who can not be removed.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/chatrooms/{chatroom_uid}/participants/{user_id}",
+    tag = "chatrooms",
+    params(
+        ("chatroom_uid" = i32, Path, description = "The internal id of the chatroom."),
+        ("user_id" = i32, Path, description = "The participant to remove."),
+    ),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The participant has been removed."),
+        (status = 400, description = "The user is not a participant, is the owner, or is the one making the request.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not an admin of the chatroom, or removes another admin without owning the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn kick_participant(
+    State(state): State<ServerState>,
+    Path((chatroom_uid, kicked_user_id)): Path<(i32, i32)>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let user_id = user_session.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                let chatroom_entry =
+                    find_administered_chatroom(pg_connection, chatroom_uid, user_id)?;
+
+                if kicked_user_id == user_id
+                    || chatroom_entry.owner_user_id == Some(kicked_user_id)
+                    || !chatroom_entry.participants.contains(&Some(kicked_user_id))
+                {
+                    return Err(ApiError::InvalidInput {
+                        message: "Only other participants than the owner can be removed.",
+                    });
+                }
+
+                if chatroom_entry
+                    .admin_user_ids
+                    .contains(&Some(kicked_user_id))
+                    && chatroom_entry.owner_user_id != Some(user_id)
+                {
+                    return Err(ApiError::NotChatroomOwner);
+                }
+
+                remove_participant(pg_connection, chatroom_uid, kicked_user_id)?;
+
+                emit_system_event(
+                    pg_connection,
+                    chatroom_uid,
+                    user_id,
+                    SystemEvent::MemberKicked {
+                        user_id: kicked_user_id,
+                    },
+                )?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Makes a participant an admin of the chatroom, only its owner can do this.
+#[utoipa::path(
+    put,
+    path = "/api/v1/chatrooms/{chatroom_uid}/admins/{user_id}",
+    tag = "chatrooms",
+    params(
+        ("chatroom_uid" = i32, Path, description = "The internal id of the chatroom."),
+        ("user_id" = i32, Path, description = "The participant to promote."),
+    ),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The participant is an admin."),
+        (status = 400, description = "The user is not a participant, or is the owner.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user does not own the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn add_admin(
+    State(state): State<ServerState>,
+    Path((chatroom_uid, admin_user_id)): Path<(i32, i32)>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            change_role(
+                pg_connection,
+                &user_session,
+                chatroom_uid,
+                admin_user_id,
+                ChatroomRole::Admin,
+            )
+        })
+        .await
+}
+
+/// Makes an admin a regular participant again, only the owner of the chatroom can do this.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/chatrooms/{chatroom_uid}/admins/{user_id}",
+    tag = "chatrooms",
+    params(
+        ("chatroom_uid" = i32, Path, description = "The internal id of the chatroom."),
+        ("user_id" = i32, Path, description = "The admin to demote."),
+    ),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The participant is not an admin."),
+        (status = 400, description = "The user is not a participant, or is the owner.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user does not own the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn remove_admin(
+    State(state): State<ServerState>,
+    Path((chatroom_uid, admin_user_id)): Path<(i32, i32)>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            change_role(
+                pg_connection,
+                &user_session,
+                chatroom_uid,
+                admin_user_id,
+                ChatroomRole::Member,
+            )
+        })
+        .await
+}
+
+/// Gives a participant `role`, recording the change if the participant did not have it yet.
+fn change_role(
+    pg_connection: &mut PgConnection,
+    user_session: &UserSession,
+    chatroom_uid: i32,
+    participant_id: i32,
+    role: ChatroomRole,
+) -> Result<StatusCode, ApiError> {
+    verify_user_session(pg_connection, user_session)?;
+
+    let user_id = user_session.user_id;
+
+    pg_connection.transaction(|pg_connection| {
+        let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+        if chatroom_entry.owner_user_id != Some(user_id) {
+            return Err(ApiError::NotChatroomOwner);
+        }
+
+        if participant_id == user_id || !chatroom_entry.participants.contains(&Some(participant_id))
+        {
+            return Err(ApiError::InvalidInput {
+                message: "Only other participants than the owner can be given a role.",
+            });
+        }
+
+        let is_admin = chatroom_entry
+            .admin_user_ids
+            .contains(&Some(participant_id));
+
+        if is_admin == (role == ChatroomRole::Admin) {
+            return Ok(StatusCode::NO_CONTENT);
+        }
+
+        // Removing first keeps the list free of duplicates
+        let admin_user_ids = array_remove(chatrooms::admin_user_ids, Some(participant_id));
+
+        info_span!("db", query = "update_chatroom_admins")
+            .in_scope(|| match role {
+                ChatroomRole::Admin => diesel::update(chatrooms::table.find(chatroom_uid))
+                    .set(
+                        chatrooms::admin_user_ids
+                            .eq(array_append(admin_user_ids, Some(participant_id))),
+                    )
+                    .execute(pg_connection),
+                ChatroomRole::Member => diesel::update(chatrooms::table.find(chatroom_uid))
+                    .set(chatrooms::admin_user_ids.eq(admin_user_ids))
+                    .execute(pg_connection),
+            })
+            .context("An error occured while updating the chatroom's admins")?;
+
+        emit_system_event(
+            pg_connection,
+            chatroom_uid,
+            user_id,
+            SystemEvent::RoleChanged {
+                user_id: participant_id,
+                role,
+            },
+        )?;
+
+        Ok(StatusCode::NO_CONTENT)
+    })
+}
+
+fn add_participant(
+    pg_connection: &mut PgConnection,
+    chatroom_uid: i32,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    info_span!("db", query = "add_chatroom_participant")
+        .in_scope(|| {
+            diesel::update(chatrooms::table.find(chatroom_uid))
+                .set(
+                    chatrooms::participants
+                        .eq(array_append(chatrooms::participants, Some(user_id))),
+                )
+                .execute(pg_connection)
+        })
+        .context("An error occured while adding the user to the chatroom's participants")?;
+
+    info_span!("db", query = "update_chatrooms_joined")
+        .in_scope(|| {
+            diesel::update(users::table.find(user_id))
+                .set(
+                    users::chatrooms_joined
+                        .eq(array_append(users::chatrooms_joined, Some(chatroom_uid))),
+                )
+                .execute(pg_connection)
+        })
+        .context("An error occured while adding the chatroom to the user's joined chatrooms")?;
+
+    Ok(())
+}
+
+/// Removes the user from the participants and from the admins of the chatroom.
+fn remove_participant(
+    pg_connection: &mut PgConnection,
+    chatroom_uid: i32,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    info_span!("db", query = "remove_chatroom_participant")
+        .in_scope(|| {
+            diesel::update(chatrooms::table.find(chatroom_uid))
+                .set((
+                    chatrooms::participants
+                        .eq(array_remove(chatrooms::participants, Some(user_id))),
+                    chatrooms::admin_user_ids
+                        .eq(array_remove(chatrooms::admin_user_ids, Some(user_id))),
+                ))
+                .execute(pg_connection)
+        })
+        .context("An error occured while removing the user from the chatroom's participants")?;
+
+    info_span!("db", query = "update_chatrooms_joined")
+        .in_scope(|| {
+            diesel::update(users::table.find(user_id))
+                .set(
+                    users::chatrooms_joined
+                        .eq(array_remove(users::chatrooms_joined, Some(chatroom_uid))),
+                )
+                .execute(pg_connection)
+        })
+        .context("An error occured while removing the chatroom from the user's joined chatrooms")?;
+
+    Ok(())
+}
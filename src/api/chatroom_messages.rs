@@ -0,0 +1,126 @@
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{ChatMessage, UserSession};
+
+use crate::{
+    ServerState,
+    api::{
+        chatroom_history::{TimelineEntry, timeline_entry_of},
+        contacts::is_blocked_between,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        user_account_control::{find_chatroom, verify_user_session},
+    },
+    models::{MessageEntry, NewMessage},
+    monitoring::MESSAGES_SENT_TOTAL,
+    schema::{chatrooms, messages},
+};
+
+const MESSAGE_MAX_CHARS: usize = 4000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SendMessageRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// At most 4000 characters, and not only whitespace.
+    pub message: String,
+}
+
+/// Sends a message to a chatroom the user participates in.
+/// The stored message is returned as it appears in the history, and becomes the last message of the chatroom.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/{chatroom_uid}/messages",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = SendMessageRequest,
+    responses(
+        (status = 200, body = TimelineEntry),
+        (status = 400, description = "The message is empty or too long.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not a participant of the chatroom, or has blocked (or was blocked by) the other participant of a direct message.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn send_chatroom_message(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(request): Json<SendMessageRequest>,
+) -> Result<Json<TimelineEntry>, ApiError> {
+    if request.message.trim().is_empty() || request.message.chars().count() > MESSAGE_MAX_CHARS {
+        return Err(ApiError::InvalidInput {
+            message: "A message has to be between 1 and 4000 characters long.",
+        });
+    }
+
+    let timeline_entry = state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+            let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+            if !chatroom_entry.participants.contains(&Some(user_id)) {
+                return Err(ApiError::NotChatroomMember);
+            }
+
+            // A block between the two users of a direct message closes it
+            if chatroom_entry.is_direct_message {
+                for other_user_id in chatroom_entry.participants.iter().flatten() {
+                    if *other_user_id != user_id
+                        && is_blocked_between(pg_connection, user_id, *other_user_id)?
+                    {
+                        return Err(ApiError::UserBlocked);
+                    }
+                }
+            }
+
+            // The messages are stored serialized with rmp_serde
+            let raw_message = rmp_serde::to_vec(&ChatMessage {
+                owner_user_id: user_id,
+                chatroom_uid,
+                message: request.message,
+            })
+            .context("An error occured while serializing a message")?;
+
+            let message_entry = pg_connection.transaction(|pg_connection| {
+                let message_entry = info_span!("db", query = "insert_message")
+                    .in_scope(|| {
+                        diesel::insert_into(messages::table)
+                            .values(&NewMessage {
+                                parent_chatroom_id: chatroom_uid,
+                                owner_user_id: user_id,
+                                raw_message,
+                            })
+                            .returning(MessageEntry::as_returning())
+                            .get_result(pg_connection)
+                    })
+                    .context("An error occured while storing a message")?;
+
+                info_span!("db", query = "update_last_message")
+                    .in_scope(|| {
+                        diesel::update(chatrooms::table.find(chatroom_uid))
+                            .set(chatrooms::last_message_id.eq(message_entry.id))
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while updating the chatroom's last message")?;
+
+                Ok::<_, ApiError>(message_entry)
+            })?;
+
+            timeline_entry_of(message_entry)
+        })
+        .await?;
+
+    counter!(MESSAGES_SENT_TOTAL).increment(1);
+
+    Ok(Json(timeline_entry))
+}
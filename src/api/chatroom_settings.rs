@@ -0,0 +1,281 @@
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use diesel::{
+    Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+    dsl::{array_append, array_remove},
+};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{FetchChatroomResponse, UserSession};
+
+use crate::{
+    ServerState,
+    api::{
+        account_settings::find_user_account,
+        chatroom_directory::{DESCRIPTION_MAX_CHARS, find_owned_chatroom},
+        contacts::UserTargetRequest,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        profiles::bounded_text,
+        system_messages::{SystemEvent, emit_system_event},
+        user_account_control::{find_chatroom, verify_user_session},
+    },
+    models::{ChatroomEntry, ChatroomSettingsChangeset},
+    schema::chatrooms,
+};
+
+const CHATROOM_NAME_MAX_CHARS: usize = 64;
+
+/// The settings to change, the ones left out stay as they are.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateChatroomSettingsRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    #[serde(default)]
+    pub chatroom_name: Option<String>,
+    /// Protects the chatroom with a new password, public chatrooms can not have one.
+    #[serde(default)]
+    pub chatroom_password: Option<String>,
+    /// Lets anyone knowing the chatroom's id join it, this requires a verified email address.
+    #[serde(default)]
+    pub remove_password: bool,
+    /// An empty description removes it.
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Changes the name, the password or the description of a chatroom, only its admins can do this.
+/// Every change is recorded in the chatroom as a system message.
+#[utoipa::path(
+    put,
+    path = "/api/v1/chatrooms/{chatroom_uid}/settings",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = UpdateChatroomSettingsRequest,
+    responses(
+        (status = 200, body = schemas::FetchChatroomResponse),
+        (status = 400, description = "A field is out of bounds, or a password is set on a public chatroom.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not an admin of the chatroom, or removes its password without a verified email address.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn update_chatroom_settings(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(request): Json<UpdateChatroomSettingsRequest>,
+) -> Result<Json<FetchChatroomResponse>, ApiError> {
+    let chatroom_name = request
+        .chatroom_name
+        .map(|chatroom_name| chatroom_name.trim().to_string());
+
+    if chatroom_name.as_ref().is_some_and(|chatroom_name| {
+        chatroom_name.is_empty() || chatroom_name.chars().count() > CHATROOM_NAME_MAX_CHARS
+    }) {
+        return Err(ApiError::InvalidInput {
+            message: "The name of a chatroom has to be between 1 and 64 characters long.",
+        });
+    }
+
+    let description = request
+        .description
+        .map(|description| {
+            bounded_text(
+                Some(description),
+                DESCRIPTION_MAX_CHARS,
+                "The description can be at most 300 characters long.",
+            )
+        })
+        .transpose()?;
+
+    if request.remove_password && request.chatroom_password.is_some() {
+        return Err(ApiError::InvalidInput {
+            message: "A password can not be set and removed at the same time.",
+        });
+    }
+
+    if request
+        .chatroom_password
+        .as_ref()
+        .is_some_and(|password| password.is_empty())
+    {
+        return Err(ApiError::InvalidInput {
+            message: "The password of a chatroom can not be empty.",
+        });
+    }
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                let chatroom_entry =
+                    find_administered_chatroom(pg_connection, chatroom_uid, user_id)?;
+
+                let mut changeset = ChatroomSettingsChangeset::default();
+                let mut events = Vec::new();
+
+                if let Some(chatroom_name) = chatroom_name
+                    && chatroom_name != chatroom_entry.chatroom_name
+                {
+                    events.push(SystemEvent::ChatroomRenamed {
+                        previous_name: chatroom_entry.chatroom_name.clone(),
+                        chatroom_name: chatroom_name.clone(),
+                    });
+                    changeset.chatroom_name = Some(chatroom_name);
+                }
+
+                if let Some(chatroom_password) = request.chatroom_password {
+                    if chatroom_entry.is_public {
+                        return Err(ApiError::InvalidInput {
+                            message: "Public chatrooms can not have a password, remove the chatroom from the directory first.",
+                        });
+                    }
+
+                    events.push(SystemEvent::PasswordSet);
+                    changeset.chatroom_password = Some(Some(chatroom_password));
+                }
+
+                if request.remove_password && chatroom_entry.chatroom_password.is_some() {
+                    // Like creating a chatroom without a password, opening one up is not for throwaway accounts
+                    let user_account = find_user_account(pg_connection, user_id)?;
+
+                    if user_account.email_verified_at.is_none() {
+                        return Err(ApiError::EmailNotVerified);
+                    }
+
+                    events.push(SystemEvent::PasswordRemoved);
+                    changeset.chatroom_password = Some(None);
+                }
+
+                if let Some(description) = description
+                    && description != chatroom_entry.description
+                {
+                    events.push(SystemEvent::DescriptionChanged {
+                        description: description.clone(),
+                    });
+                    changeset.description = Some(description);
+                }
+
+                // Diesel refuses to run an update without any column to set
+                if !events.is_empty() {
+                    info_span!("db", query = "update_chatroom_settings")
+                        .in_scope(|| {
+                            diesel::update(chatrooms::table.find(chatroom_uid))
+                                .set(&changeset)
+                                .execute(pg_connection)
+                        })
+                        .context("An error occured while updating the chatroom's settings")?;
+                }
+
+                for event in events {
+                    emit_system_event(pg_connection, chatroom_uid, user_id, event)?;
+                }
+
+                let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+                Ok(Json(FetchChatroomResponse {
+                    chatroom_uid: chatroom_entry.id,
+                    chatroom_id: chatroom_entry.chatroom_id,
+                    chatroom_name: chatroom_entry.chatroom_name,
+                    participants: chatroom_entry.participants,
+                    is_direct_message: chatroom_entry.is_direct_message,
+                    last_message_id: chatroom_entry.last_message_id,
+                }))
+            })
+        })
+        .await
+}
+
+/// Hands the chatroom over to another participant, only its owner can do this.
+/// The previous owner stays an admin of the chatroom.
+#[utoipa::path(
+    put,
+    path = "/api/v1/chatrooms/{chatroom_uid}/owner",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = UserTargetRequest,
+    responses(
+        (status = 204, description = "The ownership has been transferred."),
+        (status = 400, description = "The new owner is not a participant of the chatroom, or is already its owner.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user does not own the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn transfer_ownership(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(request): Json<UserTargetRequest>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+            let new_owner_id = request.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                let chatroom_entry = find_owned_chatroom(pg_connection, chatroom_uid, user_id)?;
+
+                if new_owner_id == user_id
+                    || !chatroom_entry.participants.contains(&Some(new_owner_id))
+                {
+                    return Err(ApiError::InvalidInput {
+                        message: "The new owner has to be another participant of the chatroom.",
+                    });
+                }
+
+                info_span!("db", query = "update_chatroom_owner")
+                    .in_scope(|| {
+                        diesel::update(chatrooms::table.find(chatroom_uid))
+                            .set((
+                                chatrooms::owner_user_id.eq(new_owner_id),
+                                chatrooms::admin_user_ids.eq(array_append(
+                                    array_remove(chatrooms::admin_user_ids, Some(new_owner_id)),
+                                    Some(user_id),
+                                )),
+                            ))
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while transferring the chatroom's ownership")?;
+
+                emit_system_event(
+                    pg_connection,
+                    chatroom_uid,
+                    user_id,
+                    SystemEvent::OwnershipTransferred {
+                        previous_owner_user_id: user_id,
+                        owner_user_id: new_owner_id,
+                    },
+                )?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Fetches a chatroom, which the user has to own or be an admin of.
+pub fn find_administered_chatroom(
+    pg_connection: &mut PgConnection,
+    chatroom_uid: i32,
+    user_id: i32,
+) -> Result<ChatroomEntry, ApiError> {
+    let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+    if chatroom_entry.owner_user_id != Some(user_id)
+        && !chatroom_entry.admin_user_ids.contains(&Some(user_id))
+    {
+        return Err(ApiError::NotChatroomAdmin);
+    }
+
+    Ok(chatroom_entry)
+}
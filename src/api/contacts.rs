@@ -0,0 +1,715 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    PgArrayExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper, dsl,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{FetchChatroomResponse, UserSession};
+
+use crate::{
+    ServerState,
+    api::{
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        user_account_control::{generate_chatroom_id, verify_user_session},
+    },
+    models::{ChatroomEntry, ContactEntry, NewChatroom},
+    schema::{chatrooms, contacts, user_blocks, users},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactStatus {
+    /// The request is waiting for the other user to accept it.
+    Pending,
+    Accepted,
+}
+
+/// Names another user, used to send them a contact request or to open a direct message with them.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserTargetRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    pub user_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ContactRequestResponse {
+    pub status: ContactStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Contact {
+    pub user_id: i32,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ContactListResponse {
+    pub contacts: Vec<Contact>,
+    /// Requests other users have sent to the user.
+    pub incoming_requests: Vec<Contact>,
+    /// Requests the user has sent, which have not been accepted yet.
+    pub outgoing_requests: Vec<Contact>,
+    pub blocked_users: Vec<Contact>,
+}
+
+/// Lists the contacts of the user, their pending contact requests and the users they have blocked.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/contacts",
+    tag = "contacts",
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, body = ContactListResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn list_contacts(
+    State(state): State<ServerState>,
+    Json(user_session): Json<UserSession>,
+) -> Result<Json<ContactListResponse>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let user_id = user_session.user_id;
+
+            let contact_entries = info_span!("db", query = "find_user_contacts")
+                .in_scope(|| {
+                    contacts::table
+                        .filter(
+                            contacts::requester_id
+                                .eq(user_id)
+                                .or(contacts::addressee_id.eq(user_id)),
+                        )
+                        .select(ContactEntry::as_select())
+                        .load(pg_connection)
+                })
+                .context("An error occured while fetching the user's contacts")?;
+
+            let blocked_user_ids = blocked_user_ids(pg_connection, user_id)?;
+
+            let related_user_ids: Vec<i32> = contact_entries
+                .iter()
+                .flat_map(|entry| [entry.requester_id, entry.addressee_id])
+                .chain(blocked_user_ids.iter().copied())
+                .filter(|related_user_id| *related_user_id != user_id)
+                .collect();
+
+            let usernames: HashMap<i32, String> = info_span!("db", query = "find_usernames")
+                .in_scope(|| {
+                    users::table
+                        .filter(users::id.eq_any(&related_user_ids))
+                        .select((users::id, users::username))
+                        .load::<(i32, String)>(pg_connection)
+                })
+                .context("An error occured while fetching the usernames")?
+                .into_iter()
+                .collect();
+
+            let contact_of = |other_user_id: i32| Contact {
+                user_id: other_user_id,
+                // Users deleted since the contacts have been loaded get an empty name
+                username: usernames.get(&other_user_id).cloned().unwrap_or_default(),
+            };
+
+            let mut response = ContactListResponse {
+                contacts: Vec::new(),
+                incoming_requests: Vec::new(),
+                outgoing_requests: Vec::new(),
+                blocked_users: blocked_user_ids.into_iter().map(contact_of).collect(),
+            };
+
+            for entry in contact_entries {
+                if entry.accepted_at.is_some() {
+                    let other_user_id = if entry.requester_id == user_id {
+                        entry.addressee_id
+                    } else {
+                        entry.requester_id
+                    };
+
+                    response.contacts.push(contact_of(other_user_id));
+                } else if entry.requester_id == user_id {
+                    response
+                        .outgoing_requests
+                        .push(contact_of(entry.addressee_id));
+                } else {
+                    response
+                        .incoming_requests
+                        .push(contact_of(entry.requester_id));
+                }
+            }
+
+            for list in [
+                &mut response.contacts,
+                &mut response.incoming_requests,
+                &mut response.outgoing_requests,
+                &mut response.blocked_users,
+            ] {
+                list.sort_by(|a, b| a.username.cmp(&b.username));
+            }
+
+            Ok(Json(response))
+        })
+        .await
+}
+
+/// Sends a contact request to another user.
+/// If the other user has already sent one to the user, it is accepted instead.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/contacts/requests",
+    tag = "contacts",
+    request_body = UserTargetRequest,
+    responses(
+        (status = 200, description = "The status of the contact after the request.", body = ContactRequestResponse),
+        (status = 400, description = "The user has tried to add themselves.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "One of the users has blocked the other.", body = ApiErrorResponse),
+        (status = 404, description = "The other user does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn send_contact_request(
+    State(state): State<ServerState>,
+    Json(request): Json<UserTargetRequest>,
+) -> Result<Json<ContactRequestResponse>, ApiError> {
+    let user_id = request.user_session.user_id;
+    let other_user_id = request.user_id;
+
+    if user_id == other_user_id {
+        return Err(ApiError::InvalidInput {
+            message: "You can not add yourself as a contact.",
+        });
+    }
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            pg_connection.transaction(|pg_connection| {
+                ensure_user_exists(pg_connection, other_user_id)?;
+
+                if is_blocked_between(pg_connection, user_id, other_user_id)? {
+                    return Err(ApiError::UserBlocked);
+                }
+
+                let existing_entry = find_contact(pg_connection, user_id, other_user_id)?;
+
+                let status = match existing_entry {
+                    Some(entry) if entry.accepted_at.is_some() => ContactStatus::Accepted,
+                    Some(entry) if entry.requester_id == user_id => ContactStatus::Pending,
+                    // Both users want to be contacts, the request of the other one is accepted
+                    Some(_) => {
+                        accept_request(pg_connection, other_user_id, user_id)?;
+
+                        ContactStatus::Accepted
+                    }
+                    None => {
+                        info_span!("db", query = "insert_contact_request")
+                            .in_scope(|| {
+                                diesel::insert_into(contacts::table)
+                                    .values((
+                                        contacts::requester_id.eq(user_id),
+                                        contacts::addressee_id.eq(other_user_id),
+                                    ))
+                                    // A request racing the other user's one is ignored by the unique index on the pair
+                                    .on_conflict_do_nothing()
+                                    .execute(pg_connection)
+                            })
+                            .context("An error occured while storing the contact request")?;
+
+                        ContactStatus::Pending
+                    }
+                };
+
+                Ok(Json(ContactRequestResponse { status }))
+            })
+        })
+        .await
+}
+
+/// Accepts the contact request another user has sent to the user.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/contacts/requests/{user_id}/accept",
+    tag = "contacts",
+    params(("user_id" = i32, Path, description = "The id of the user who has sent the request.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The users are now contacts of each other."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 404, description = "The other user has not sent a request to the user.", body = ApiErrorResponse),
+    )
+)]
+pub async fn accept_contact_request(
+    State(state): State<ServerState>,
+    Path(other_user_id): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            accept_request(pg_connection, other_user_id, user_session.user_id)?;
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Declines a contact request sent to the user, or withdraws one the user has sent.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/contacts/requests/{user_id}",
+    tag = "contacts",
+    params(("user_id" = i32, Path, description = "The id of the other user of the request.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The request has been removed."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 404, description = "There is no pending request between the users.", body = ApiErrorResponse),
+    )
+)]
+pub async fn decline_contact_request(
+    State(state): State<ServerState>,
+    Path(other_user_id): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let r_affected = info_span!("db", query = "delete_contact_request")
+                .in_scope(|| {
+                    diesel::delete(
+                        contacts::table
+                            .filter(between(user_session.user_id, other_user_id))
+                            .filter(contacts::accepted_at.is_null()),
+                    )
+                    .execute(pg_connection)
+                })
+                .context("An error occured while deleting the contact request")?;
+
+            if r_affected == 0 {
+                return Err(ApiError::ContactNotFound);
+            }
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Removes another user from the contacts of the user, and the user from theirs.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/contacts/{user_id}",
+    tag = "contacts",
+    params(("user_id" = i32, Path, description = "The id of the contact.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The users are no longer contacts."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 404, description = "The users are not contacts.", body = ApiErrorResponse),
+    )
+)]
+pub async fn remove_contact(
+    State(state): State<ServerState>,
+    Path(other_user_id): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let r_affected = info_span!("db", query = "delete_contact")
+                .in_scope(|| {
+                    diesel::delete(
+                        contacts::table
+                            .filter(between(user_session.user_id, other_user_id))
+                            .filter(contacts::accepted_at.is_not_null()),
+                    )
+                    .execute(pg_connection)
+                })
+                .context("An error occured while deleting the contact")?;
+
+            if r_affected == 0 {
+                return Err(ApiError::ContactNotFound);
+            }
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Blocks another user: they can no longer send the user contact requests or direct messages, and their messages are hidden from the user.
+/// The contact between the users, or a pending request, is removed.
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/blocks/{user_id}",
+    tag = "contacts",
+    params(("user_id" = i32, Path, description = "The id of the user to block.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The user is blocked."),
+        (status = 400, description = "The user has tried to block themselves.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 404, description = "The other user does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn block_user(
+    State(state): State<ServerState>,
+    Path(other_user_id): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    let user_id = user_session.user_id;
+
+    if user_id == other_user_id {
+        return Err(ApiError::InvalidInput {
+            message: "You can not block yourself.",
+        });
+    }
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            pg_connection.transaction(|pg_connection| {
+                ensure_user_exists(pg_connection, other_user_id)?;
+
+                info_span!("db", query = "insert_user_block")
+                    .in_scope(|| {
+                        diesel::insert_into(user_blocks::table)
+                            .values((
+                                user_blocks::blocker_id.eq(user_id),
+                                user_blocks::blocked_id.eq(other_user_id),
+                            ))
+                            .on_conflict_do_nothing()
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while blocking the user")?;
+
+                info_span!("db", query = "delete_contact")
+                    .in_scope(|| {
+                        diesel::delete(contacts::table.filter(between(user_id, other_user_id)))
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while deleting the contact")?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Unblocks a user. Their contact with the user is not restored.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/blocks/{user_id}",
+    tag = "contacts",
+    params(("user_id" = i32, Path, description = "The id of the user to unblock.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The user is not blocked (anymore)."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn unblock_user(
+    State(state): State<ServerState>,
+    Path(other_user_id): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            info_span!("db", query = "delete_user_block")
+                .in_scope(|| {
+                    diesel::delete(
+                        user_blocks::table
+                            .filter(user_blocks::blocker_id.eq(user_session.user_id))
+                            .filter(user_blocks::blocked_id.eq(other_user_id)),
+                    )
+                    .execute(pg_connection)
+                })
+                .context("An error occured while unblocking the user")?;
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Opens the direct message chatroom of the user and another user, it is created if they have none yet.
+/// Neither of the users may have blocked the other, and depending on the server's configuration they have to be contacts.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/direct",
+    tag = "chatrooms",
+    request_body = UserTargetRequest,
+    responses(
+        (status = 200, body = schemas::FetchChatroomResponse),
+        (status = 400, description = "The user has tried to message themselves.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "One of the users has blocked the other, or the server only allows direct messages between contacts.", body = ApiErrorResponse),
+        (status = 404, description = "The other user does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn open_direct_message(
+    State(state): State<ServerState>,
+    Json(request): Json<UserTargetRequest>,
+) -> Result<Json<FetchChatroomResponse>, ApiError> {
+    let user_id = request.user_session.user_id;
+    let other_user_id = request.user_id;
+    let require_contact = state.config.direct_messages_require_contact;
+    let generated_chatroom_id = generate_chatroom_id();
+
+    if user_id == other_user_id {
+        return Err(ApiError::InvalidInput {
+            message: "You can not send direct messages to yourself.",
+        });
+    }
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            pg_connection.transaction(|pg_connection| {
+                ensure_user_exists(pg_connection, other_user_id)?;
+
+                // Blocks apply to existing direct messages too, reopening one must fail just like creating it
+                if is_blocked_between(pg_connection, user_id, other_user_id)? {
+                    return Err(ApiError::UserBlocked);
+                }
+
+                if require_contact && !are_contacts(pg_connection, user_id, other_user_id)? {
+                    return Err(ApiError::ContactRequired);
+                }
+
+                // Two concurrent opens of the same pair would both find no direct message and create one each, locking both users serialises them
+                // The rows are locked in the order of their ids, so that the opens from either side can not deadlock
+                info_span!("db", query = "lock_direct_message_users")
+                    .in_scope(|| {
+                        users::table
+                            .filter(users::id.eq_any([user_id, other_user_id]))
+                            .order(users::id)
+                            .select(users::id)
+                            .for_update()
+                            .load::<i32>(pg_connection)
+                    })
+                    .context("An error occured while locking the users of the direct message")?;
+
+                let existing_chatroom = info_span!("db", query = "find_direct_message")
+                    .in_scope(|| {
+                        chatrooms::table
+                            .filter(chatrooms::is_direct_message.eq(true))
+                            .filter(
+                                chatrooms::participants
+                                    .contains(vec![Some(user_id), Some(other_user_id)]),
+                            )
+                            .select(ChatroomEntry::as_select())
+                            .first(pg_connection)
+                            .optional()
+                    })
+                    .context("An error occured while searching for the direct message")?;
+
+                let chatroom_entry = match existing_chatroom {
+                    Some(chatroom_entry) => chatroom_entry,
+                    None => create_direct_message(
+                        pg_connection,
+                        generated_chatroom_id,
+                        user_id,
+                        other_user_id,
+                    )?,
+                };
+
+                Ok(Json(FetchChatroomResponse {
+                    chatroom_uid: chatroom_entry.id,
+                    chatroom_id: chatroom_entry.chatroom_id,
+                    chatroom_name: chatroom_entry.chatroom_name,
+                    participants: chatroom_entry.participants,
+                    is_direct_message: chatroom_entry.is_direct_message,
+                    last_message_id: chatroom_entry.last_message_id,
+                }))
+            })
+        })
+        .await
+}
+
+/// Whether either of the users has blocked the other.
+pub fn is_blocked_between(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+    other_user_id: i32,
+) -> Result<bool, ApiError> {
+    Ok(info_span!("db", query = "find_user_block")
+        .in_scope(|| {
+            dsl::select(dsl::exists(
+                user_blocks::table.filter(
+                    user_blocks::blocker_id
+                        .eq(user_id)
+                        .and(user_blocks::blocked_id.eq(other_user_id))
+                        .or(user_blocks::blocker_id
+                            .eq(other_user_id)
+                            .and(user_blocks::blocked_id.eq(user_id))),
+                ),
+            ))
+            .get_result(pg_connection)
+        })
+        .context("An error occured while checking whether the users have blocked each other")?)
+}
+
+/// The ids of the users `user_id` has blocked, whose messages must be hidden from them.
+pub fn blocked_user_ids(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+) -> Result<HashSet<i32>, ApiError> {
+    Ok(info_span!("db", query = "find_blocked_users")
+        .in_scope(|| {
+            user_blocks::table
+                .filter(user_blocks::blocker_id.eq(user_id))
+                .select(user_blocks::blocked_id)
+                .load::<i32>(pg_connection)
+        })
+        .context("An error occured while fetching the users blocked by the user")?
+        .into_iter()
+        .collect())
+}
+
+/// Whether the users have accepted each other as contacts.
+pub fn are_contacts(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+    other_user_id: i32,
+) -> Result<bool, ApiError> {
+    Ok(find_contact(pg_connection, user_id, other_user_id)?
+        .is_some_and(|entry| entry.accepted_at.is_some()))
+}
+
+/// The contact (or the pending request) between the users, whoever has sent the request.
+fn find_contact(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+    other_user_id: i32,
+) -> Result<Option<ContactEntry>, ApiError> {
+    Ok(info_span!("db", query = "find_contact")
+        .in_scope(|| {
+            contacts::table
+                .filter(between(user_id, other_user_id))
+                .select(ContactEntry::as_select())
+                .first(pg_connection)
+                .optional()
+        })
+        .context("An error occured while fetching the contact")?)
+}
+
+/// Matches the contact between the users in either direction.
+#[dsl::auto_type]
+fn between(user_id: i32, other_user_id: i32) -> _ {
+    contacts::requester_id
+        .eq(user_id)
+        .and(contacts::addressee_id.eq(other_user_id))
+        .or(contacts::requester_id
+            .eq(other_user_id)
+            .and(contacts::addressee_id.eq(user_id)))
+}
+
+fn accept_request(
+    pg_connection: &mut PgConnection,
+    requester_id: i32,
+    addressee_id: i32,
+) -> Result<(), ApiError> {
+    let r_affected = info_span!("db", query = "accept_contact_request")
+        .in_scope(|| {
+            diesel::update(
+                contacts::table
+                    .find((requester_id, addressee_id))
+                    .filter(contacts::accepted_at.is_null()),
+            )
+            .set(contacts::accepted_at.eq(Utc::now().naive_utc()))
+            .execute(pg_connection)
+        })
+        .context("An error occured while accepting the contact request")?;
+
+    if r_affected == 0 {
+        return Err(ApiError::ContactNotFound);
+    }
+
+    Ok(())
+}
+
+fn ensure_user_exists(pg_connection: &mut PgConnection, user_id: i32) -> Result<(), ApiError> {
+    let exists: bool = info_span!("db", query = "find_user")
+        .in_scope(|| dsl::select(dsl::exists(users::table.find(user_id))).get_result(pg_connection))
+        .context("An error occured while searching for the user")?;
+
+    if !exists {
+        return Err(ApiError::UserNotFound);
+    }
+
+    Ok(())
+}
+
+fn create_direct_message(
+    pg_connection: &mut PgConnection,
+    generated_chatroom_id: String,
+    user_id: i32,
+    other_user_id: i32,
+) -> Result<ChatroomEntry, ApiError> {
+    let mut usernames = info_span!("db", query = "find_usernames")
+        .in_scope(|| {
+            users::table
+                .filter(users::id.eq_any([user_id, other_user_id]))
+                .select(users::username)
+                .load::<String>(pg_connection)
+        })
+        .context("An error occured while fetching the usernames")?;
+
+    usernames.sort();
+
+    let chatroom_entry: ChatroomEntry = info_span!("db", query = "insert_chatroom")
+        .in_scope(|| {
+            diesel::insert_into(chatrooms::table)
+                .values(&NewChatroom {
+                    chatroom_id: generated_chatroom_id,
+                    chatroom_name: usernames.join(", "),
+                    // The lookup by id skips direct messages, so they need no password
+                    chatroom_password: None,
+                    participants: vec![user_id, other_user_id],
+                    is_direct_message: true,
+                    last_message_id: None,
+                    owner_user_id: None,
+                })
+                .get_result(pg_connection)
+        })
+        .map_err(|err| {
+            ApiError::from_query_error(err, "An error occured while creating a direct message")
+        })?;
+
+    info_span!("db", query = "update_chatrooms_joined")
+        .in_scope(|| {
+            diesel::update(users::table.filter(users::id.eq_any([user_id, other_user_id])))
+                .set(
+                    users::chatrooms_joined
+                        .eq(dsl::array_append(users::chatrooms_joined, Some(chatroom_entry.id))),
+                )
+                .execute(pg_connection)
+        })
+        .with_context(|| {
+            format!(
+                "An error occured while adding chatroom {} to the joined chatrooms of the participants",
+                chatroom_entry.id
+            )
+        })?;
+
+    Ok(chatroom_entry)
+}
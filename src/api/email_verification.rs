@@ -0,0 +1,173 @@
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use chrono::{TimeDelta, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl, SelectableHelper};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::{IntoParams, ToSchema};
+use whatssock_lib::UserSession;
+
+use crate::{
+    ServerState,
+    api::{
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        user_account_control::verify_user_session,
+    },
+    mailer::Email,
+    models::UserAccountEntry,
+    schema::users,
+    signed_links::SignedToken,
+};
+
+/// Keeps the verification tokens apart from the tokens of other links.
+const EMAIL_VERIFICATION_PURPOSE: &str = "email_verification";
+
+/// How long the link of a verification email stays valid.
+const VERIFICATION_LINK_LIFETIME: TimeDelta = TimeDelta::hours(48);
+
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+pub struct ConfirmEmailQuery {
+    /// The token of the link sent in the verification email.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EmailVerifiedResponse {
+    pub email: String,
+}
+
+/// Checks that `email` is a valid address before it is stored and mailed to.
+pub fn check_email_address(email: &str) -> Result<(), ApiError> {
+    email
+        .parse::<lettre::Address>()
+        .map(|_| ())
+        .map_err(|_| ApiError::InvalidInput {
+            message: "The email address is invalid.",
+        })
+}
+
+/// Emails the user a link confirming that `email` belongs to them.
+/// The link is bound to the address, so it stops working if the user changes their email address in the meantime.
+pub async fn send_verification_email(
+    state: &ServerState,
+    user_id: i32,
+    email: String,
+) -> anyhow::Result<()> {
+    let token = state.link_signer.sign(
+        EMAIL_VERIFICATION_PURPOSE,
+        user_id,
+        &email,
+        Utc::now() + VERIFICATION_LINK_LIFETIME,
+    );
+
+    state
+        .send_email(Email {
+            to: email,
+            subject: String::from("Confirm your email address"),
+            body: format!(
+                "Welcome to Whatssock!\n\nOpen the link below to confirm your email address:\n{}/api/v1/users/verification?token={token}\n\nThe link expires in {} hours. If you have not created an account, ignore this email.",
+                state.config.public_url,
+                VERIFICATION_LINK_LIFETIME.num_hours(),
+            ),
+        })
+        .await
+}
+
+/// Sends a new verification email to the address of the user.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/verification",
+    tag = "accounts",
+    request_body = schemas::UserSession,
+    responses(
+        (status = 202, description = "The verification email has been sent."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 409, description = "The email address has already been verified.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn request_email_verification(
+    State(state): State<ServerState>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    let user_account = state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            Ok(info_span!("db", query = "find_user_account")
+                .in_scope(|| {
+                    users::table
+                        .find(user_session.user_id)
+                        .select(UserAccountEntry::as_select())
+                        .first(pg_connection)
+                })
+                .context("An error occured while fetching the account of the session's user")?)
+        })
+        .await?;
+
+    if user_account.email_verified_at.is_some() {
+        return Err(ApiError::EmailAlreadyVerified);
+    }
+
+    send_verification_email(&state, user_account.id, user_account.email).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Confirms the email address of the user, this is where the link of the verification email points.
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/verification",
+    tag = "accounts",
+    params(ConfirmEmailQuery),
+    responses(
+        (status = 200, body = EmailVerifiedResponse),
+        (status = 400, description = "The link is invalid or has expired.", body = ApiErrorResponse),
+    )
+)]
+pub async fn confirm_email(
+    State(state): State<ServerState>,
+    Query(query): Query<ConfirmEmailQuery>,
+) -> Result<Json<EmailVerifiedResponse>, ApiError> {
+    let token = SignedToken::parse(&query.token).ok_or(ApiError::InvalidToken)?;
+    let link_signer = state.link_signer.clone();
+
+    state
+        .run_query(move |pg_connection| {
+            let user_account = info_span!("db", query = "find_user_account")
+                .in_scope(|| {
+                    users::table
+                        .find(token.user_id)
+                        .select(UserAccountEntry::as_select())
+                        .first(pg_connection)
+                        .optional()
+                })
+                .context("An error occured while fetching the account of the token's user")?
+                .ok_or(ApiError::InvalidToken)?;
+
+            if !link_signer.verify(&token, EMAIL_VERIFICATION_PURPOSE, &user_account.email) {
+                return Err(ApiError::InvalidToken);
+            }
+
+            // Opening the link again keeps the time of the first verification
+            if user_account.email_verified_at.is_none() {
+                info_span!("db", query = "set_email_verified")
+                    .in_scope(|| {
+                        diesel::update(users::table.find(user_account.id))
+                            .set(users::email_verified_at.eq(Utc::now().naive_utc()))
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while marking the user's email as verified")?;
+            }
+
+            Ok(Json(EmailVerifiedResponse {
+                email: user_account.email,
+            }))
+        })
+        .await
+}
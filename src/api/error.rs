@@ -0,0 +1,261 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use diesel::result::DatabaseErrorKind;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::logging::current_request_id;
+
+/// Stable, machine-readable identifiers of every error the API can return.
+/// Clients branch on these, so a code must never be renamed or reused for a different meaning once it has been released.
+/// Until `ErrorCode` and [`ApiErrorResponse`] move into whatssock-lib, clients have to mirror them from the `ErrorCode` schema of `/openapi.json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidCredentials,
+    InvalidSession,
+    UsernameTaken,
+    EmailTaken,
+    ChatroomIdTaken,
+    ChatroomNotFound,
+    NotChatroomMember,
+    NotChatroomOwner,
+    NotChatroomAdmin,
+    RateLimited,
+    EmailNotVerified,
+    EmailAlreadyVerified,
+    InvalidToken,
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotEnabled,
+    InvalidTwoFactorCode,
+    TwoFactorRequired,
+    UserNotFound,
+    InvalidInput,
+    ContactNotFound,
+    ContactRequired,
+    UserBlocked,
+    MessageNotFound,
+    PinLimitReached,
+    PayloadTooLarge,
+    InternalError,
+}
+
+/// The JSON body of every error response.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiErrorResponse {
+    pub code: ErrorCode,
+    /// Human readable description of the error, this is not meant to be parsed.
+    pub message: String,
+    /// The id of the request which has failed, this is also sent in the `x-request-id` header.
+    pub request_id: Option<String>,
+}
+
+/// Every error a handler can return.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The username and password pair does not belong to any account.
+    InvalidCredentials,
+    /// The session token does not belong to the user, or it has been revoked.
+    InvalidSession,
+    UsernameTaken,
+    EmailTaken,
+    /// The randomly generated id of a new chatroom collided with an existing one, retrying the request resolves it.
+    ChatroomIdTaken,
+    /// The chatroom does not exist, or the password supplied for it is incorrect.
+    ChatroomNotFound,
+    /// The user has requested a chatroom they are not a participant of.
+    NotChatroomMember,
+    /// Only the owner of the chatroom may do this.
+    NotChatroomOwner,
+    /// Only the owner and the admins of the chatroom may do this.
+    NotChatroomAdmin,
+    /// The client has sent too many requests, or has failed to log in too many times.
+    RateLimited {
+        retry_after: Duration,
+    },
+    /// The action is only available to users who have confirmed their email address.
+    EmailNotVerified,
+    EmailAlreadyVerified,
+    /// The token of an emailed link or of a login challenge is malformed, has been tampered with, or has expired.
+    InvalidToken,
+    TwoFactorAlreadyEnabled,
+    /// The user has no authenticator, or has not confirmed its enrolment yet.
+    TwoFactorNotEnabled,
+    /// The code is neither the current code of the user's authenticator nor one of their unused recovery codes.
+    InvalidTwoFactorCode,
+    /// Admins can not turn off two-factor authentication while the server requires it of them.
+    TwoFactorRequired,
+    /// The user does not exist, or is not visible to the requester.
+    UserNotFound,
+    /// A field of the request is malformed or out of bounds, `message` says which one.
+    InvalidInput {
+        message: &'static str,
+    },
+    /// There is no contact, or no pending contact request, between the two users.
+    ContactNotFound,
+    /// The server only allows direct messages between contacts.
+    ContactRequired,
+    /// One of the two users has blocked the other.
+    UserBlocked,
+    /// The message does not exist in the chatroom, or is a system message.
+    MessageNotFound,
+    /// The chatroom already has as many pinned messages as the server allows.
+    PinLimitReached,
+    /// The body of the request is bigger than the endpoint accepts.
+    PayloadTooLarge,
+    /// Anything which is not the client's fault. The underlying error is logged, but never sent to the client.
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::InvalidCredentials => ErrorCode::InvalidCredentials,
+            Self::InvalidSession => ErrorCode::InvalidSession,
+            Self::UsernameTaken => ErrorCode::UsernameTaken,
+            Self::EmailTaken => ErrorCode::EmailTaken,
+            Self::ChatroomIdTaken => ErrorCode::ChatroomIdTaken,
+            Self::ChatroomNotFound => ErrorCode::ChatroomNotFound,
+            Self::NotChatroomMember => ErrorCode::NotChatroomMember,
+            Self::NotChatroomOwner => ErrorCode::NotChatroomOwner,
+            Self::NotChatroomAdmin => ErrorCode::NotChatroomAdmin,
+            Self::RateLimited { .. } => ErrorCode::RateLimited,
+            Self::EmailNotVerified => ErrorCode::EmailNotVerified,
+            Self::EmailAlreadyVerified => ErrorCode::EmailAlreadyVerified,
+            Self::InvalidToken => ErrorCode::InvalidToken,
+            Self::TwoFactorAlreadyEnabled => ErrorCode::TwoFactorAlreadyEnabled,
+            Self::TwoFactorNotEnabled => ErrorCode::TwoFactorNotEnabled,
+            Self::InvalidTwoFactorCode => ErrorCode::InvalidTwoFactorCode,
+            Self::TwoFactorRequired => ErrorCode::TwoFactorRequired,
+            Self::UserNotFound => ErrorCode::UserNotFound,
+            Self::InvalidInput { .. } => ErrorCode::InvalidInput,
+            Self::ContactNotFound => ErrorCode::ContactNotFound,
+            Self::ContactRequired => ErrorCode::ContactRequired,
+            Self::UserBlocked => ErrorCode::UserBlocked,
+            Self::MessageNotFound => ErrorCode::MessageNotFound,
+            Self::PinLimitReached => ErrorCode::PinLimitReached,
+            Self::PayloadTooLarge => ErrorCode::PayloadTooLarge,
+            Self::Internal(_) => ErrorCode::InternalError,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidCredentials | Self::InvalidSession | Self::InvalidTwoFactorCode => {
+                StatusCode::UNAUTHORIZED
+            }
+            Self::UsernameTaken | Self::EmailTaken | Self::ChatroomIdTaken => StatusCode::CONFLICT,
+            Self::ChatroomNotFound
+            | Self::UserNotFound
+            | Self::ContactNotFound
+            | Self::MessageNotFound => StatusCode::NOT_FOUND,
+            Self::NotChatroomMember
+            | Self::NotChatroomOwner
+            | Self::NotChatroomAdmin
+            | Self::EmailNotVerified
+            | Self::TwoFactorRequired
+            | Self::ContactRequired
+            | Self::UserBlocked => StatusCode::FORBIDDEN,
+            Self::EmailAlreadyVerified
+            | Self::TwoFactorAlreadyEnabled
+            | Self::TwoFactorNotEnabled
+            | Self::PinLimitReached => StatusCode::CONFLICT,
+            Self::InvalidToken | Self::InvalidInput { .. } => StatusCode::BAD_REQUEST,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::InvalidCredentials => "The username or the password is incorrect.",
+            Self::InvalidSession => "The session is invalid, please log in again.",
+            Self::UsernameTaken => "The username is already taken.",
+            Self::EmailTaken => "The email address is already in use.",
+            Self::ChatroomIdTaken => "Failed to allocate an id for the chatroom, please try again.",
+            Self::ChatroomNotFound => "The chatroom does not exist, or its password is incorrect.",
+            Self::NotChatroomMember => "You are not a participant of this chatroom.",
+            Self::NotChatroomOwner => "Only the owner of the chatroom can do this.",
+            Self::NotChatroomAdmin => "Only the admins of the chatroom can do this.",
+            Self::RateLimited { .. } => "Too many attempts, please try again later.",
+            Self::EmailNotVerified => "Please confirm your email address first.",
+            Self::EmailAlreadyVerified => "The email address has already been confirmed.",
+            Self::InvalidToken => "The token is invalid or has expired.",
+            Self::TwoFactorAlreadyEnabled => "Two-factor authentication is already enabled.",
+            Self::TwoFactorNotEnabled => "Two-factor authentication is not enabled.",
+            Self::InvalidTwoFactorCode => "The authentication code is incorrect.",
+            Self::TwoFactorRequired => "Admins can not turn off two-factor authentication.",
+            Self::UserNotFound => "The user does not exist.",
+            Self::InvalidInput { message } => message,
+            Self::ContactNotFound => "There is no such contact or contact request.",
+            Self::ContactRequired => "Direct messages can only be sent to your contacts.",
+            Self::UserBlocked => "You can not interact with this user.",
+            Self::MessageNotFound => "The message does not exist.",
+            Self::PinLimitReached => {
+                "The chatroom can not have more pinned messages, unpin one first."
+            }
+            Self::PayloadTooLarge => "The request is too large.",
+            Self::Internal(_) => "An internal error has occured, please try again later.",
+        }
+    }
+
+    /// Converts a failed query into an error.
+    /// Violations of the schema's unique constraints become the matching conflict, anything else is an internal error with `context` attached.
+    pub fn from_query_error(err: diesel::result::Error, context: &'static str) -> Self {
+        if let diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) = &err
+        {
+            match info.constraint_name() {
+                Some("users_username_key") => return Self::UsernameTaken,
+                Some("users_email_key") => return Self::EmailTaken,
+                Some("chatrooms_chatroom_id_key") => return Self::ChatroomIdTaken,
+                _ => (),
+            }
+        }
+
+        Self::Internal(anyhow::Error::new(err).context(context))
+    }
+}
+
+impl<E> From<E> for ApiError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        Self::Internal(err.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let Self::Internal(err) = &self {
+            error!("{err:#}");
+        }
+
+        let body = ApiErrorResponse {
+            code: self.code(),
+            message: self.message().to_string(),
+            request_id: current_request_id(),
+        };
+
+        let mut response = (self.status(), Json(body)).into_response();
+
+        if let Self::RateLimited { retry_after } = self {
+            // Rounded up, so that retrying right after the advertised delay succeeds
+            let retry_after_secs =
+                retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+        }
+
+        response
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use axum::{Json, extract::State, http::StatusCode};
+use diesel_migrations::MigrationHarness;
+use serde::Serialize;
+use tokio::task::spawn_blocking;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{PgPool, ServerState, migrations::MIGRATIONS};
+
+/// How long the readiness probe waits for a connection from the pool before reporting the database as unavailable.
+const READINESS_CHECKOUT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    /// Whether a connection could be checked out from the pool.
+    pub database_reachable: bool,
+    /// Whether every embedded migration has been applied to the database.
+    pub migrations_current: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VersionResponse {
+    pub crate_version: &'static str,
+    pub git_commit: &'static str,
+    /// The version of whatssock-lib, which defines the types of the API.
+    pub protocol_version: &'static str,
+}
+
+/// The process is up and able to answer requests.
+#[utoipa::path(get, path = "/health/live", tag = "operations", responses((status = 200)))]
+pub async fn liveness() -> StatusCode {
+    StatusCode::OK
+}
+
+/// The server is able to serve traffic: the database is reachable and its schema is up to date.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "operations",
+    responses(
+        (status = 200, body = ReadinessResponse),
+        (status = 503, description = "The server should not receive traffic.", body = ReadinessResponse),
+    )
+)]
+pub async fn readiness(State(state): State<ServerState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let pg_pool = state.pg_pool.clone();
+
+    let readiness = spawn_blocking(move || check_readiness(&pg_pool))
+        .await
+        .unwrap_or_else(|err| {
+            error!("Readiness check has panicked: {err}");
+
+            ReadinessResponse {
+                database_reachable: false,
+                migrations_current: false,
+            }
+        });
+
+    let status = if readiness.database_reachable && readiness.migrations_current {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
+
+fn check_readiness(pg_pool: &PgPool) -> ReadinessResponse {
+    let mut pg_connection = match pg_pool.get_timeout(READINESS_CHECKOUT_TIMEOUT) {
+        Ok(pg_connection) => pg_connection,
+        Err(err) => {
+            error!("Readiness check failed to check out a connection: {err}");
+
+            return ReadinessResponse {
+                database_reachable: false,
+                migrations_current: false,
+            };
+        }
+    };
+
+    let migrations_current = match pg_connection.has_pending_migration(MIGRATIONS) {
+        Ok(has_pending_migration) => !has_pending_migration,
+        Err(err) => {
+            error!("Readiness check failed to look up pending migrations: {err}");
+
+            false
+        }
+    };
+
+    ReadinessResponse {
+        database_reachable: true,
+        migrations_current,
+    }
+}
+
+/// The version of the server, and the protocol it speaks.
+#[utoipa::path(get, path = "/version", tag = "operations", responses((status = 200, body = VersionResponse)))]
+pub async fn version() -> Json<VersionResponse> {
+    Json(VersionResponse {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("WHATSSOCK_GIT_COMMIT"),
+        protocol_version: env!("WHATSSOCK_LIB_VERSION"),
+    })
+}
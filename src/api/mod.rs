@@ -1 +1,307 @@
+use std::convert::Infallible;
+
+use axum::{
+    Router,
+    http::{HeaderName, HeaderValue, header},
+    middleware,
+    routing::{MethodRouter, delete, get, post, put},
+};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    set_header::SetResponseHeaderLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    ServerState,
+    api::{
+        account_settings::{change_email, change_password, change_username},
+        chatroom_directory::{
+            delete_chatroom_icon, fetch_chatroom_directory, fetch_chatroom_icon, update_listing,
+            upload_chatroom_icon,
+        },
+        chatroom_history::fetch_chatroom_history,
+        chatroom_members::{
+            add_admin, join_chatroom, kick_participant, leave_chatroom, remove_admin,
+        },
+        chatroom_messages::send_chatroom_message,
+        chatroom_settings::{transfer_ownership, update_chatroom_settings},
+        contacts::{
+            accept_contact_request, block_user, decline_contact_request, list_contacts,
+            open_direct_message, remove_contact, send_contact_request, unblock_user,
+        },
+        email_verification::{confirm_email, request_email_verification},
+        health::{liveness, readiness, version},
+        monitoring::render_metrics,
+        openapi::ApiDoc,
+        password_reset::{confirm_password_reset, request_password_reset},
+        personal_data::{delete_account, export_personal_data},
+        pinned_messages::{fetch_pinned_messages, pin_message, unpin_message},
+        profiles::{delete_avatar, fetch_avatar, fetch_profiles, update_profile, upload_avatar},
+        two_factor::{
+            complete_login, confirm_two_factor, disable_two_factor, enroll_two_factor,
+            regenerate_recovery_codes,
+        },
+        user_account_control::{
+            create_chatroom, fetch_known_chatrooms, fetch_login, fetch_session_token,
+            fetch_unknown_chatroom, handle_logout_request, register_user,
+        },
+        user_search::{search_users, update_discoverability},
+        versions::api_versions,
+    },
+    logging::{make_request_span, scope_request_id},
+    monitoring::track_requests,
+    rate_limit::{limit_auth_requests, throttle_failed_logins},
+};
+
+pub mod account_settings;
+pub mod chatroom_directory;
+pub mod chatroom_history;
+pub mod chatroom_members;
+pub mod chatroom_messages;
+pub mod chatroom_settings;
+pub mod contacts;
+pub mod email_verification;
+pub mod error;
+pub mod health;
+pub mod monitoring;
+pub mod openapi;
+pub mod password_reset;
+pub mod personal_data;
+pub mod pinned_messages;
+pub mod profiles;
+pub mod system_messages;
+pub mod two_factor;
 pub mod user_account_control;
+pub mod user_search;
+pub mod versions;
+
+/// Creates the router serving every endpoint of the server, with all of its middlewares.
+pub fn router(state: ServerState) -> Router {
+    Router::new()
+        .nest("/api/v1", v1_router(&state))
+        .merge(legacy_router(&state))
+        .route("/api/versions", get(api_versions))
+        .route("/health/live", get(liveness))
+        .route("/health/ready", get(readiness))
+        .route("/version", get(version))
+        .route("/metrics", get(render_metrics))
+        // The specification is served at `/openapi.json`, and can be browsed at `/docs`
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()))
+        .layer(middleware::from_fn(track_requests))
+        .layer(middleware::from_fn(scope_request_id))
+        // Every request is handled inside a span carrying its request id, which is also returned to the client in the `x-request-id` header
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(make_request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state)
+}
+
+/// The current version of the API, nested under `/api/v1`.
+fn v1_router(state: &ServerState) -> Router<ServerState> {
+    Router::new()
+        .route(
+            "/users",
+            rate_limited(post(register_user), state).delete(delete_account),
+        )
+        .route("/users/export", post(export_personal_data))
+        .route("/users/profile", put(update_profile))
+        .route(
+            "/users/profile/avatar",
+            put(upload_avatar).delete(delete_avatar),
+        )
+        .route("/users/profiles", post(fetch_profiles))
+        .route("/users/{user_id}/avatar", post(fetch_avatar))
+        .route("/users/search", post(search_users))
+        .route("/users/discoverability", put(update_discoverability))
+        .route("/users/contacts", post(list_contacts))
+        .route("/users/contacts/requests", post(send_contact_request))
+        .route(
+            "/users/contacts/requests/{user_id}",
+            delete(decline_contact_request),
+        )
+        .route(
+            "/users/contacts/requests/{user_id}/accept",
+            post(accept_contact_request),
+        )
+        .route("/users/contacts/{user_id}", delete(remove_contact))
+        .route(
+            "/users/blocks/{user_id}",
+            put(block_user).delete(unblock_user),
+        )
+        .route(
+            "/users/verification",
+            rate_limited(post(request_email_verification), state).get(confirm_email),
+        )
+        .route("/users/password", rate_limited(put(change_password), state))
+        .route("/users/username", rate_limited(put(change_username), state))
+        .route("/users/email", rate_limited(put(change_email), state))
+        .route(
+            "/users/two_factor",
+            post(enroll_two_factor).delete(disable_two_factor),
+        )
+        .route("/users/two_factor/confirm", post(confirm_two_factor))
+        .route(
+            "/users/two_factor/recovery_codes",
+            post(regenerate_recovery_codes),
+        )
+        .route(
+            "/password_reset",
+            rate_limited(post(request_password_reset), state),
+        )
+        .route(
+            "/password_reset/confirm",
+            rate_limited(post(confirm_password_reset), state),
+        )
+        .route(
+            "/sessions",
+            rate_limited(login_throttled(post(fetch_login), state), state)
+                .delete(handle_logout_request),
+        )
+        .route(
+            "/sessions/two_factor",
+            rate_limited(post(complete_login), state),
+        )
+        .route("/sessions/current", post(fetch_session_token))
+        .route("/chatrooms", post(create_chatroom))
+        .route(
+            "/chatrooms/lookup",
+            rate_limited(post(fetch_unknown_chatroom), state),
+        )
+        .route("/chatrooms/known", post(fetch_known_chatrooms))
+        .route("/chatrooms/join", rate_limited(post(join_chatroom), state))
+        .route("/chatrooms/direct", post(open_direct_message))
+        .route("/chatrooms/directory", post(fetch_chatroom_directory))
+        .route("/chatrooms/{chatroom_uid}/listing", put(update_listing))
+        .route(
+            "/chatrooms/{chatroom_uid}/settings",
+            put(update_chatroom_settings),
+        )
+        .route("/chatrooms/{chatroom_uid}/owner", put(transfer_ownership))
+        .route("/chatrooms/{chatroom_uid}/leave", post(leave_chatroom))
+        .route(
+            "/chatrooms/{chatroom_uid}/participants/{user_id}",
+            delete(kick_participant),
+        )
+        .route(
+            "/chatrooms/{chatroom_uid}/admins/{user_id}",
+            put(add_admin).delete(remove_admin),
+        )
+        .route(
+            "/chatrooms/{chatroom_uid}/history",
+            post(fetch_chatroom_history),
+        )
+        .route(
+            "/chatrooms/{chatroom_uid}/pins",
+            post(fetch_pinned_messages),
+        )
+        .route(
+            "/chatrooms/{chatroom_uid}/pins/{message_id}",
+            put(pin_message).delete(unpin_message),
+        )
+        .route(
+            "/chatrooms/{chatroom_uid}/icon",
+            put(upload_chatroom_icon)
+                .delete(delete_chatroom_icon)
+                .post(fetch_chatroom_icon),
+        )
+        .route(
+            "/chatrooms/{chatroom_uid}/messages",
+            post(send_chatroom_message),
+        )
+}
+
+/// The routes which predate `/api/v1`, kept so that older clients keep working.
+/// Every response is marked deprecated, and points at the route replacing it.
+fn legacy_router(state: &ServerState) -> Router<ServerState> {
+    let routes: [(&str, &'static str, MethodRouter<ServerState>); 8] = [
+        (
+            "/api/register",
+            "/api/v1/users",
+            rate_limited(post(register_user), state),
+        ),
+        (
+            "/api/login",
+            "/api/v1/sessions",
+            rate_limited(login_throttled(post(fetch_login), state), state),
+        ),
+        (
+            "/api/session",
+            "/api/v1/sessions/current",
+            post(fetch_session_token),
+        ),
+        (
+            "/api/logout",
+            "/api/v1/sessions",
+            post(handle_logout_request),
+        ),
+        (
+            "/api/request_unknown_chatroom",
+            "/api/v1/chatrooms/lookup",
+            rate_limited(post(fetch_unknown_chatroom), state),
+        ),
+        (
+            "/api/request_known_chatroom",
+            "/api/v1/chatrooms/known",
+            post(fetch_known_chatrooms),
+        ),
+        (
+            "/api/chatroom_new",
+            "/api/v1/chatrooms",
+            post(create_chatroom),
+        ),
+        (
+            "/api/chatroom_send_message",
+            "/api/v1/chatrooms",
+            post(create_chatroom),
+        ),
+    ];
+
+    routes
+        .into_iter()
+        .fold(Router::new(), |router, (path, successor, method_router)| {
+            let successor_link = format!("<{successor}>; rel=\"successor-version\"");
+
+            router.route(
+                path,
+                method_router
+                    .layer::<_, Infallible>(SetResponseHeaderLayer::overriding(
+                        HeaderName::from_static("deprecation"),
+                        HeaderValue::from_static("true"),
+                    ))
+                    .layer::<_, Infallible>(SetResponseHeaderLayer::overriding(
+                        header::LINK,
+                        HeaderValue::from_str(&successor_link).unwrap(),
+                    )),
+            )
+        })
+}
+
+/// Guards an endpoint accepting guessable secrets, see [`limit_auth_requests`].
+fn rate_limited(
+    method_router: MethodRouter<ServerState>,
+    state: &ServerState,
+) -> MethodRouter<ServerState> {
+    method_router.layer(middleware::from_fn_with_state(
+        state.clone(),
+        limit_auth_requests,
+    ))
+}
+
+/// Blocks logins to accounts with recent failed attempts, see [`throttle_failed_logins`].
+fn login_throttled(
+    method_router: MethodRouter<ServerState>,
+    state: &ServerState,
+) -> MethodRouter<ServerState> {
+    method_router.layer(middleware::from_fn_with_state(
+        state.clone(),
+        throttle_failed_logins,
+    ))
+}
@@ -0,0 +1,53 @@
+use axum::extract::State;
+use diesel::{RunQueryDsl, dsl::count_star, query_dsl::methods::SelectDsl};
+use metrics::gauge;
+use tokio::task::spawn_blocking;
+use tracing::error;
+
+use crate::{
+    PgPool, ServerState,
+    monitoring::{
+        ACTIVE_SESSIONS, DB_POOL_CONNECTIONS, DB_POOL_IDLE_CONNECTIONS, DB_POOL_MAX_SIZE,
+        prometheus_handle,
+    },
+    schema::user_signin_tokens::dsl::user_signin_tokens,
+};
+
+/// Renders every metric in the Prometheus text format.
+/// Gauges which describe the current state of the server are sampled right before rendering.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Metrics in the Prometheus text format.", body = String, content_type = "text/plain"))
+)]
+pub async fn render_metrics(State(state): State<ServerState>) -> String {
+    let handle = prometheus_handle();
+
+    let pool_state = state.pg_pool.state();
+
+    gauge!(DB_POOL_CONNECTIONS).set(pool_state.connections);
+    gauge!(DB_POOL_IDLE_CONNECTIONS).set(pool_state.idle_connections);
+    gauge!(DB_POOL_MAX_SIZE).set(state.pg_pool.max_size());
+
+    // A failing database should not take the rest of the metrics down with it
+    let pg_pool = state.pg_pool.clone();
+
+    match spawn_blocking(move || sample_active_sessions(&pg_pool)).await {
+        Ok(Ok(session_count)) => gauge!(ACTIVE_SESSIONS).set(session_count as f64),
+        Ok(Err(err)) => error!("An error occured while counting the active sessions: {err}"),
+        Err(err) => error!("Counting the active sessions has panicked: {err}"),
+    }
+
+    handle.run_upkeep();
+
+    handle.render()
+}
+
+fn sample_active_sessions(pg_pool: &PgPool) -> anyhow::Result<i64> {
+    let mut pg_connection = pg_pool.get()?;
+
+    Ok(user_signin_tokens
+        .select(count_star())
+        .first::<i64>(&mut pg_connection)?)
+}
@@ -0,0 +1,185 @@
+use utoipa::OpenApi;
+
+use crate::api::{
+    account_settings, chatroom_directory, chatroom_history, chatroom_members, chatroom_messages,
+    chatroom_settings, contacts, email_verification, error, health, monitoring, password_reset,
+    personal_data, pinned_messages, profiles, two_factor, user_account_control, user_search,
+    versions,
+};
+
+/// The OpenAPI document of the server, served at `/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Whatssock",
+        description = "The HTTP API of the Whatssock chat server. Every error is answered with an `ApiErrorResponse`.\n\nOnly the current version of the API is documented here, see `/api/versions` for every version the server speaks."
+    ),
+    paths(
+        user_account_control::register_user,
+        user_account_control::fetch_login,
+        user_account_control::fetch_session_token,
+        user_account_control::handle_logout_request,
+        user_account_control::fetch_unknown_chatroom,
+        user_account_control::fetch_known_chatrooms,
+        user_account_control::create_chatroom,
+        contacts::open_direct_message,
+        chatroom_directory::fetch_chatroom_directory,
+        chatroom_directory::update_listing,
+        chatroom_directory::upload_chatroom_icon,
+        chatroom_directory::delete_chatroom_icon,
+        chatroom_directory::fetch_chatroom_icon,
+        chatroom_settings::update_chatroom_settings,
+        chatroom_settings::transfer_ownership,
+        chatroom_members::join_chatroom,
+        chatroom_members::leave_chatroom,
+        chatroom_members::kick_participant,
+        chatroom_members::add_admin,
+        chatroom_members::remove_admin,
+        chatroom_history::fetch_chatroom_history,
+        chatroom_messages::send_chatroom_message,
+        pinned_messages::pin_message,
+        pinned_messages::unpin_message,
+        pinned_messages::fetch_pinned_messages,
+        account_settings::change_password,
+        account_settings::change_username,
+        account_settings::change_email,
+        email_verification::request_email_verification,
+        email_verification::confirm_email,
+        password_reset::request_password_reset,
+        password_reset::confirm_password_reset,
+        personal_data::export_personal_data,
+        personal_data::delete_account,
+        profiles::update_profile,
+        profiles::upload_avatar,
+        profiles::delete_avatar,
+        profiles::fetch_profiles,
+        profiles::fetch_avatar,
+        user_search::search_users,
+        user_search::update_discoverability,
+        contacts::list_contacts,
+        contacts::send_contact_request,
+        contacts::accept_contact_request,
+        contacts::decline_contact_request,
+        contacts::remove_contact,
+        contacts::block_user,
+        contacts::unblock_user,
+        two_factor::enroll_two_factor,
+        two_factor::confirm_two_factor,
+        two_factor::regenerate_recovery_codes,
+        two_factor::disable_two_factor,
+        two_factor::complete_login,
+        health::liveness,
+        health::readiness,
+        health::version,
+        monitoring::render_metrics,
+        versions::api_versions,
+    ),
+    components(schemas(error::ErrorCode, error::ApiErrorResponse)),
+    tags(
+        (name = "accounts", description = "Registration, login and sessions."),
+        (name = "profiles", description = "Display names, avatars and statuses of users."),
+        (name = "contacts", description = "Finding other users, contact requests, contact lists and blocked users."),
+        (name = "chatrooms", description = "Creating, looking up, joining, listing and administering chatrooms, and their history."),
+        (name = "operations", description = "Health checks, version information and metrics."),
+    )
+)]
+pub struct ApiDoc;
+
+/// Documents the shapes of the types shared with the clients through whatssock-lib.
+/// The types themselves can not implement [`utoipa::ToSchema`] (they live in another crate), so they are mirrored here field by field.
+/// `tests/openapi.rs` fails if these drift from the real types.
+pub mod schemas {
+    use utoipa::ToSchema;
+
+    /// Mirror of `whatssock_lib::client::RegisterRequest`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct RegisterRequest {
+        pub username: String,
+        pub password: String,
+        pub email: String,
+    }
+
+    /// Mirror of `whatssock_lib::client::LoginRequest`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct LoginRequest {
+        pub username: String,
+        pub password: String,
+    }
+
+    /// Mirror of `whatssock_lib::server::LoginResponse`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct LoginResponse {
+        pub user_id: i32,
+        /// 32 random bytes identifying the session, send them back in every `UserSession`.
+        #[schema(min_items = 32, max_items = 32)]
+        pub session_token: Vec<u8>,
+        /// The internal ids of the chatrooms the user is a participant of.
+        pub chatrooms_joined: Vec<Option<i32>>,
+    }
+
+    /// Mirror of `whatssock_lib::server::LogoutResponse`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct LogoutResponse {}
+
+    /// Mirror of `whatssock_lib::UserSession`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct UserSession {
+        pub user_id: i32,
+        pub session_token: Vec<u8>,
+    }
+
+    /// Mirror of `whatssock_lib::client::UserInformation`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct UserInformation {
+        pub username: String,
+        pub chatrooms_joined: Vec<Option<i32>>,
+    }
+
+    /// Mirror of `whatssock_lib::CreateChatroomRequest`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct CreateChatroomRequest {
+        pub user_session: UserSession,
+        pub chatroom_name: String,
+        /// Anyone knowing the chatroom's id can look it up if this is not set.
+        pub chatroom_passw: Option<String>,
+    }
+
+    /// Mirror of `whatssock_lib::FetchUnknownChatroom`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct FetchUnknownChatroom {
+        /// The public id of the chatroom, this is what users share with each other.
+        pub chatroom_id: String,
+        pub password: Option<String>,
+    }
+
+    /// Mirror of `whatssock_lib::FetchKnownChatrooms`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct FetchKnownChatrooms {
+        pub user_session: UserSession,
+        /// The internal ids of the requested chatrooms, the user has to be a participant of every one of them.
+        pub chatroom_uids: Vec<i32>,
+    }
+
+    /// Mirror of `whatssock_lib::FetchChatroomResponse`.
+    #[derive(ToSchema)]
+    #[allow(dead_code)]
+    pub struct FetchChatroomResponse {
+        /// The internal id of the chatroom.
+        pub chatroom_uid: i32,
+        /// The public id of the chatroom.
+        pub chatroom_id: String,
+        pub chatroom_name: String,
+        pub participants: Vec<Option<i32>>,
+        pub is_direct_message: bool,
+        pub last_message_id: Option<i32>,
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::Context;
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{TimeDelta, Utc};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl, SelectableHelper,
+};
+use rand::{Rng, rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{Instrument, Span, error, info_span};
+use utoipa::ToSchema;
+
+use crate::{
+    ServerState,
+    api::error::{ApiError, ApiErrorResponse},
+    mailer::Email,
+    models::{NewPasswordResetToken, PasswordResetTokenEntry, UserAccountEntry},
+    schema::{password_reset_tokens, user_signin_tokens, users},
+};
+
+/// How long a reset token can be used after it has been requested.
+const RESET_TOKEN_LIFETIME: TimeDelta = TimeDelta::minutes(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetRequest {
+    /// The email address of the account.
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConfirmPasswordReset {
+    /// The token sent in the password reset email.
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Emails a password reset token to the address, if it belongs to an account.
+/// The response is the same either way, so that it can not be used to find out which addresses are registered.
+#[utoipa::path(
+    post,
+    path = "/api/v1/password_reset",
+    tag = "accounts",
+    request_body = PasswordResetRequest,
+    responses(
+        (status = 202, description = "If the address belongs to an account, a reset token has been sent to it."),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn request_password_reset(
+    State(state): State<ServerState>,
+    Json(request): Json<PasswordResetRequest>,
+) -> Result<StatusCode, ApiError> {
+    let token = generate_reset_token();
+    let token_hash = Sha256::digest(token).to_vec();
+
+    let user_account = state
+        .run_query(move |pg_connection| {
+            let Some(user_account) = info_span!("db", query = "find_user_by_email")
+                .in_scope(|| {
+                    users::table
+                        .filter(users::email.eq(request.email))
+                        .select(UserAccountEntry::as_select())
+                        .first(pg_connection)
+                        .optional()
+                })
+                .context("An error occured while searching for the account of the email address")?
+            else {
+                return Ok(None);
+            };
+
+            info_span!("db", query = "insert_password_reset_token")
+                .in_scope(|| {
+                    diesel::insert_into(password_reset_tokens::table)
+                        .values(&NewPasswordResetToken {
+                            user_id: user_account.id,
+                            token_hash,
+                            expires_at: (Utc::now() + RESET_TOKEN_LIFETIME).naive_utc(),
+                        })
+                        .execute(pg_connection)
+                })
+                .context("An error occured while storing the password reset token")?;
+
+            Ok(Some(user_account))
+        })
+        .await?;
+
+    if let Some(user_account) = user_account {
+        let email = Email {
+            to: user_account.email,
+            subject: String::from("Reset your password"),
+            body: format!(
+                "Hi {}!\n\nSomebody has asked to reset the password of your Whatssock account. Enter the code below in the app to choose a new password:\n{}\n\nThe code expires in {} minutes, and can only be used once. If you have not asked for it, ignore this email, your password stays the same.",
+                user_account.username,
+                hex::encode(token),
+                RESET_TOKEN_LIFETIME.num_minutes(),
+            ),
+        };
+
+        // The email is sent in the background, waiting for the mailer would make known addresses answer slower than unknown ones
+        tokio::spawn(
+            async move {
+                if let Err(err) = state.send_email(email).await {
+                    error!("An error occured while sending the password reset email: {err:#}");
+                }
+            }
+            .instrument(Span::current()),
+        );
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Sets a new password with a reset token, and logs the user out everywhere.
+#[utoipa::path(
+    post,
+    path = "/api/v1/password_reset/confirm",
+    tag = "accounts",
+    request_body = ConfirmPasswordReset,
+    responses(
+        (status = 204, description = "The password has been changed, every session of the user has been revoked."),
+        (status = 400, description = "The token is invalid, has expired, or has already been used.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn confirm_password_reset(
+    State(state): State<ServerState>,
+    Json(request): Json<ConfirmPasswordReset>,
+) -> Result<StatusCode, ApiError> {
+    let token = hex::decode(&request.token).map_err(|_| ApiError::InvalidToken)?;
+    let token_hash = Sha256::digest(token).to_vec();
+
+    let username = state
+        .run_query(move |pg_connection| {
+            pg_connection.transaction(|pg_connection| {
+                let now = Utc::now().naive_utc();
+
+                // Locking the row makes sure that the token can not be used twice concurrently
+                let reset_token = info_span!("db", query = "find_password_reset_token")
+                    .in_scope(|| {
+                        password_reset_tokens::table
+                            .filter(password_reset_tokens::token_hash.eq(token_hash))
+                            .filter(password_reset_tokens::used_at.is_null())
+                            .filter(password_reset_tokens::expires_at.gt(now))
+                            .select(PasswordResetTokenEntry::as_select())
+                            .for_update()
+                            .first(pg_connection)
+                            .optional()
+                    })
+                    .context("An error occured while looking up the password reset token")?
+                    .ok_or(ApiError::InvalidToken)?;
+
+                // Every outstanding token of the user is used up, not only the one presented
+                info_span!("db", query = "use_password_reset_tokens")
+                    .in_scope(|| {
+                        diesel::update(
+                            password_reset_tokens::table
+                                .filter(password_reset_tokens::user_id.eq(reset_token.user_id))
+                                .filter(password_reset_tokens::used_at.is_null()),
+                        )
+                        .set(password_reset_tokens::used_at.eq(now))
+                        .execute(pg_connection)
+                    })
+                    .context("An error occured while marking the password reset tokens used")?;
+
+                let username = info_span!("db", query = "update_password")
+                    .in_scope(|| {
+                        diesel::update(users::table.find(reset_token.user_id))
+                            .set(users::passw.eq(request.new_password))
+                            .returning(users::username)
+                            .get_result::<String>(pg_connection)
+                    })
+                    .context("An error occured while updating the user's password")?;
+
+                info_span!("db", query = "delete_user_sessions")
+                    .in_scope(|| {
+                        diesel::delete(
+                            user_signin_tokens::table
+                                .filter(user_signin_tokens::user_id.eq(reset_token.user_id)),
+                        )
+                        .execute(pg_connection)
+                    })
+                    .context("An error occured while revoking the user's sessions")?;
+
+                Ok(username)
+            })
+        })
+        .await?;
+
+    // The failed attempts of whoever made the user reset their password must not keep them locked out
+    state.auth_limits.failed_logins.record_success(&username);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn generate_reset_token() -> [u8; 32] {
+    let mut token = [0_u8; 32];
+
+    rng().fill(&mut token);
+
+    token
+}
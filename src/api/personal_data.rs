@@ -0,0 +1,598 @@
+use std::{
+    io::{Cursor, Write},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    PgArrayExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper,
+    dsl::array_remove,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{ChatMessage, UserSession};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    ServerState,
+    api::{
+        account_settings::{check_current_password, find_user_account},
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        profiles::load_profiles,
+        system_messages::{SystemEvent, emit_system_event},
+        user_account_control::verify_user_session,
+    },
+    models::{
+        ChatroomEntry, ContactEntry, MessageEntry, PasswordResetTokenEntry, UserSessionEntry,
+    },
+    schema::{
+        chatrooms, contacts, messages, password_reset_tokens, recovery_codes, user_blocks,
+        user_profiles, user_signin_tokens, user_totp, users,
+    },
+};
+
+/// The username of the account the messages of deleted accounts are attributed to, it is created by the migrations.
+pub const TOMBSTONE_USERNAME: &str = "[deleted]";
+
+/// What happens to the messages of a user who deletes their account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletedUserMessages {
+    /// The messages stay in their chatrooms, attributed to the tombstone user.
+    Anonymize,
+    /// The messages are deleted from every chatroom, the system messages recording the changes the user has made to chatrooms are anonymized.
+    Delete,
+}
+
+impl FromStr for DeletedUserMessages {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "anonymize" => Ok(Self::Anonymize),
+            "delete" => Ok(Self::Delete),
+            _ => Err(format!(
+                "Unknown policy `{value}`, expected `anonymize` or `delete`"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeleteAccountRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    pub current_password: String,
+}
+
+/// `account.json` of the export.
+/// The password and the secrets (session tokens, two-factor secret, recovery codes) are left out, the archive is likely to be stored less carefully than the database.
+#[derive(Debug, Serialize)]
+struct AccountExport {
+    id: i32,
+    username: String,
+    email: String,
+    created_at: chrono::NaiveDate,
+    email_verified_at: Option<chrono::NaiveDateTime>,
+    is_admin: bool,
+    discoverability: String,
+    two_factor_enabled: bool,
+    unused_recovery_codes: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionExport {
+    session_id: i32,
+}
+
+#[derive(Debug, Serialize)]
+struct PasswordResetExport {
+    expires_at: chrono::NaiveDateTime,
+    used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+struct MembershipExport {
+    chatroom_uid: i32,
+    chatroom_id: String,
+    chatroom_name: String,
+    is_direct_message: bool,
+}
+
+/// `contacts.json` of the export.
+#[derive(Debug, Default, Serialize)]
+struct ContactsExport {
+    contacts: Vec<ContactExport>,
+    incoming_requests: Vec<ContactExport>,
+    outgoing_requests: Vec<ContactExport>,
+}
+
+#[derive(Debug, Serialize)]
+struct ContactExport {
+    user_id: i32,
+    requested_at: chrono::NaiveDateTime,
+    accepted_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+struct BlockExport {
+    user_id: i32,
+    blocked_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+struct MessageExport {
+    id: i32,
+    chatroom_uid: i32,
+    send_date: chrono::NaiveDateTime,
+    message: Option<ChatMessage>,
+    /// The hex encoded message as stored, only set if it could not be decoded.
+    raw_message: Option<String>,
+    /// The change the user has made to the chatroom, only set for system messages.
+    event: Option<serde_json::Value>,
+}
+
+/// Exports everything the server stores about the user as a zip archive of JSON files.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/export",
+    tag = "accounts",
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, description = "A zip archive containing `account.json`, `profile.json`, `sessions.json`, `password_resets.json`, `chatrooms.json`, `messages.json`, `contacts.json`, `blocks.json` and the avatar of the user.", content_type = "application/zip"),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn export_personal_data(
+    State(state): State<ServerState>,
+    Json(user_session): Json<UserSession>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (username, archive) = state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let user_account = find_user_account(pg_connection, user_session.user_id)?;
+
+            let two_factor_enabled = info_span!("db", query = "find_user_totp")
+                .in_scope(|| {
+                    user_totp::table
+                        .find(user_account.id)
+                        .select(user_totp::confirmed_at.is_not_null())
+                        .first::<bool>(pg_connection)
+                        .optional()
+                })
+                .context("An error occured while fetching the user's authenticator")?
+                .unwrap_or(false);
+
+            let unused_recovery_codes = info_span!("db", query = "count_recovery_codes")
+                .in_scope(|| {
+                    recovery_codes::table
+                        .filter(recovery_codes::user_id.eq(user_account.id))
+                        .filter(recovery_codes::used_at.is_null())
+                        .count()
+                        .get_result::<i64>(pg_connection)
+                })
+                .context("An error occured while counting the user's recovery codes")?;
+
+            let profile = load_profiles(pg_connection, &[user_account.id])?;
+
+            let avatar = info_span!("db", query = "find_user_avatar")
+                .in_scope(|| {
+                    user_profiles::table
+                        .find(user_account.id)
+                        .select((user_profiles::avatar_content_type, user_profiles::avatar))
+                        .first::<(Option<String>, Option<Vec<u8>>)>(pg_connection)
+                        .optional()
+                })
+                .context("An error occured while fetching the user's avatar")?;
+
+            let sessions = info_span!("db", query = "find_user_sessions")
+                .in_scope(|| {
+                    user_signin_tokens::table
+                        .filter(user_signin_tokens::user_id.eq(user_account.id))
+                        .select(UserSessionEntry::as_select())
+                        .load(pg_connection)
+                })
+                .context("An error occured while fetching the user's sessions")?;
+
+            let password_resets = info_span!("db", query = "find_password_reset_tokens")
+                .in_scope(|| {
+                    password_reset_tokens::table
+                        .filter(password_reset_tokens::user_id.eq(user_account.id))
+                        .select(PasswordResetTokenEntry::as_select())
+                        .load(pg_connection)
+                })
+                .context("An error occured while fetching the user's password reset tokens")?;
+
+            let memberships = info_span!("db", query = "find_user_chatrooms")
+                .in_scope(|| {
+                    chatrooms::table
+                        .filter(chatrooms::participants.contains(vec![Some(user_account.id)]))
+                        .select(ChatroomEntry::as_select())
+                        .load(pg_connection)
+                })
+                .context("An error occured while fetching the user's chatrooms")?;
+
+            let own_messages = info_span!("db", query = "find_user_messages")
+                .in_scope(|| {
+                    messages::table
+                        .filter(messages::owner_user_id.eq(user_account.id))
+                        .order(messages::id)
+                        .select(MessageEntry::as_select())
+                        .load(pg_connection)
+                })
+                .context("An error occured while fetching the user's messages")?;
+
+            let contact_entries = info_span!("db", query = "find_user_contacts")
+                .in_scope(|| {
+                    contacts::table
+                        .filter(
+                            contacts::requester_id
+                                .eq(user_account.id)
+                                .or(contacts::addressee_id.eq(user_account.id)),
+                        )
+                        .order(contacts::created_at)
+                        .select(ContactEntry::as_select())
+                        .load(pg_connection)
+                })
+                .context("An error occured while fetching the user's contacts")?;
+
+            let blocks = info_span!("db", query = "find_user_blocks")
+                .in_scope(|| {
+                    user_blocks::table
+                        .filter(user_blocks::blocker_id.eq(user_account.id))
+                        .order(user_blocks::created_at)
+                        .select((user_blocks::blocked_id, user_blocks::created_at))
+                        .load::<(i32, chrono::NaiveDateTime)>(pg_connection)
+                })
+                .context("An error occured while fetching the users blocked by the user")?;
+
+            let account = AccountExport {
+                id: user_account.id,
+                username: user_account.username.clone(),
+                email: user_account.email,
+                created_at: user_account.created_at,
+                email_verified_at: user_account.email_verified_at,
+                is_admin: user_account.is_admin,
+                discoverability: user_account.discoverability,
+                two_factor_enabled,
+                unused_recovery_codes,
+            };
+
+            let sessions: Vec<SessionExport> = sessions
+                .into_iter()
+                .map(|session| SessionExport {
+                    session_id: session.token_id,
+                })
+                .collect();
+
+            let password_resets: Vec<PasswordResetExport> = password_resets
+                .into_iter()
+                .map(|reset_token| PasswordResetExport {
+                    expires_at: reset_token.expires_at,
+                    used_at: reset_token.used_at,
+                })
+                .collect();
+
+            let memberships: Vec<MembershipExport> = memberships
+                .into_iter()
+                .map(|chatroom| MembershipExport {
+                    chatroom_uid: chatroom.id,
+                    chatroom_id: chatroom.chatroom_id,
+                    chatroom_name: chatroom.chatroom_name,
+                    is_direct_message: chatroom.is_direct_message,
+                })
+                .collect();
+
+            let mut contact_list = ContactsExport::default();
+
+            for entry in contact_entries {
+                let is_requester = entry.requester_id == user_account.id;
+                let contact = ContactExport {
+                    user_id: if is_requester {
+                        entry.addressee_id
+                    } else {
+                        entry.requester_id
+                    },
+                    requested_at: entry.created_at,
+                    accepted_at: entry.accepted_at,
+                };
+
+                if contact.accepted_at.is_some() {
+                    contact_list.contacts.push(contact);
+                } else if is_requester {
+                    contact_list.outgoing_requests.push(contact);
+                } else {
+                    contact_list.incoming_requests.push(contact);
+                }
+            }
+
+            let blocks: Vec<BlockExport> = blocks
+                .into_iter()
+                .map(|(blocked_id, created_at)| BlockExport {
+                    user_id: blocked_id,
+                    blocked_at: created_at,
+                })
+                .collect();
+
+            // The messages are stored serialized with rmp_serde, a message which does not decode is exported as it is stored
+            let own_messages: Vec<MessageExport> = own_messages
+                .into_iter()
+                .map(|message| {
+                    let decoded = message.raw_message.as_deref().and_then(|raw_message| {
+                        rmp_serde::from_slice::<ChatMessage>(raw_message).ok()
+                    });
+
+                    MessageExport {
+                        id: message.id,
+                        chatroom_uid: message.parent_chatroom_id,
+                        send_date: message.send_date,
+                        raw_message: message
+                            .raw_message
+                            .filter(|_| decoded.is_none())
+                            .map(hex::encode),
+                        message: decoded,
+                        event: message.event_payload,
+                    }
+                })
+                .collect();
+
+            let mut files = vec![
+                ("account.json", serde_json::to_vec_pretty(&account)?),
+                ("profile.json", serde_json::to_vec_pretty(&profile[0])?),
+                ("sessions.json", serde_json::to_vec_pretty(&sessions)?),
+                (
+                    "password_resets.json",
+                    serde_json::to_vec_pretty(&password_resets)?,
+                ),
+                ("chatrooms.json", serde_json::to_vec_pretty(&memberships)?),
+                ("messages.json", serde_json::to_vec_pretty(&own_messages)?),
+                ("contacts.json", serde_json::to_vec_pretty(&contact_list)?),
+                ("blocks.json", serde_json::to_vec_pretty(&blocks)?),
+            ];
+
+            if let Some((Some(content_type), Some(avatar))) = avatar {
+                files.push((avatar_file_name(&content_type), avatar));
+            }
+
+            let archive = write_archive(&files)
+                .context("An error occured while writing the export archive")?;
+
+            Ok((user_account.username, archive))
+        })
+        .await?;
+
+    // Usernames are free text, only the safe characters are kept in the file name
+    let file_name: String = username
+        .chars()
+        .filter(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '_'))
+        .collect();
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, String::from("application/zip")),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"whatssock-{file_name}.zip\""),
+            ),
+        ],
+        archive,
+    ))
+}
+
+/// Deletes the account of the user.
+/// The user is removed from every chatroom and logged out everywhere, the chatrooms they own are handed over to an admin or to the longest-standing participant.
+/// Their messages are anonymized or deleted depending on `DELETED_USER_MESSAGES`.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users",
+    tag = "accounts",
+    request_body = DeleteAccountRequest,
+    responses(
+        (status = 204, description = "The account has been deleted."),
+        (status = 401, description = "The session or the current password is invalid.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn delete_account(
+    State(state): State<ServerState>,
+    Json(request): Json<DeleteAccountRequest>,
+) -> Result<StatusCode, ApiError> {
+    let auth_limits = state.auth_limits.clone();
+    let deleted_user_messages = state.config.deleted_user_messages;
+
+    state
+        .run_query(move |pg_connection| {
+            pg_connection.transaction(|pg_connection| {
+                verify_user_session(pg_connection, &request.user_session)?;
+
+                let user_account = find_user_account(pg_connection, request.user_session.user_id)?;
+
+                check_current_password(
+                    &auth_limits.failed_logins,
+                    &user_account,
+                    &request.current_password,
+                )?;
+
+                let tombstone_id = info_span!("db", query = "find_tombstone_user")
+                    .in_scope(|| {
+                        users::table
+                            .filter(users::username.eq(TOMBSTONE_USERNAME))
+                            .select(users::id)
+                            .first::<i32>(pg_connection)
+                    })
+                    .context("An error occured while fetching the tombstone user")?;
+
+                hand_over_owned_chatrooms(pg_connection, user_account.id, tombstone_id)?;
+
+                let left_chatroom_uids = info_span!("db", query = "remove_user_from_chatrooms")
+                    .in_scope(|| {
+                        diesel::update(
+                            chatrooms::table.filter(
+                                chatrooms::participants.contains(vec![Some(user_account.id)]),
+                            ),
+                        )
+                        .set(
+                            chatrooms::participants
+                                .eq(array_remove(chatrooms::participants, Some(user_account.id))),
+                        )
+                        .returning(chatrooms::id)
+                        .get_results::<i32>(pg_connection)
+                    })
+                    .context("An error occured while removing the user from their chatrooms")?;
+
+                for chatroom_uid in left_chatroom_uids {
+                    emit_system_event(
+                        pg_connection,
+                        chatroom_uid,
+                        tombstone_id,
+                        SystemEvent::MemberLeft,
+                    )?;
+                }
+
+                info_span!("db", query = "remove_user_from_chatroom_admins")
+                    .in_scope(|| {
+                        diesel::update(
+                            chatrooms::table.filter(
+                                chatrooms::admin_user_ids.contains(vec![Some(user_account.id)]),
+                            ),
+                        )
+                        .set(
+                            chatrooms::admin_user_ids
+                                .eq(array_remove(chatrooms::admin_user_ids, Some(user_account.id))),
+                        )
+                        .execute(pg_connection)
+                    })
+                    .context("An error occured while removing the user from the admins of their chatrooms")?;
+
+                if deleted_user_messages == DeletedUserMessages::Delete {
+                    info_span!("db", query = "delete_user_messages")
+                        .in_scope(|| {
+                            diesel::delete(
+                                messages::table
+                                    .filter(messages::owner_user_id.eq(user_account.id))
+                                    .filter(messages::event_kind.is_null()),
+                            )
+                            .execute(pg_connection)
+                        })
+                        .context("An error occured while deleting the user's messages")?;
+                }
+
+                // System messages are part of the chatrooms' history, they are anonymized under either policy
+                info_span!("db", query = "anonymize_user_messages")
+                    .in_scope(|| {
+                        diesel::update(
+                            messages::table.filter(messages::owner_user_id.eq(user_account.id)),
+                        )
+                        .set(messages::owner_user_id.eq(tombstone_id))
+                        .execute(pg_connection)
+                    })
+                    .context("An error occured while anonymizing the user's messages")?;
+
+                // Sessions, tokens and the two-factor secret are deleted together with the user
+                info_span!("db", query = "delete_user")
+                    .in_scope(|| {
+                        diesel::delete(users::table.find(user_account.id)).execute(pg_connection)
+                    })
+                    .context("An error occured while deleting the user's account")?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Hands every chatroom the user owns over to one of its admins, or to its longest-standing participant if it has none.
+/// The chatrooms nobody else participates in are left without an owner.
+fn hand_over_owned_chatrooms(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+    tombstone_id: i32,
+) -> Result<(), ApiError> {
+    let owned_chatrooms = info_span!("db", query = "find_owned_chatrooms")
+        .in_scope(|| {
+            chatrooms::table
+                .filter(chatrooms::owner_user_id.eq(user_id))
+                .select(ChatroomEntry::as_select())
+                .load(pg_connection)
+        })
+        .context("An error occured while fetching the user's chatrooms")?;
+
+    for chatroom_entry in owned_chatrooms {
+        let is_candidate = |candidate_id: &&i32| {
+            **candidate_id != user_id && chatroom_entry.participants.contains(&Some(**candidate_id))
+        };
+
+        // The participants are stored in the order they have joined
+        let Some(new_owner_id) = chatroom_entry
+            .admin_user_ids
+            .iter()
+            .flatten()
+            .find(is_candidate)
+            .or_else(|| {
+                chatroom_entry
+                    .participants
+                    .iter()
+                    .flatten()
+                    .find(is_candidate)
+            })
+            .copied()
+        else {
+            continue;
+        };
+
+        info_span!("db", query = "update_chatroom_owner")
+            .in_scope(|| {
+                diesel::update(chatrooms::table.find(chatroom_entry.id))
+                    .set((
+                        chatrooms::owner_user_id.eq(new_owner_id),
+                        chatrooms::admin_user_ids
+                            .eq(array_remove(chatrooms::admin_user_ids, Some(new_owner_id))),
+                    ))
+                    .execute(pg_connection)
+            })
+            .context("An error occured while transferring the chatroom's ownership")?;
+
+        emit_system_event(
+            pg_connection,
+            chatroom_entry.id,
+            tombstone_id,
+            SystemEvent::OwnershipTransferred {
+                previous_owner_user_id: tombstone_id,
+                owner_user_id: new_owner_id,
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+fn avatar_file_name(content_type: &str) -> &'static str {
+    match content_type {
+        "image/png" => "avatar.png",
+        "image/jpeg" => "avatar.jpg",
+        "image/gif" => "avatar.gif",
+        "image/webp" => "avatar.webp",
+        _ => "avatar",
+    }
+}
+
+fn write_archive(files: &[(&str, Vec<u8>)]) -> anyhow::Result<Vec<u8>> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, contents) in files {
+        archive.start_file(*name, options)?;
+        archive.write_all(contents)?;
+    }
+
+    Ok(archive.finish()?.into_inner())
+}
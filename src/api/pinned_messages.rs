@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+    SelectableHelper, dsl::count_star,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{FetchChatroomResponse, UserSession};
+
+use crate::{
+    ServerState,
+    api::{
+        chatroom_history::{TimelineEntry, timeline_entry_of},
+        chatroom_settings::find_administered_chatroom,
+        contacts::blocked_user_ids,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        system_messages::{SystemEvent, emit_system_event},
+        user_account_control::{find_chatroom, verify_user_session},
+    },
+    models::MessageEntry,
+    schema::{chatrooms, messages, pinned_messages},
+};
+
+/// A chatroom with the number of its pinned messages, so that clients can show the pin bar without fetching the pins.
+/// The fields of `FetchChatroomResponse` are inlined.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChatroomWithPins {
+    #[serde(flatten)]
+    #[schema(value_type = schemas::FetchChatroomResponse)]
+    pub chatroom: FetchChatroomResponse,
+    pub pinned_message_count: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct KnownChatroomsResponse {
+    pub chatrooms: Vec<ChatroomWithPins>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PinnedMessage {
+    /// `None` if the user who pinned the message has deleted their account.
+    pub pinned_by: Option<i32>,
+    pub message: TimelineEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PinnedMessagesResponse {
+    /// The most recently pinned message first.
+    pub pinned_messages: Vec<PinnedMessage>,
+}
+
+/// Pins a message of the chatroom, only its admins can do this.
+/// Pinning a message which is already pinned does nothing.
+#[utoipa::path(
+    put,
+    path = "/api/v1/chatrooms/{chatroom_uid}/pins/{message_id}",
+    tag = "chatrooms",
+    params(
+        ("chatroom_uid" = i32, Path, description = "The internal id of the chatroom."),
+        ("message_id" = i32, Path, description = "The message to pin."),
+    ),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The message is pinned."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not an admin of the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist, or the message is not one of its messages.", body = ApiErrorResponse),
+        (status = 409, description = "The chatroom has as many pinned messages as allowed.", body = ApiErrorResponse),
+    )
+)]
+pub async fn pin_message(
+    State(state): State<ServerState>,
+    Path((chatroom_uid, message_id)): Path<(i32, i32)>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    let max_pinned_messages = state.config.max_pinned_messages as i64;
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let user_id = user_session.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                find_administered_chatroom(pg_connection, chatroom_uid, user_id)?;
+
+                // System messages can not be pinned
+                let is_chatroom_message = info_span!("db", query = "find_chatroom_message")
+                    .in_scope(|| {
+                        messages::table
+                            .filter(messages::id.eq(message_id))
+                            .filter(messages::parent_chatroom_id.eq(chatroom_uid))
+                            .filter(messages::event_kind.is_null())
+                            .select(messages::id)
+                            .first::<i32>(pg_connection)
+                            .optional()
+                    })
+                    .context("An error occured while fetching the message")?
+                    .is_some();
+
+                if !is_chatroom_message {
+                    return Err(ApiError::MessageNotFound);
+                }
+
+                // Pins of concurrent requests are counted one after the other
+                info_span!("db", query = "lock_chatroom")
+                    .in_scope(|| {
+                        chatrooms::table
+                            .find(chatroom_uid)
+                            .select(chatrooms::id)
+                            .for_update()
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while locking the chatroom")?;
+
+                let pinned_message_ids = info_span!("db", query = "find_pinned_message_ids")
+                    .in_scope(|| {
+                        pinned_messages::table
+                            .filter(pinned_messages::chatroom_uid.eq(chatroom_uid))
+                            .select(pinned_messages::message_id)
+                            .load::<i32>(pg_connection)
+                    })
+                    .context("An error occured while fetching the chatroom's pinned messages")?;
+
+                if pinned_message_ids.contains(&message_id) {
+                    return Ok(StatusCode::NO_CONTENT);
+                }
+
+                if pinned_message_ids.len() as i64 >= max_pinned_messages {
+                    return Err(ApiError::PinLimitReached);
+                }
+
+                info_span!("db", query = "insert_pinned_message")
+                    .in_scope(|| {
+                        diesel::insert_into(pinned_messages::table)
+                            .values((
+                                pinned_messages::message_id.eq(message_id),
+                                pinned_messages::chatroom_uid.eq(chatroom_uid),
+                                pinned_messages::pinned_by.eq(user_id),
+                            ))
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while pinning the message")?;
+
+                emit_system_event(
+                    pg_connection,
+                    chatroom_uid,
+                    user_id,
+                    SystemEvent::MessagePinned { message_id },
+                )?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Unpins a message of the chatroom, only its admins can do this.
+/// Unpinning a message which is not pinned does nothing.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/chatrooms/{chatroom_uid}/pins/{message_id}",
+    tag = "chatrooms",
+    params(
+        ("chatroom_uid" = i32, Path, description = "The internal id of the chatroom."),
+        ("message_id" = i32, Path, description = "The message to unpin."),
+    ),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The message is not pinned."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not an admin of the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn unpin_message(
+    State(state): State<ServerState>,
+    Path((chatroom_uid, message_id)): Path<(i32, i32)>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let user_id = user_session.user_id;
+
+            pg_connection.transaction(|pg_connection| {
+                find_administered_chatroom(pg_connection, chatroom_uid, user_id)?;
+
+                let deleted_rows = info_span!("db", query = "delete_pinned_message")
+                    .in_scope(|| {
+                        diesel::delete(
+                            pinned_messages::table
+                                .filter(pinned_messages::message_id.eq(message_id))
+                                .filter(pinned_messages::chatroom_uid.eq(chatroom_uid)),
+                        )
+                        .execute(pg_connection)
+                    })
+                    .context("An error occured while unpinning the message")?;
+
+                if deleted_rows > 0 {
+                    emit_system_event(
+                        pg_connection,
+                        chatroom_uid,
+                        user_id,
+                        SystemEvent::MessageUnpinned { message_id },
+                    )?;
+                }
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Returns the pinned messages of a chatroom to its participants.
+/// Messages of users the requester has blocked are left out.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/{chatroom_uid}/pins",
+    tag = "chatrooms",
+    params(("chatroom_uid" = i32, Path, description = "The internal id of the chatroom.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, body = PinnedMessagesResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not a participant of the chatroom.", body = ApiErrorResponse),
+        (status = 404, description = "The chatroom does not exist.", body = ApiErrorResponse),
+    )
+)]
+pub async fn fetch_pinned_messages(
+    State(state): State<ServerState>,
+    Path(chatroom_uid): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<Json<PinnedMessagesResponse>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            let chatroom_entry = find_chatroom(pg_connection, chatroom_uid)?;
+
+            if !chatroom_entry
+                .participants
+                .contains(&Some(user_session.user_id))
+            {
+                return Err(ApiError::NotChatroomMember);
+            }
+
+            let blocked_ids = blocked_user_ids(pg_connection, user_session.user_id)?;
+
+            let pinned = info_span!("db", query = "find_pinned_messages")
+                .in_scope(|| {
+                    pinned_messages::table
+                        .inner_join(messages::table)
+                        .filter(pinned_messages::chatroom_uid.eq(chatroom_uid))
+                        .filter(messages::owner_user_id.ne_all(blocked_ids))
+                        .order((
+                            pinned_messages::pinned_at.desc(),
+                            pinned_messages::message_id.desc(),
+                        ))
+                        .select((pinned_messages::pinned_by, MessageEntry::as_select()))
+                        .load::<(Option<i32>, MessageEntry)>(pg_connection)
+                })
+                .context("An error occured while fetching the chatroom's pinned messages")?;
+
+            Ok(Json(PinnedMessagesResponse {
+                pinned_messages: pinned
+                    .into_iter()
+                    .map(|(pinned_by, message_entry)| {
+                        Ok(PinnedMessage {
+                            pinned_by,
+                            message: timeline_entry_of(message_entry)?,
+                        })
+                    })
+                    .collect::<Result<_, ApiError>>()?,
+            }))
+        })
+        .await
+}
+
+/// The number of pinned messages of each of the chatrooms, chatrooms without any are left out.
+pub fn pinned_message_counts(
+    pg_connection: &mut PgConnection,
+    chatroom_uids: &[i32],
+) -> Result<HashMap<i32, i64>, ApiError> {
+    Ok(info_span!("db", query = "count_pinned_messages")
+        .in_scope(|| {
+            pinned_messages::table
+                .filter(pinned_messages::chatroom_uid.eq_any(chatroom_uids))
+                .group_by(pinned_messages::chatroom_uid)
+                .select((pinned_messages::chatroom_uid, count_star()))
+                .load::<(i32, i64)>(pg_connection)
+        })
+        .context("An error occured while counting the chatrooms' pinned messages")?
+        .into_iter()
+        .collect())
+}
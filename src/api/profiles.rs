@@ -0,0 +1,597 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chrono::Utc;
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgArrayExpressionMethods, PgConnection, QueryDsl,
+    RunQueryDsl, SelectableHelper, upsert::excluded,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::UserSession;
+
+use crate::{
+    ServerState,
+    api::{
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        user_account_control::verify_user_session,
+    },
+    models::{NewUserProfile, UserProfileEntry},
+    schema::{chatrooms, user_profiles, users},
+};
+
+const DISPLAY_NAME_MAX_CHARS: usize = 64;
+
+const BIO_MAX_CHARS: usize = 500;
+
+const STATUS_TEXT_MAX_CHARS: usize = 128;
+
+/// Emoji built from several code points (flags, skin tones, families) take up to around ten characters.
+const STATUS_EMOJI_MAX_CHARS: usize = 16;
+
+/// The most profiles a single request can fetch.
+const FETCH_PROFILES_MAX: usize = 100;
+
+/// Every field replaces the stored one, leaving a field out (or sending `null` or an empty string) clears it.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateProfileRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub status_text: Option<String>,
+    #[serde(default)]
+    pub status_emoji: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UploadAvatarRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// The base64 encoded image, a PNG, JPEG, GIF or WebP file.
+    pub image: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FetchProfilesRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// At most 100 ids, e.g. the `participants` of a chatroom.
+    pub user_ids: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserProfile {
+    pub user_id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    /// The avatar is fetched from `/api/v1/users/{user_id}/avatar`.
+    pub has_avatar: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FetchProfilesResponse {
+    pub profiles: Vec<UserProfile>,
+}
+
+/// Updates the profile of the user.
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/profile",
+    tag = "profiles",
+    request_body = UpdateProfileRequest,
+    responses(
+        (status = 200, body = UserProfile),
+        (status = 400, description = "A field is too long, or the status emoji is not a single emoji.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn update_profile(
+    State(state): State<ServerState>,
+    Json(request): Json<UpdateProfileRequest>,
+) -> Result<Json<UserProfile>, ApiError> {
+    let new_profile = NewUserProfile {
+        user_id: request.user_session.user_id,
+        display_name: bounded_text(
+            request.display_name,
+            DISPLAY_NAME_MAX_CHARS,
+            "The display name can be at most 64 characters long.",
+        )?,
+        bio: bounded_text(
+            request.bio,
+            BIO_MAX_CHARS,
+            "The bio can be at most 500 characters long.",
+        )?,
+        status_text: bounded_text(
+            request.status_text,
+            STATUS_TEXT_MAX_CHARS,
+            "The status can be at most 128 characters long.",
+        )?,
+        status_emoji: bounded_text(
+            request.status_emoji,
+            STATUS_EMOJI_MAX_CHARS,
+            "The status emoji has to be a single emoji.",
+        )?
+        .map(|status_emoji| {
+            if is_single_emoji(&status_emoji) {
+                Ok(status_emoji)
+            } else {
+                Err(ApiError::InvalidInput {
+                    message: "The status emoji has to be a single emoji.",
+                })
+            }
+        })
+        .transpose()?,
+        updated_at: Utc::now().naive_utc(),
+    };
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            info_span!("db", query = "upsert_user_profile")
+                .in_scope(|| {
+                    diesel::insert_into(user_profiles::table)
+                        .values(&new_profile)
+                        .on_conflict(user_profiles::user_id)
+                        .do_update()
+                        .set(&new_profile)
+                        .execute(pg_connection)
+                })
+                .context("An error occured while updating the user's profile")?;
+
+            let mut profiles = load_profiles(pg_connection, &[new_profile.user_id])?;
+
+            Ok(Json(profiles.remove(0)))
+        })
+        .await
+}
+
+/// Replaces the avatar of the user.
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/profile/avatar",
+    tag = "profiles",
+    request_body = UploadAvatarRequest,
+    responses(
+        (status = 204, description = "The avatar has been replaced."),
+        (status = 400, description = "The image is malformed, too big, or not in a supported format.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn upload_avatar(
+    State(state): State<ServerState>,
+    Json(request): Json<UploadAvatarRequest>,
+) -> Result<StatusCode, ApiError> {
+    let (image, content_type) = decode_image(&request.image, state.config.avatar_max_size)?;
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            info_span!("db", query = "upsert_user_avatar")
+                .in_scope(|| {
+                    diesel::insert_into(user_profiles::table)
+                        .values((
+                            user_profiles::user_id.eq(request.user_session.user_id),
+                            user_profiles::avatar.eq(image),
+                            user_profiles::avatar_content_type.eq(content_type),
+                        ))
+                        .on_conflict(user_profiles::user_id)
+                        .do_update()
+                        .set((
+                            user_profiles::avatar.eq(excluded(user_profiles::avatar)),
+                            user_profiles::avatar_content_type
+                                .eq(excluded(user_profiles::avatar_content_type)),
+                            user_profiles::updated_at.eq(Utc::now().naive_utc()),
+                        ))
+                        .execute(pg_connection)
+                })
+                .context("An error occured while storing the user's avatar")?;
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Removes the avatar of the user.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/profile/avatar",
+    tag = "profiles",
+    request_body = schemas::UserSession,
+    responses(
+        (status = 204, description = "The avatar has been removed."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn delete_avatar(
+    State(state): State<ServerState>,
+    Json(user_session): Json<UserSession>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            info_span!("db", query = "delete_user_avatar")
+                .in_scope(|| {
+                    diesel::update(user_profiles::table.find(user_session.user_id))
+                        .set((
+                            user_profiles::avatar.eq(None::<Vec<u8>>),
+                            user_profiles::avatar_content_type.eq(None::<String>),
+                            user_profiles::updated_at.eq(Utc::now().naive_utc()),
+                        ))
+                        .execute(pg_connection)
+                })
+                .context("An error occured while deleting the user's avatar")?;
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Returns the profiles of users, e.g. to show the participants of a chatroom.
+/// Only the profiles of the user themselves and of users sharing a chatroom with them are returned, the other ids are left out.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/profiles",
+    tag = "profiles",
+    request_body = FetchProfilesRequest,
+    responses(
+        (status = 200, body = FetchProfilesResponse),
+        (status = 400, description = "Too many ids have been requested.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn fetch_profiles(
+    State(state): State<ServerState>,
+    Json(request): Json<FetchProfilesRequest>,
+) -> Result<Json<FetchProfilesResponse>, ApiError> {
+    if request.user_ids.len() > FETCH_PROFILES_MAX {
+        return Err(ApiError::InvalidInput {
+            message: "At most 100 profiles can be fetched at once.",
+        });
+    }
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let visible_user_ids = visible_user_ids(pg_connection, request.user_session.user_id)?;
+
+            let user_ids: Vec<i32> = request
+                .user_ids
+                .into_iter()
+                .filter(|user_id| visible_user_ids.contains(user_id))
+                .collect();
+
+            Ok(Json(FetchProfilesResponse {
+                profiles: load_profiles(pg_connection, &user_ids)?,
+            }))
+        })
+        .await
+}
+
+/// Returns the avatar of a user, with the same visibility as [`fetch_profiles`].
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{user_id}/avatar",
+    tag = "profiles",
+    params(("user_id" = i32, Path, description = "The id of the user whose avatar is fetched.")),
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, description = "The image, with its content type.", content_type = "image/*"),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 404, description = "The user does not exist, is not visible, or has no avatar.", body = ApiErrorResponse),
+    )
+)]
+pub async fn fetch_avatar(
+    State(state): State<ServerState>,
+    Path(user_id): Path<i32>,
+    Json(user_session): Json<UserSession>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (content_type, avatar) = state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            if !visible_user_ids(pg_connection, user_session.user_id)?.contains(&user_id) {
+                return Err(ApiError::UserNotFound);
+            }
+
+            let avatar = info_span!("db", query = "find_user_avatar")
+                .in_scope(|| {
+                    user_profiles::table
+                        .find(user_id)
+                        .select((user_profiles::avatar_content_type, user_profiles::avatar))
+                        .first::<(Option<String>, Option<Vec<u8>>)>(pg_connection)
+                        .optional()
+                })
+                .context("An error occured while fetching the user's avatar")?;
+
+            match avatar {
+                Some((Some(content_type), Some(avatar))) => Ok((content_type, avatar)),
+                _ => Err(ApiError::UserNotFound),
+            }
+        })
+        .await?;
+
+    Ok(([(header::CONTENT_TYPE, content_type)], avatar))
+}
+
+/// The ids of the users whose profile `user_id` can see: themselves, and everyone sharing a chatroom with them.
+pub fn visible_user_ids(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+) -> Result<HashSet<i32>, ApiError> {
+    let participants = info_span!("db", query = "find_chatroom_participants")
+        .in_scope(|| {
+            chatrooms::table
+                .filter(chatrooms::participants.contains(vec![Some(user_id)]))
+                .select(chatrooms::participants)
+                .load::<Vec<Option<i32>>>(pg_connection)
+        })
+        .context("An error occured while fetching the participants of the user's chatrooms")?;
+
+    Ok(participants
+        .into_iter()
+        .flatten()
+        .flatten()
+        .chain([user_id])
+        .collect())
+}
+
+/// Loads the profiles of the users, users without a profile get an empty one.
+/// The profiles are returned in the order of `user_ids`, ids of missing users are left out.
+pub fn load_profiles(
+    pg_connection: &mut PgConnection,
+    user_ids: &[i32],
+) -> Result<Vec<UserProfile>, ApiError> {
+    let usernames: HashMap<i32, String> = info_span!("db", query = "find_usernames")
+        .in_scope(|| {
+            users::table
+                .filter(users::id.eq_any(user_ids))
+                .select((users::id, users::username))
+                .load::<(i32, String)>(pg_connection)
+        })
+        .context("An error occured while fetching the usernames")?
+        .into_iter()
+        .collect();
+
+    let mut profiles: HashMap<i32, UserProfileEntry> =
+        info_span!("db", query = "find_user_profiles")
+            .in_scope(|| {
+                user_profiles::table
+                    .filter(user_profiles::user_id.eq_any(user_ids))
+                    .select(UserProfileEntry::as_select())
+                    .load(pg_connection)
+            })
+            .context("An error occured while fetching the user profiles")?
+            .into_iter()
+            .map(|profile| (profile.user_id, profile))
+            .collect();
+
+    Ok(user_ids
+        .iter()
+        .filter_map(|user_id| {
+            let username = usernames.get(user_id)?.clone();
+
+            Some(match profiles.remove(user_id) {
+                Some(profile) => UserProfile {
+                    user_id: *user_id,
+                    username,
+                    display_name: profile.display_name,
+                    bio: profile.bio,
+                    status_text: profile.status_text,
+                    status_emoji: profile.status_emoji,
+                    has_avatar: profile.avatar_content_type.is_some(),
+                },
+                None => UserProfile {
+                    user_id: *user_id,
+                    username,
+                    display_name: None,
+                    bio: None,
+                    status_text: None,
+                    status_emoji: None,
+                    has_avatar: false,
+                },
+            })
+        })
+        .collect())
+}
+
+/// Decodes a base64 encoded image uploaded by a user, and detects its content type.
+/// The content type is served back to other users, so it is never taken from the client.
+pub fn decode_image(image: &str, max_size: usize) -> Result<(Vec<u8>, &'static str), ApiError> {
+    let image = BASE64.decode(image).map_err(|_| ApiError::InvalidInput {
+        message: "The image is not valid base64.",
+    })?;
+
+    if image.len() > max_size {
+        return Err(ApiError::InvalidInput {
+            message: "The image is too big.",
+        });
+    }
+
+    let content_type = detect_image_type(&image).ok_or(ApiError::InvalidInput {
+        message: "The image has to be a PNG, JPEG, GIF or WebP file.",
+    })?;
+
+    Ok((image, content_type))
+}
+
+/// Trims the text, empty text becomes `None`.
+pub fn bounded_text(
+    text: Option<String>,
+    max_chars: usize,
+    message: &'static str,
+) -> Result<Option<String>, ApiError> {
+    let Some(text) = text.map(|text| text.trim().to_string()) else {
+        return Ok(None);
+    };
+
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    if text.chars().count() > max_chars {
+        return Err(ApiError::InvalidInput { message });
+    }
+
+    Ok(Some(text))
+}
+
+/// Whether the text is a single emoji, including the ones built from several code points: flags, keycaps, skin tones and sequences joined with zero width joiners.
+/// The pictographs are the `Extended_Pictographic` code points of the Unicode emoji data, so arrows, shapes and lone skin tones are not emoji.
+fn is_single_emoji(text: &str) -> bool {
+    const ZERO_WIDTH_JOINER: char = '\u{200D}';
+    const VARIATION_SELECTOR: char = '\u{FE0F}';
+    const KEYCAP: char = '\u{20E3}';
+
+    let is_regional_indicator = |char: char| ('\u{1F1E6}'..='\u{1F1FF}').contains(&char);
+    let is_modifier = |char: char| {
+        char == VARIATION_SELECTOR
+            || ('\u{1F3FB}'..='\u{1F3FF}').contains(&char)
+            // Tags, which spell out the flags of subdivisions
+            || ('\u{E0020}'..='\u{E007F}').contains(&char)
+    };
+    let is_pictographic = |char: char| {
+        matches!(
+            char,
+            '\u{A9}'
+                | '\u{AE}'
+                | '\u{203C}'
+                | '\u{2049}'
+                | '\u{2122}'
+                | '\u{2139}'
+                | '\u{2194}'..='\u{2199}'
+                | '\u{21A9}'..='\u{21AA}'
+                | '\u{231A}'..='\u{231B}'
+                | '\u{2328}'
+                | '\u{2388}'
+                | '\u{23CF}'
+                | '\u{23E9}'..='\u{23F3}'
+                | '\u{23F8}'..='\u{23FA}'
+                | '\u{24C2}'
+                | '\u{25AA}'..='\u{25AB}'
+                | '\u{25B6}'
+                | '\u{25C0}'
+                | '\u{25FB}'..='\u{25FE}'
+                | '\u{2600}'..='\u{2605}'
+                | '\u{2607}'..='\u{2612}'
+                | '\u{2614}'..='\u{2685}'
+                | '\u{2690}'..='\u{2705}'
+                | '\u{2708}'..='\u{2712}'
+                | '\u{2714}'
+                | '\u{2716}'
+                | '\u{271D}'
+                | '\u{2721}'
+                | '\u{2728}'
+                | '\u{2733}'..='\u{2734}'
+                | '\u{2744}'
+                | '\u{2747}'
+                | '\u{274C}'
+                | '\u{274E}'
+                | '\u{2753}'..='\u{2755}'
+                | '\u{2757}'
+                | '\u{2763}'..='\u{2767}'
+                | '\u{2795}'..='\u{2797}'
+                | '\u{27A1}'
+                | '\u{27B0}'
+                | '\u{27BF}'
+                | '\u{2934}'..='\u{2935}'
+                | '\u{2B05}'..='\u{2B07}'
+                | '\u{2B1B}'..='\u{2B1C}'
+                | '\u{2B50}'
+                | '\u{2B55}'
+                | '\u{3030}'
+                | '\u{303D}'
+                | '\u{3297}'
+                | '\u{3299}'
+                | '\u{1F000}'..='\u{1F0FF}'
+                | '\u{1F10D}'..='\u{1F10F}'
+                | '\u{1F12F}'
+                | '\u{1F16C}'..='\u{1F171}'
+                | '\u{1F17E}'..='\u{1F17F}'
+                | '\u{1F18E}'
+                | '\u{1F191}'..='\u{1F19A}'
+                | '\u{1F1AD}'..='\u{1F1E5}'
+                | '\u{1F201}'..='\u{1F20F}'
+                | '\u{1F21A}'
+                | '\u{1F22F}'
+                | '\u{1F232}'..='\u{1F23A}'
+                | '\u{1F23C}'..='\u{1F23F}'
+                | '\u{1F249}'..='\u{1F3FA}'
+                | '\u{1F400}'..='\u{1F53D}'
+                | '\u{1F546}'..='\u{1F64F}'
+                | '\u{1F680}'..='\u{1F6FF}'
+                | '\u{1F774}'..='\u{1F77F}'
+                | '\u{1F7D5}'..='\u{1F7FF}'
+                | '\u{1F80C}'..='\u{1F80F}'
+                | '\u{1F848}'..='\u{1F84F}'
+                | '\u{1F85A}'..='\u{1F85F}'
+                | '\u{1F888}'..='\u{1F88F}'
+                | '\u{1F8AE}'..='\u{1F8FF}'
+                | '\u{1F90C}'..='\u{1F93A}'
+                | '\u{1F93C}'..='\u{1F945}'
+                | '\u{1F947}'..='\u{1FAFF}'
+                | '\u{1FC00}'..='\u{1FFFD}'
+        )
+    };
+
+    let chars: Vec<char> = text.chars().collect();
+
+    match chars.as_slice() {
+        // Flags are made of two regional indicators
+        [first, second] if is_regional_indicator(*first) => is_regional_indicator(*second),
+        // Keycaps, like 1️⃣
+        [base, rest @ ..] if base.is_ascii_digit() || matches!(base, '#' | '*') => {
+            matches!(rest, [KEYCAP] | [VARIATION_SELECTOR, KEYCAP])
+        }
+        [first, rest @ ..] if is_pictographic(*first) => {
+            let mut joined = false;
+
+            for char in rest.iter().copied() {
+                if char == ZERO_WIDTH_JOINER && !joined {
+                    joined = true;
+                } else if is_pictographic(char) && joined {
+                    joined = false;
+                } else if !is_modifier(char) || joined {
+                    return false;
+                }
+            }
+
+            !joined
+        }
+        _ => false,
+    }
+}
+
+/// Detects the format of the image from its magic bytes.
+fn detect_image_type(image: &[u8]) -> Option<&'static str> {
+    if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if image.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if image.starts_with(b"GIF87a") || image.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if image.len() >= 12 && image.starts_with(b"RIFF") && &image[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
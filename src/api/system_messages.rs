@@ -0,0 +1,114 @@
+use anyhow::Context;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+
+use crate::{
+    api::error::ApiError,
+    models::NewSystemMessage,
+    schema::{chatrooms, messages},
+};
+
+/// The role of a participant in a chatroom, besides its owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatroomRole {
+    Member,
+    /// Can change the settings of the chatroom and remove members from it.
+    Admin,
+}
+
+/// A change made to a chatroom, recorded in its history as a system message.
+/// The user who made the change is the owner of the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SystemEvent {
+    MemberJoined,
+    MemberLeft,
+    MemberKicked {
+        user_id: i32,
+    },
+    RoleChanged {
+        user_id: i32,
+        role: ChatroomRole,
+    },
+    ChatroomRenamed {
+        previous_name: String,
+        chatroom_name: String,
+    },
+    PasswordSet,
+    PasswordRemoved,
+    DescriptionChanged {
+        description: Option<String>,
+    },
+    IconChanged,
+    IconRemoved,
+    OwnershipTransferred {
+        previous_owner_user_id: i32,
+        owner_user_id: i32,
+    },
+    MessagePinned {
+        message_id: i32,
+    },
+    MessageUnpinned {
+        message_id: i32,
+    },
+}
+
+impl SystemEvent {
+    /// The value stored in `messages.event_kind`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::MemberJoined => "member_joined",
+            Self::MemberLeft => "member_left",
+            Self::MemberKicked { .. } => "member_kicked",
+            Self::RoleChanged { .. } => "role_changed",
+            Self::ChatroomRenamed { .. } => "chatroom_renamed",
+            Self::PasswordSet => "password_set",
+            Self::PasswordRemoved => "password_removed",
+            Self::DescriptionChanged { .. } => "description_changed",
+            Self::IconChanged => "icon_changed",
+            Self::IconRemoved => "icon_removed",
+            Self::OwnershipTransferred { .. } => "ownership_transferred",
+            Self::MessagePinned { .. } => "message_pinned",
+            Self::MessageUnpinned { .. } => "message_unpinned",
+        }
+    }
+}
+
+/// Records `event`, made by `user_id`, in the history of the chatroom.
+/// It becomes the last message of the chatroom, so that clients fetch it like any other message.
+pub fn emit_system_event(
+    pg_connection: &mut PgConnection,
+    chatroom_uid: i32,
+    user_id: i32,
+    event: SystemEvent,
+) -> Result<(), ApiError> {
+    let system_message = NewSystemMessage {
+        parent_chatroom_id: chatroom_uid,
+        owner_user_id: user_id,
+        event_kind: event.kind().to_string(),
+        event_payload: serde_json::to_value(&event)
+            .context("An error occured while serializing a system event")?,
+    };
+
+    let message_id = info_span!("db", query = "insert_system_message")
+        .in_scope(|| {
+            diesel::insert_into(messages::table)
+                .values(&system_message)
+                .returning(messages::id)
+                .get_result::<i32>(pg_connection)
+        })
+        .context("An error occured while storing a system message")?;
+
+    info_span!("db", query = "update_last_message")
+        .in_scope(|| {
+            diesel::update(chatrooms::table.find(chatroom_uid))
+                .set(chatrooms::last_message_id.eq(message_id))
+                .execute(pg_connection)
+        })
+        .context("An error occured while updating the chatroom's last message")?;
+
+    Ok(())
+}
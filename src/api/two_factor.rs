@@ -0,0 +1,610 @@
+use anyhow::Context;
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{TimeDelta, Utc};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl, SelectableHelper, upsert::excluded,
+};
+use metrics::counter;
+use rand::{Rng, distr::Uniform, rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, TOTP};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::{UserSession, server::LoginResponse};
+
+use crate::{
+    ServerState,
+    api::{
+        account_settings::find_user_account,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        user_account_control::{issue_session, verify_user_session},
+    },
+    logging::record_user_id,
+    models::{NewRecoveryCode, NewUserTotp, UserTotpEntry},
+    monitoring::RATE_LIMITED_REQUESTS_TOTAL,
+    rate_limit::LoginThrottle,
+    schema::{recovery_codes, user_totp},
+    signed_links::{LinkSigner, SignedToken},
+};
+
+/// Keeps the login challenges apart from the tokens of the emailed links.
+const LOGIN_CHALLENGE_PURPOSE: &str = "login_challenge";
+
+/// How long the user has to enter their code after their password has been checked.
+const LOGIN_CHALLENGE_LIFETIME: TimeDelta = TimeDelta::minutes(5);
+
+/// The name authenticator apps list the accounts under.
+const TOTP_ISSUER: &str = "Whatssock";
+
+/// Seconds a code is valid for, every authenticator app defaults to this.
+const TOTP_STEP: u64 = 30;
+
+const TOTP_DIGITS: usize = 6;
+
+/// The size of the secrets, as recommended by RFC 4226.
+const TOTP_SECRET_LENGTH: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Recovery codes are typed in by hand, so the characters which are easy to mix up are left out.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TotpEnrollment {
+    /// The base32 encoded secret, for entering it into an authenticator by hand.
+    pub secret: String,
+    /// The `otpauth://` URL of the secret, usually shown as a QR code.
+    pub otpauth_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorCodeRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// The current code of the user's authenticator, or one of their recovery codes.
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodesResponse {
+    /// Every code can be used once in place of a code of the authenticator. They are only ever shown once.
+    pub recovery_codes: Vec<String>,
+}
+
+/// Returned instead of a session by the password step of a login, if the user has to enter a code as well.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoginChallenge {
+    /// Send this to `/api/v1/sessions/two_factor` together with the code to complete the login.
+    pub challenge: String,
+    /// Set if the user has to enrol an authenticator before they can log in.
+    /// The code completing the login confirms the enrolment.
+    pub enrollment: Option<TotpEnrollment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CompleteLoginRequest {
+    pub challenge: String,
+    /// The current code of the user's authenticator, or one of their recovery codes.
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorLoginResponse {
+    #[serde(flatten)]
+    #[schema(value_type = schemas::LoginResponse)]
+    pub login: LoginResponse,
+    /// Only set if the login has confirmed the enrolment of an authenticator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+/// Starts setting up two-factor authentication, the enrolment has to be confirmed with a code of the authenticator.
+/// Starting over replaces the secret of an unconfirmed enrolment.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/two_factor",
+    tag = "accounts",
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, body = TotpEnrollment),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 409, description = "Two-factor authentication is already enabled.", body = ApiErrorResponse),
+    )
+)]
+pub async fn enroll_two_factor(
+    State(state): State<ServerState>,
+    Json(user_session): Json<UserSession>,
+) -> Result<Json<TotpEnrollment>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &user_session)?;
+
+            if find_user_totp(pg_connection, user_session.user_id)?
+                .is_some_and(|user_totp| user_totp.confirmed_at.is_some())
+            {
+                return Err(ApiError::TwoFactorAlreadyEnabled);
+            }
+
+            let user_account = find_user_account(pg_connection, user_session.user_id)?;
+            let user_totp = enroll_authenticator(pg_connection, user_account.id)?;
+
+            Ok(Json(enrollment_of(&user_totp, &user_account.username)))
+        })
+        .await
+}
+
+/// Confirms the enrolment with a code of the authenticator, and issues the recovery codes of the user.
+/// Logins require a code from here on.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/two_factor/confirm",
+    tag = "accounts",
+    request_body = TwoFactorCodeRequest,
+    responses(
+        (status = 200, body = RecoveryCodesResponse),
+        (status = 401, description = "The session or the code is invalid.", body = ApiErrorResponse),
+        (status = 409, description = "There is no enrolment to confirm.", body = ApiErrorResponse),
+        (status = 429, description = "Too many incorrect codes, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn confirm_two_factor(
+    State(state): State<ServerState>,
+    Json(request): Json<TwoFactorCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, ApiError> {
+    let auth_limits = state.auth_limits.clone();
+
+    state
+        .run_query(move |pg_connection| {
+            pg_connection.transaction(|pg_connection| {
+                verify_user_session(pg_connection, &request.user_session)?;
+
+                let user_totp = find_user_totp(pg_connection, request.user_session.user_id)?
+                    .ok_or(ApiError::TwoFactorNotEnabled)?;
+
+                if user_totp.confirmed_at.is_some() {
+                    return Err(ApiError::TwoFactorAlreadyEnabled);
+                }
+
+                let user_account = find_user_account(pg_connection, user_totp.user_id)?;
+
+                redeem_code_throttled(
+                    pg_connection,
+                    &auth_limits.failed_logins,
+                    &user_account.username,
+                    &user_totp,
+                    &request.code,
+                )?;
+
+                confirm_enrollment(pg_connection, user_totp.user_id)?;
+
+                Ok(Json(RecoveryCodesResponse {
+                    recovery_codes: replace_recovery_codes(pg_connection, user_totp.user_id)?,
+                }))
+            })
+        })
+        .await
+}
+
+/// Issues new recovery codes, the previous ones stop working.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/two_factor/recovery_codes",
+    tag = "accounts",
+    request_body = TwoFactorCodeRequest,
+    responses(
+        (status = 200, body = RecoveryCodesResponse),
+        (status = 401, description = "The session or the code is invalid.", body = ApiErrorResponse),
+        (status = 409, description = "Two-factor authentication is not enabled.", body = ApiErrorResponse),
+        (status = 429, description = "Too many incorrect codes, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn regenerate_recovery_codes(
+    State(state): State<ServerState>,
+    Json(request): Json<TwoFactorCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, ApiError> {
+    let auth_limits = state.auth_limits.clone();
+
+    state
+        .run_query(move |pg_connection| {
+            pg_connection.transaction(|pg_connection| {
+                verify_user_session(pg_connection, &request.user_session)?;
+
+                let user_totp = find_user_totp(pg_connection, request.user_session.user_id)?
+                    .filter(|user_totp| user_totp.confirmed_at.is_some())
+                    .ok_or(ApiError::TwoFactorNotEnabled)?;
+
+                let user_account = find_user_account(pg_connection, user_totp.user_id)?;
+
+                redeem_code_throttled(
+                    pg_connection,
+                    &auth_limits.failed_logins,
+                    &user_account.username,
+                    &user_totp,
+                    &request.code,
+                )?;
+
+                Ok(Json(RecoveryCodesResponse {
+                    recovery_codes: replace_recovery_codes(pg_connection, user_totp.user_id)?,
+                }))
+            })
+        })
+        .await
+}
+
+/// Turns off two-factor authentication, and deletes the recovery codes of the user.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/two_factor",
+    tag = "accounts",
+    request_body = TwoFactorCodeRequest,
+    responses(
+        (status = 204, description = "Two-factor authentication has been turned off."),
+        (status = 401, description = "The session or the code is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is an admin, and the server requires admins to use two-factor authentication.", body = ApiErrorResponse),
+        (status = 409, description = "Two-factor authentication is not enabled.", body = ApiErrorResponse),
+        (status = 429, description = "Too many incorrect codes, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn disable_two_factor(
+    State(state): State<ServerState>,
+    Json(request): Json<TwoFactorCodeRequest>,
+) -> Result<StatusCode, ApiError> {
+    let auth_limits = state.auth_limits.clone();
+    let require_admin_two_factor = state.config.require_admin_two_factor;
+
+    state
+        .run_query(move |pg_connection| {
+            pg_connection.transaction(|pg_connection| {
+                verify_user_session(pg_connection, &request.user_session)?;
+
+                let user_totp = find_user_totp(pg_connection, request.user_session.user_id)?
+                    .filter(|user_totp| user_totp.confirmed_at.is_some())
+                    .ok_or(ApiError::TwoFactorNotEnabled)?;
+
+                let user_account = find_user_account(pg_connection, user_totp.user_id)?;
+
+                if user_account.is_admin && require_admin_two_factor {
+                    return Err(ApiError::TwoFactorRequired);
+                }
+
+                redeem_code_throttled(
+                    pg_connection,
+                    &auth_limits.failed_logins,
+                    &user_account.username,
+                    &user_totp,
+                    &request.code,
+                )?;
+
+                info_span!("db", query = "delete_user_totp")
+                    .in_scope(|| {
+                        diesel::delete(user_totp::table.find(user_totp.user_id))
+                            .execute(pg_connection)
+                    })
+                    .context("An error occured while deleting the user's authenticator")?;
+
+                info_span!("db", query = "delete_recovery_codes")
+                    .in_scope(|| {
+                        diesel::delete(
+                            recovery_codes::table
+                                .filter(recovery_codes::user_id.eq(user_totp.user_id)),
+                        )
+                        .execute(pg_connection)
+                    })
+                    .context("An error occured while deleting the user's recovery codes")?;
+
+                Ok(StatusCode::NO_CONTENT)
+            })
+        })
+        .await
+}
+
+/// Completes a login with the challenge returned by the password step, and a code.
+/// Incorrect codes count as failed logins of the user.
+#[utoipa::path(
+    post,
+    path = "/api/v1/sessions/two_factor",
+    tag = "accounts",
+    request_body = CompleteLoginRequest,
+    responses(
+        (status = 200, body = TwoFactorLoginResponse),
+        (status = 400, description = "The challenge is invalid or has expired, log in with the password again.", body = ApiErrorResponse),
+        (status = 401, description = "The code is incorrect.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn complete_login(
+    State(state): State<ServerState>,
+    Json(request): Json<CompleteLoginRequest>,
+) -> Result<Json<TwoFactorLoginResponse>, ApiError> {
+    let challenge = SignedToken::parse(&request.challenge).ok_or(ApiError::InvalidToken)?;
+    let link_signer = state.link_signer.clone();
+    let auth_limits = state.auth_limits.clone();
+
+    state
+        .run_query(move |pg_connection| {
+            pg_connection.transaction(|pg_connection| {
+                let user_totp = find_user_totp(pg_connection, challenge.user_id)?
+                    .ok_or(ApiError::InvalidToken)?;
+
+                if !link_signer.verify(
+                    &challenge,
+                    LOGIN_CHALLENGE_PURPOSE,
+                    &challenge_subject(&user_totp),
+                ) {
+                    return Err(ApiError::InvalidToken);
+                }
+
+                let user_account = find_user_account(pg_connection, user_totp.user_id)?;
+
+                record_user_id(user_account.id);
+
+                redeem_code_throttled(
+                    pg_connection,
+                    &auth_limits.failed_logins,
+                    &user_account.username,
+                    &user_totp,
+                    &request.code,
+                )?;
+
+                // The challenge of an admin made to enrol while logging in, the code has just proven the enrolment
+                let recovery_codes = if user_totp.confirmed_at.is_none() {
+                    confirm_enrollment(pg_connection, user_totp.user_id)?;
+
+                    Some(replace_recovery_codes(pg_connection, user_totp.user_id)?)
+                } else {
+                    None
+                };
+
+                Ok(Json(TwoFactorLoginResponse {
+                    login: issue_session(pg_connection, &user_account)?,
+                    recovery_codes,
+                }))
+            })
+        })
+        .await
+}
+
+pub fn find_user_totp(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+) -> Result<Option<UserTotpEntry>, ApiError> {
+    Ok(info_span!("db", query = "find_user_totp")
+        .in_scope(|| {
+            user_totp::table
+                .find(user_id)
+                .select(UserTotpEntry::as_select())
+                .first(pg_connection)
+                .optional()
+        })
+        .context("An error occured while fetching the user's authenticator")?)
+}
+
+/// Stores a new, unconfirmed secret for the user, replacing their previous one.
+pub fn enroll_authenticator(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+) -> Result<UserTotpEntry, ApiError> {
+    let mut secret = vec![0_u8; TOTP_SECRET_LENGTH];
+
+    rng().fill(&mut secret[..]);
+
+    Ok(info_span!("db", query = "upsert_user_totp")
+        .in_scope(|| {
+            diesel::insert_into(user_totp::table)
+                .values(&NewUserTotp { user_id, secret })
+                .on_conflict(user_totp::user_id)
+                .do_update()
+                .set((
+                    user_totp::secret.eq(excluded(user_totp::secret)),
+                    user_totp::confirmed_at.eq(None::<chrono::NaiveDateTime>),
+                    user_totp::last_used_step.eq(None::<i64>),
+                ))
+                .returning(UserTotpEntry::as_returning())
+                .get_result(pg_connection)
+        })
+        .context("An error occured while storing the user's authenticator")?)
+}
+
+pub fn enrollment_of(user_totp: &UserTotpEntry, username: &str) -> TotpEnrollment {
+    let totp = totp_of(user_totp, username);
+
+    TotpEnrollment {
+        secret: totp.get_secret_base32(),
+        otpauth_url: totp.get_url(),
+    }
+}
+
+/// Issues the challenge completing the login of the user at [`complete_login`].
+/// The challenge is bound to the secret and the last code used, so that it stops working once it has been completed, or the secret has been replaced.
+pub fn issue_login_challenge(
+    link_signer: &LinkSigner,
+    user_totp: &UserTotpEntry,
+    enrollment: Option<TotpEnrollment>,
+) -> LoginChallenge {
+    LoginChallenge {
+        challenge: link_signer.sign(
+            LOGIN_CHALLENGE_PURPOSE,
+            user_totp.user_id,
+            &challenge_subject(user_totp),
+            Utc::now() + LOGIN_CHALLENGE_LIFETIME,
+        ),
+        enrollment,
+    }
+}
+
+fn challenge_subject(user_totp: &UserTotpEntry) -> String {
+    format!(
+        "{}:{}",
+        hex::encode(&user_totp.secret),
+        user_totp.last_used_step.unwrap_or(-1)
+    )
+}
+
+fn totp_of(user_totp: &UserTotpEntry, username: &str) -> TOTP {
+    // The secrets are always generated with the recommended length, and the issuer has no colon in it
+    TOTP::new_unchecked(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP,
+        user_totp.secret.clone(),
+        Some(TOTP_ISSUER.to_string()),
+        username.to_string(),
+    )
+}
+
+fn confirm_enrollment(pg_connection: &mut PgConnection, user_id: i32) -> Result<(), ApiError> {
+    info_span!("db", query = "confirm_user_totp")
+        .in_scope(|| {
+            diesel::update(user_totp::table.find(user_id))
+                .set(user_totp::confirmed_at.eq(Utc::now().naive_utc()))
+                .execute(pg_connection)
+        })
+        .context("An error occured while confirming the user's authenticator")?;
+
+    Ok(())
+}
+
+/// Like [`redeem_code`], but every incorrect code counts as a failed login of the user, so that the codes can not be guessed.
+fn redeem_code_throttled(
+    pg_connection: &mut PgConnection,
+    failed_logins: &LoginThrottle,
+    username: &str,
+    user_totp: &UserTotpEntry,
+    code: &str,
+) -> Result<(), ApiError> {
+    if let Err(retry_after) = failed_logins.check(username) {
+        counter!(RATE_LIMITED_REQUESTS_TOTAL, "limit" => "login").increment(1);
+
+        return Err(ApiError::RateLimited { retry_after });
+    }
+
+    if !redeem_code(pg_connection, user_totp, code)? {
+        failed_logins.record_failure(username);
+
+        return Err(ApiError::InvalidTwoFactorCode);
+    }
+
+    failed_logins.record_success(username);
+
+    Ok(())
+}
+
+/// Checks the code against the authenticator and the unused recovery codes of the user, and uses it up if it is valid.
+fn redeem_code(
+    pg_connection: &mut PgConnection,
+    user_totp: &UserTotpEntry,
+    code: &str,
+) -> Result<bool, ApiError> {
+    let code = normalize_code(code);
+
+    if code.len() == TOTP_DIGITS && code.bytes().all(|byte| byte.is_ascii_digit()) {
+        let current_step = Utc::now().timestamp() as u64 / TOTP_STEP;
+        let totp = totp_of(user_totp, "");
+
+        // The neighbouring steps are accepted too, to tolerate the clock of the authenticator drifting
+        let Some(step) = (current_step - 1..=current_step + 1)
+            .filter(|&step| {
+                user_totp
+                    .last_used_step
+                    .is_none_or(|last_used_step| step as i64 > last_used_step)
+            })
+            .find(|&step| totp.check(&code, step * TOTP_STEP))
+        else {
+            return Ok(false);
+        };
+
+        // Only advances if no concurrent request has used this (or a later) code in the meantime
+        let updated = info_span!("db", query = "use_totp_step")
+            .in_scope(|| {
+                diesel::update(
+                    user_totp::table.find(user_totp.user_id).filter(
+                        user_totp::last_used_step
+                            .is_null()
+                            .or(user_totp::last_used_step.lt(step as i64)),
+                    ),
+                )
+                .set(user_totp::last_used_step.eq(step as i64))
+                .execute(pg_connection)
+            })
+            .context("An error occured while recording the user's last used code")?;
+
+        return Ok(updated == 1);
+    }
+
+    let used = info_span!("db", query = "use_recovery_code")
+        .in_scope(|| {
+            diesel::update(
+                recovery_codes::table
+                    .filter(recovery_codes::user_id.eq(user_totp.user_id))
+                    .filter(recovery_codes::code_hash.eq(Sha256::digest(&code).to_vec()))
+                    .filter(recovery_codes::used_at.is_null()),
+            )
+            .set(recovery_codes::used_at.eq(Utc::now().naive_utc()))
+            .execute(pg_connection)
+        })
+        .context("An error occured while using a recovery code")?;
+
+    Ok(used > 0)
+}
+
+/// Replaces the recovery codes of the user with new ones, returns them formatted for display.
+fn replace_recovery_codes(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+) -> Result<Vec<String>, ApiError> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    info_span!("db", query = "delete_recovery_codes")
+        .in_scope(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                .execute(pg_connection)
+        })
+        .context("An error occured while deleting the user's recovery codes")?;
+
+    info_span!("db", query = "insert_recovery_codes")
+        .in_scope(|| {
+            diesel::insert_into(recovery_codes::table)
+                .values(
+                    codes
+                        .iter()
+                        .map(|code| NewRecoveryCode {
+                            user_id,
+                            code_hash: Sha256::digest(normalize_code(code)).to_vec(),
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(pg_connection)
+        })
+        .context("An error occured while storing the user's recovery codes")?;
+
+    Ok(codes)
+}
+
+/// Ten random characters, split in two halves so that they are easier to type (e.g. `k7pqz-m2xwa`).
+fn generate_recovery_code() -> String {
+    let alphabet = Uniform::new(0, RECOVERY_CODE_ALPHABET.len()).unwrap();
+
+    let mut code: String = rng()
+        .sample_iter(alphabet)
+        .take(10)
+        .map(|index| char::from(RECOVERY_CODE_ALPHABET[index]))
+        .collect();
+
+    code.insert(5, '-');
+
+    code
+}
+
+/// Codes are accepted regardless of case, separators and whitespace.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|char| char.is_ascii_alphanumeric())
+        .map(|char| char.to_ascii_lowercase())
+        .collect()
+}
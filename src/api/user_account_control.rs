@@ -1,3 +1,13 @@
+use crate::api::account_settings::validate_username;
+use crate::api::email_verification::{check_email_address, send_verification_email};
+use crate::api::error::{ApiError, ApiErrorResponse};
+use crate::api::openapi::schemas;
+use crate::api::pinned_messages::{
+    ChatroomWithPins, KnownChatroomsResponse, pinned_message_counts,
+};
+use crate::api::two_factor::{
+    LoginChallenge, enroll_authenticator, enrollment_of, find_user_totp, issue_login_challenge,
+};
 use crate::api::user_account_control::users::dsl::users;
 use crate::models::{
     ChatroomEntry, NewChatroom, NewUserAccount, NewUserSession, UserAccountEntry, UserSessionEntry,
@@ -9,450 +19,560 @@ use crate::schema::user_signin_tokens::{session_token, user_id};
 use crate::schema::users::{chatrooms_joined, id, passw, username};
 use crate::{
     ServerState,
+    logging::record_user_id,
     schema::{self, *},
 };
-use axum::{Json, extract::State, http::StatusCode};
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
 use diesel::dsl::count_star;
 use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
-use diesel::{ExpressionMethods, RunQueryDsl, SelectableHelper, delete};
-use log::error;
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, RunQueryDsl, SelectableHelper, delete,
+};
 use rand::distr::Uniform;
 use rand::{Rng, rng};
+use tracing::{debug, error, info_span};
 use whatssock_lib::client::{LoginRequest, RegisterRequest, UserInformation};
 use whatssock_lib::server::{LoginResponse, LogoutResponse};
 use whatssock_lib::{
-    ChatMessage, CreateChatroomRequest, FetchChatroomResponse, FetchKnownChatroomResponse,
-    FetchKnownChatrooms, FetchUnknownChatroom, UserSession,
+    CreateChatroomRequest, FetchChatroomResponse, FetchKnownChatrooms, FetchUnknownChatroom,
+    UserSession,
 };
 
+/// Logs the user in, the sessions of the user on other devices stay valid.
+/// Users with two-factor authentication get a challenge instead, which they complete with a code at `/api/v1/sessions/two_factor`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/sessions",
+    tag = "accounts",
+    request_body = schemas::LoginRequest,
+    responses(
+        (status = 200, body = schemas::LoginResponse),
+        (status = 202, description = "The password is correct, but the user has to enter a code of their authenticator as well.", body = LoginChallenge),
+        (status = 401, description = "The username or the password is incorrect.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
 pub async fn fetch_login(
     State(state): State<ServerState>,
     Json(information): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, StatusCode> {
-    let mut pg_connection = state.pg_pool.get().map_err(|err| {
-        error!(
-            "An error occured while fetching login information from db: {}",
-            err
-        );
-
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let user_account = users
-        .filter(username.eq(information.username.clone()))
-        .filter(passw.eq(information.password))
-        .select(UserAccountEntry::as_select())
-        .get_result(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while searching for the user's account: {}",
-                err
-            );
-
-            StatusCode::NOT_FOUND
-        })?;
+) -> Result<LoginOutcome, ApiError> {
+    let link_signer = state.link_signer.clone();
+    let require_admin_two_factor = state.config.require_admin_two_factor;
+
+    state
+        .run_query(move |pg_connection| {
+            let user_account = info_span!("db", query = "find_user_by_credentials")
+                .in_scope(|| {
+                    users
+                        .filter(username.eq(information.username.clone()))
+                        .filter(passw.eq(information.password))
+                        .select(UserAccountEntry::as_select())
+                        .get_result(pg_connection)
+                        .optional()
+                })
+                .context("An error occured while searching for the user's account")?
+                .ok_or(ApiError::InvalidCredentials)?;
+
+            record_user_id(user_account.id);
+
+            match find_user_totp(pg_connection, user_account.id)? {
+                Some(user_totp) if user_totp.confirmed_at.is_some() => Ok(LoginOutcome::Challenge(
+                    issue_login_challenge(&link_signer, &user_totp, None),
+                )),
+                // Admins who have not set up two-factor authentication yet have to do it before they are let in
+                _ if user_account.is_admin && require_admin_two_factor => {
+                    let user_totp = enroll_authenticator(pg_connection, user_account.id)?;
+                    let enrollment = enrollment_of(&user_totp, &user_account.username);
+
+                    Ok(LoginOutcome::Challenge(issue_login_challenge(
+                        &link_signer,
+                        &user_totp,
+                        Some(enrollment),
+                    )))
+                }
+                _ => Ok(LoginOutcome::Session(issue_session(
+                    pg_connection,
+                    &user_account,
+                )?)),
+            }
+        })
+        .await
+}
+
+/// The answer to the password step of a login.
+pub enum LoginOutcome {
+    /// The user is logged in.
+    Session(LoginResponse),
+    /// The user has to complete the login with a code, see [`crate::api::two_factor::complete_login`].
+    Challenge(LoginChallenge),
+}
 
+impl IntoResponse for LoginOutcome {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Session(login_response) => Json(login_response).into_response(),
+            Self::Challenge(challenge) => (StatusCode::ACCEPTED, Json(challenge)).into_response(),
+        }
+    }
+}
+
+/// Issues a new session token for the user, next to the sessions they already have on other devices.
+pub fn issue_session(
+    pg_connection: &mut PgConnection,
+    user_account: &UserAccountEntry,
+) -> Result<LoginResponse, ApiError> {
     // Issue a new session token for future logins
     let session_cookie_token = generate_session_token();
 
-    let user_session_count = user_signin_tokens
-        .filter(user_id.eq(user_account.id))
-        .select(count_star())
-        .first::<i64>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while searching for the user's session token: {}",
-                err
-            );
-
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    // Check if there are any existing user sessions
-    // If there arent this means some sort of issue has occured, thus the session has been invalidated or deleted.
-    if user_session_count != 0 {
-        // Search up a session token for the user, if it exists update it
-        diesel::update(user_signin_tokens)
-            .filter(user_id.eq(user_account.id))
-            .set(&NewUserSession {
-                user_id: user_account.id,
-                session_token: session_cookie_token.clone().to_vec(),
-            })
-            .get_result::<UserSessionEntry>(&mut pg_connection)
-            .map_err(|err| {
-                error!(
-                    "An error occured while searching for the user's session token: {}",
-                    err
-                );
-
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-    } else {
-        diesel::insert_into(user_signin_tokens)
-            .values(&NewUserSession {
-                user_id: user_account.id,
-                session_token: session_cookie_token.clone().to_vec(),
-            })
-            .get_result::<UserSessionEntry>(&mut pg_connection)
-            .map_err(|err| {
-                error!(
-                    "An error occured while fetching login information from db: {}",
-                    err
-                );
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-    }
+    info_span!("db", query = "insert_user_session")
+        .in_scope(|| {
+            diesel::insert_into(user_signin_tokens)
+                .values(&NewUserSession {
+                    user_id: user_account.id,
+                    session_token: session_cookie_token.clone().to_vec(),
+                })
+                .get_result::<UserSessionEntry>(pg_connection)
+        })
+        .context("An error occured while storing the user's new session token")?;
 
-    Ok(Json(LoginResponse {
+    Ok(LoginResponse {
         user_id: user_account.id,
         session_token: session_cookie_token,
-        chatrooms_joined: user_account.chatrooms_joined,
-    }))
+        chatrooms_joined: user_account.chatrooms_joined.clone(),
+    })
 }
 
+/// Creates a new account and logs it in.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users",
+    tag = "accounts",
+    request_body = schemas::RegisterRequest,
+    responses(
+        (status = 200, body = schemas::LoginResponse),
+        (status = 400, description = "The username is empty or too long, or the email address is invalid.", body = ApiErrorResponse),
+        (status = 409, description = "The username or the email address is already in use.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
 pub async fn register_user(
     State(state): State<ServerState>,
     Json(information): Json<RegisterRequest>,
-) -> Result<Json<LoginResponse>, StatusCode> {
-    let mut pg_connection = state.pg_pool.get().map_err(|err| {
-        error!(
-            "An error occured while fetching login information from db: {}",
-            err
-        );
-
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let user_count = users
-        .filter(username.eq(information.username.clone()))
-        .select(count_star())
-        .first::<i64>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while fetching login information from db: {}",
-                err
-            );
-            StatusCode::REQUEST_TIMEOUT
-        })?;
-
-    if user_count != 0 {
-        return Err(StatusCode::FOUND);
-    }
-
-    // Insert the user's register information into the DB
-    let user_account = diesel::insert_into(users)
-        .values(&NewUserAccount {
-            username: information.username.clone(),
-            passw: information.password,
-            chatrooms_joined: vec![],
-            email: information.email,
+) -> Result<Json<LoginResponse>, ApiError> {
+    check_email_address(&information.email)?;
+    let new_username = validate_username(&information.username)?;
+
+    let email = information.email.clone();
+
+    let login_response = state
+        .run_query(move |pg_connection| {
+            // Insert the user's register information into the DB
+            let user_account = info_span!("db", query = "insert_user")
+                .in_scope(|| {
+                    diesel::insert_into(users)
+                        .values(&NewUserAccount {
+                            username: new_username,
+                            passw: information.password,
+                            chatrooms_joined: vec![],
+                            email: information.email,
+                        })
+                        .get_result::<UserAccountEntry>(pg_connection)
+                })
+                // The unique indexes on the username and the email turn a taken one into a conflict
+                .map_err(|err| {
+                    ApiError::from_query_error(
+                        err,
+                        "An error occured while inserting the new user account",
+                    )
+                })?;
+
+            record_user_id(user_account.id);
+
+            // Issue a new session token for future logins
+            let session_cookie_token = generate_session_token();
+
+            // Store the session token in the db, there is no way of having another session token for this user as we have just created it.
+            info_span!("db", query = "insert_user_session")
+                .in_scope(|| {
+                    diesel::insert_into(user_signin_tokens)
+                        .values(&NewUserSession {
+                            user_id: user_account.id,
+                            session_token: session_cookie_token.clone().to_vec(),
+                        })
+                        .get_result::<UserSessionEntry>(pg_connection)
+                })
+                .context("An error occured while storing the new user's session token")?;
+
+            Ok(Json(LoginResponse {
+                user_id: user_account.id,
+                session_token: session_cookie_token,
+                chatrooms_joined: user_account.chatrooms_joined,
+            }))
         })
-        .get_result::<UserAccountEntry>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while fetching login information from db: {}",
-                err
-            );
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .await?;
 
-    // Issue a new session token for future logins
-    let session_cookie_token = generate_session_token();
+    // The account is usable without a verified email address, the user can ask for a new email if this one gets lost
+    if let Err(err) = send_verification_email(&state, login_response.user_id, email).await {
+        error!("An error occured while sending the verification email: {err:#}");
+    }
 
-    // Store the session token in the db, there is no way of having another session token for this user as we have just created it.
-    diesel::insert_into(user_signin_tokens)
-        .values(&NewUserSession {
-            user_id: user_account.id,
-            session_token: session_cookie_token.clone().to_vec(),
-        })
-        .get_result::<UserSessionEntry>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while fetching login information from db: {}",
-                err
-            );
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    Ok(Json(LoginResponse {
-        user_id: user_account.id,
-        session_token: session_cookie_token,
-        chatrooms_joined: user_account.chatrooms_joined,
-    }))
+    Ok(login_response)
 }
 
+/// Returns the account information belonging to a session.
+#[utoipa::path(
+    post,
+    path = "/api/v1/sessions/current",
+    tag = "accounts",
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, body = schemas::UserInformation),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
 pub async fn fetch_session_token(
     State(state): State<ServerState>,
     Json(session_cookie): Json<UserSession>,
-) -> Result<Json<UserInformation>, StatusCode> {
-    // Get a db connection from the pool
-    let mut pg_connection = state.pg_pool.get().map_err(|err| {
-        error!(
-            "An error occured while fetching login information from db: {}",
-            err
-        );
-
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    // Get how many fields are equal (This must be one, or zero.)
-    let count = user_signin_tokens
-        .filter(user_id.eq(session_cookie.user_id))
-        .filter(session_token.eq(session_cookie.session_token))
-        .select(count_star())
-        .first::<i64>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while fetching user session information from db: {}",
-                err
-            );
-            StatusCode::REQUEST_TIMEOUT
-        })?;
-
-    // If the user token is not found return an error indication that it is false.
-    if count != 1 {
-        return Err(StatusCode::NOT_ACCEPTABLE);
-    }
-
-    let user_account = users
-        .filter(id.eq(session_cookie.user_id))
-        .select(UserAccountEntry::as_select())
-        .first::<UserAccountEntry>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while fetching user session information from db: {}",
-                err
-            );
-            StatusCode::REQUEST_TIMEOUT
-        })?;
-
-    Ok(Json(UserInformation {
-        username: user_account.username,
-        chatrooms_joined: user_account.chatrooms_joined,
-    }))
+) -> Result<Json<UserInformation>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &session_cookie)?;
+
+            let user_account = info_span!("db", query = "find_user_account")
+                .in_scope(|| {
+                    users
+                        .filter(id.eq(session_cookie.user_id))
+                        .select(UserAccountEntry::as_select())
+                        .first::<UserAccountEntry>(pg_connection)
+                })
+                .context("An error occured while fetching the account of the session's user")?;
+
+            Ok(Json(UserInformation {
+                username: user_account.username,
+                chatrooms_joined: user_account.chatrooms_joined,
+            }))
+        })
+        .await
 }
 
+/// Revokes the session, the other sessions of the user stay valid.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/sessions",
+    tag = "accounts",
+    request_body = schemas::UserSession,
+    responses(
+        (status = 200, body = schemas::LogoutResponse),
+    )
+)]
 pub async fn handle_logout_request(
     State(state): State<ServerState>,
     Json(session_cookie): Json<UserSession>,
-) -> Result<Json<LogoutResponse>, StatusCode> {
-    // Get a db connection from the pool
-    let mut pg_connection = state.pg_pool.get().map_err(|err| {
-        error!(
-            "An error occured while fetching login information from db: {}",
-            err
-        );
-
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    match delete(user_signin_tokens.filter(session_token.eq(session_cookie.session_token)))
-        .execute(&mut pg_connection)
-    {
-        Ok(r_affected) => {
-            dbg!(r_affected);
-        }
-        Err(err) => {
-            error!("{err}");
-
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    }
-
-    Ok(Json(LogoutResponse {}))
+) -> Result<Json<LogoutResponse>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            let r_affected = info_span!("db", query = "delete_user_session")
+                .in_scope(|| {
+                    delete(
+                        user_signin_tokens
+                            .filter(user_id.eq(session_cookie.user_id))
+                            .filter(session_token.eq(session_cookie.session_token)),
+                    )
+                    .execute(pg_connection)
+                })
+                .context("An error occured while deleting the user's session token")?;
+
+            debug!("Logout removed {r_affected} session token(s)");
+
+            Ok(Json(LogoutResponse {}))
+        })
+        .await
 }
 
+/// Looks up a chatroom by its public id.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/lookup",
+    tag = "chatrooms",
+    request_body = schemas::FetchUnknownChatroom,
+    responses(
+        (status = 200, body = schemas::FetchChatroomResponse),
+        (status = 404, description = "The chatroom does not exist, or its password is incorrect.", body = ApiErrorResponse),
+        (status = 429, description = "Too many attempts, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
 pub async fn fetch_unknown_chatroom(
     State(state): State<ServerState>,
     Json(chatroom_request): Json<FetchUnknownChatroom>,
-) -> Result<Json<FetchChatroomResponse>, StatusCode> {
-    // Get a db connection from the pool
-    let mut pg_connection = state.pg_pool.get().map_err(|err| {
-        error!(
-            "An error occured while fetching login information from db: {}",
-            err
-        );
-
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let chatrooms_filter = chatrooms.filter(chatroom_id.eq(chatroom_request.chatroom_id));
-
-    let query_result: ChatroomEntry = if let Some(password) = chatroom_request.password {
-        let password_filter = chatrooms_filter.filter(chatroom_password.eq(password));
-
-        password_filter
-            .select(ChatroomEntry::as_select())
-            .first(&mut pg_connection)
-            .map_err(|err| {
-                error!("An error occured while fetching chatrooms from db: {}", err);
-
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?
-    } else {
-        chatrooms_filter
-            .select(ChatroomEntry::as_select())
-            .first(&mut pg_connection)
-            .map_err(|err| {
-                error!("An error occured while fetching chatrooms from db: {}", err);
-
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?
-    };
-
-    Ok(Json(FetchChatroomResponse {
-        chatroom_uid: query_result.id,
-        chatroom_id: query_result.chatroom_id,
-        chatroom_name: query_result.chatroom_name,
-        participants: query_result.participants,
-        is_direct_message: query_result.is_direct_message,
-        last_message_id: query_result.last_message_id,
-    }))
+) -> Result<Json<FetchChatroomResponse>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            // Direct messages are only reachable by their two participants
+            let chatrooms_filter = chatrooms
+                .filter(chatroom_id.eq(chatroom_request.chatroom_id))
+                .filter(schema::chatrooms::is_direct_message.eq(false));
+
+            let query_result: Option<ChatroomEntry> =
+                if let Some(password) = chatroom_request.password {
+                    let password_filter = chatrooms_filter.filter(chatroom_password.eq(password));
+
+                    info_span!("db", query = "find_chatroom_with_password").in_scope(|| {
+                        password_filter
+                            .select(ChatroomEntry::as_select())
+                            .first(pg_connection)
+                            .optional()
+                    })
+                } else {
+                    info_span!("db", query = "find_chatroom").in_scope(|| {
+                        chatrooms_filter
+                            .select(ChatroomEntry::as_select())
+                            .first(pg_connection)
+                            .optional()
+                    })
+                }
+                .context("An error occured while fetching chatrooms from db")?;
+
+            let query_result = query_result.ok_or(ApiError::ChatroomNotFound)?;
+
+            Ok(Json(FetchChatroomResponse {
+                chatroom_uid: query_result.id,
+                chatroom_id: query_result.chatroom_id,
+                chatroom_name: query_result.chatroom_name,
+                participants: query_result.participants,
+                is_direct_message: query_result.is_direct_message,
+                last_message_id: query_result.last_message_id,
+            }))
+        })
+        .await
 }
 
+/// Fetches chatrooms the user is a participant of, with the number of their pinned messages.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms/known",
+    tag = "chatrooms",
+    request_body = schemas::FetchKnownChatrooms,
+    responses(
+        (status = 200, body = KnownChatroomsResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "The user is not a participant of one of the chatrooms.", body = ApiErrorResponse),
+        (status = 404, description = "One of the chatrooms does not exist.", body = ApiErrorResponse),
+    )
+)]
 pub async fn fetch_known_chatrooms(
     State(state): State<ServerState>,
     Json(bulk_chatrooms_request): Json<FetchKnownChatrooms>,
-) -> Result<Json<FetchKnownChatroomResponse>, StatusCode> {
-    // Get a db connection from the pool
-    let mut pg_connection = state.pg_pool.get().map_err(|err| {
-        error!(
-            "An error occured while fetching login information from db: {}",
-            err
-        );
-
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    // Verify user session validness
-    let matching_user_tokens = user_signin_tokens
-        .filter(schema::user_signin_tokens::user_id.eq(bulk_chatrooms_request.user_session.user_id))
-        .filter(
-            schema::user_signin_tokens::session_token
-                .eq(bulk_chatrooms_request.user_session.session_token),
-        )
-        .select(count_star())
-        .get_result::<i64>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while verifying login information from db: {}",
-                err
-            );
-
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    if matching_user_tokens != 1 {
-        return Err(StatusCode::FORBIDDEN);
-    }
-
-    let mut verified_chatrooms_reponses: Vec<FetchChatroomResponse> = Vec::new();
+) -> Result<Json<KnownChatroomsResponse>, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            // Verify user session validness
+            verify_user_session(pg_connection, &bulk_chatrooms_request.user_session)?;
+
+            let mut verified_chatrooms_reponses: Vec<FetchChatroomResponse> = Vec::new();
+
+            // Verify that the user is indeed present in the chatroom
+            for chatroom_request in bulk_chatrooms_request.chatroom_uids {
+                let chatroom_entry = info_span!("db", query = "find_chatroom")
+                    .in_scope(|| {
+                        chatrooms
+                            .filter(schema::chatrooms::id.eq(chatroom_request))
+                            .get_result::<ChatroomEntry>(pg_connection)
+                            .optional()
+                    })
+                    .with_context(|| {
+                        format!(
+                            "An error occured while fetching chatroom {chatroom_request} from db"
+                        )
+                    })?
+                    .ok_or(ApiError::ChatroomNotFound)?;
+
+                let is_user_present = chatroom_entry
+                    .participants
+                    .contains(&Some(bulk_chatrooms_request.user_session.user_id));
+
+                // If the user is not present in the participants list, return an error
+                if !is_user_present {
+                    return Err(ApiError::NotChatroomMember);
+                }
+
+                verified_chatrooms_reponses.push(FetchChatroomResponse {
+                    chatroom_uid: chatroom_entry.id,
+                    chatroom_id: chatroom_entry.chatroom_id,
+                    chatroom_name: chatroom_entry.chatroom_name,
+                    participants: chatroom_entry.participants,
+                    is_direct_message: chatroom_entry.is_direct_message,
+                    last_message_id: chatroom_entry.last_message_id,
+                });
+            }
+
+            let chatroom_uids: Vec<i32> = verified_chatrooms_reponses
+                .iter()
+                .map(|chatroom| chatroom.chatroom_uid)
+                .collect();
+            let pinned_counts = pinned_message_counts(pg_connection, &chatroom_uids)?;
+
+            Ok(Json(KnownChatroomsResponse {
+                chatrooms: verified_chatrooms_reponses
+                    .into_iter()
+                    .map(|chatroom| ChatroomWithPins {
+                        pinned_message_count: pinned_counts
+                            .get(&chatroom.chatroom_uid)
+                            .copied()
+                            .unwrap_or(0),
+                        chatroom,
+                    })
+                    .collect(),
+            }))
+        })
+        .await
+}
 
-    // Verify that the user is indeed present in the chatroom
-    for chatroom_request in bulk_chatrooms_request.chatroom_uids {
-        let chatroom_entry = chatrooms
-            .filter(schema::chatrooms::id.eq(chatroom_request))
-            .get_result::<ChatroomEntry>(&mut pg_connection)
-            .map_err(|err| {
-                error!("An error occured while fetching chatrooms from db: {}", err);
+/// Creates a new chatroom with the user as its only participant.
+#[utoipa::path(
+    post,
+    path = "/api/v1/chatrooms",
+    tag = "chatrooms",
+    request_body = schemas::CreateChatroomRequest,
+    responses(
+        (status = 200, body = schemas::FetchChatroomResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 403, description = "Creating a chatroom without a password requires a verified email address.", body = ApiErrorResponse),
+        (status = 409, description = "The generated chatroom id is taken, retrying resolves it.", body = ApiErrorResponse),
+    )
+)]
+pub async fn create_chatroom(
+    State(state): State<ServerState>,
+    Json(chatroom_request): Json<CreateChatroomRequest>,
+) -> Result<Json<FetchChatroomResponse>, ApiError> {
+    let generated_chatroom_id = generate_chatroom_id();
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &chatroom_request.user_session)?;
+
+            let mut user_account = info_span!("db", query = "find_user_account")
+                .in_scope(|| {
+                    users
+                        .filter(id.eq(chatroom_request.user_session.user_id))
+                        .get_result::<UserAccountEntry>(pg_connection)
+                })
+                .with_context(|| {
+                    format!(
+                        "An error occured while fetching user account with id {}",
+                        chatroom_request.user_session.user_id
+                    )
+                })?;
+
+            // Anyone knowing its id can join a chatroom without a password, throwaway accounts must not be able to create them
+            if chatroom_request.chatroom_passw.is_none() && user_account.email_verified_at.is_none()
+            {
+                return Err(ApiError::EmailNotVerified);
+            }
+
+            let chatroom_entry: ChatroomEntry = info_span!("db", query = "insert_chatroom")
+                .in_scope(|| {
+                    diesel::insert_into(chatrooms)
+                        .values(&NewChatroom {
+                            chatroom_id: generated_chatroom_id,
+                            chatroom_name: chatroom_request.chatroom_name,
+                            chatroom_password: chatroom_request.chatroom_passw,
+                            // Insert the user_id into the participants list
+                            participants: vec![chatroom_request.user_session.user_id],
+                            is_direct_message: false,
+                            last_message_id: None,
+                            owner_user_id: Some(chatroom_request.user_session.user_id),
+                        })
+                        .get_result(pg_connection)
+                })
+                .map_err(|err| {
+                    ApiError::from_query_error(err, "An error occured while creating a new chatroom")
+                })?;
+
+            user_account.chatrooms_joined.push(Some(chatroom_entry.id));
+
+            info_span!("db", query = "update_chatrooms_joined")
+                .in_scope(|| {
+                    diesel::update(users.filter(id.eq(chatroom_request.user_session.user_id)))
+                        .set(chatrooms_joined.eq(user_account.chatrooms_joined))
+                        .get_result::<UserAccountEntry>(pg_connection)
+                })
+                .with_context(|| {
+                    format!(
+                        "An error occured while adding chatroom {} to the joined chatrooms of user {}",
+                        chatroom_entry.id, chatroom_request.user_session.user_id
+                    )
+                })?;
+
+            Ok(Json(FetchChatroomResponse {
+                chatroom_uid: chatroom_entry.id,
+                chatroom_id: chatroom_entry.chatroom_id,
+                chatroom_name: chatroom_entry.chatroom_name,
+                participants: chatroom_entry.participants,
+                is_direct_message: chatroom_entry.is_direct_message,
+                last_message_id: chatroom_entry.last_message_id,
+            }))
+        })
+        .await
+}
 
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+/// Checks whether the session token belongs to the user.
+/// On success the user's id is attached to the span of the request.
+pub fn verify_user_session(
+    pg_connection: &mut PgConnection,
+    user_session: &UserSession,
+) -> Result<(), ApiError> {
+    // Get how many fields are equal (This must be one, or zero.)
+    let matching_user_tokens = info_span!("db", query = "count_matching_sessions")
+        .in_scope(|| {
+            user_signin_tokens
+                .filter(user_id.eq(user_session.user_id))
+                .filter(session_token.eq(&user_session.session_token))
+                .select(count_star())
+                .get_result::<i64>(pg_connection)
+        })
+        .context("An error occured while verifying the user's session token")?;
 
-        let is_user_present = chatroom_entry
-            .participants
-            .contains(&Some(bulk_chatrooms_request.user_session.user_id));
+    // If the user token is not found return an error indication that it is false.
+    if matching_user_tokens != 1 {
+        return Err(ApiError::InvalidSession);
+    }
 
-        // If the user is not present in the participants list, return an error
-        if !is_user_present {
-            return Err(StatusCode::FORBIDDEN);
-        }
+    record_user_id(user_session.user_id);
 
-        verified_chatrooms_reponses.push(FetchChatroomResponse {
-            chatroom_uid: chatroom_entry.id,
-            chatroom_id: chatroom_entry.chatroom_id,
-            chatroom_name: chatroom_entry.chatroom_name,
-            participants: chatroom_entry.participants,
-            is_direct_message: chatroom_entry.is_direct_message,
-            last_message_id: chatroom_entry.last_message_id,
-        });
-    }
+    Ok(())
+}
 
-    Ok(Json(FetchKnownChatroomResponse {
-        chatrooms: verified_chatrooms_reponses,
-    }))
+/// Fetches a chatroom by its internal id.
+pub fn find_chatroom(
+    pg_connection: &mut PgConnection,
+    chatroom_uid: i32,
+) -> Result<ChatroomEntry, ApiError> {
+    info_span!("db", query = "find_chatroom")
+        .in_scope(|| {
+            chatrooms
+                .filter(schema::chatrooms::id.eq(chatroom_uid))
+                .select(ChatroomEntry::as_select())
+                .first(pg_connection)
+                .optional()
+        })
+        .with_context(|| {
+            format!("An error occured while fetching chatroom {chatroom_uid} from db")
+        })?
+        .ok_or(ApiError::ChatroomNotFound)
 }
 
-pub async fn create_chatroom(
-    State(state): State<ServerState>,
-    Json(chatroom_request): Json<CreateChatroomRequest>,
-) -> Result<Json<FetchChatroomResponse>, StatusCode> {
-    // Get a db connection from the pool
-    let mut pg_connection = state.pg_pool.get().map_err(|err| {
-        error!(
-            "An error occured while fetching login information from db: {}",
-            err
-        );
-
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let generated_chatroom_id: String = rand::rng()
+/// Generates the public id of a new chatroom.
+pub fn generate_chatroom_id() -> String {
+    rand::rng()
         .sample_iter(&Uniform::new(char::from(32), char::from(126)).unwrap())
         .take(10)
-        .collect();
-
-    let chatroom_entry: ChatroomEntry = diesel::insert_into(chatrooms)
-        .values(&NewChatroom {
-            chatroom_id: generated_chatroom_id,
-            chatroom_name: chatroom_request.chatroom_name,
-            chatroom_password: chatroom_request.chatroom_passw,
-            // Insert the user_id into the participants list
-            participants: vec![chatroom_request.user_session.user_id],
-            is_direct_message: false,
-            last_message_id: None,
-        })
-        .get_result(&mut pg_connection)
-        .map_err(|err| {
-            error!("An error occured while creating a new chatroom: {}", err);
-
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    let mut user_account = users
-        .filter(id.eq(chatroom_request.user_session.user_id))
-        .get_result::<UserAccountEntry>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while fetching user account with id {}: {}",
-                chatroom_request.user_session.user_id, err
-            );
-
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    user_account.chatrooms_joined.push(Some(chatroom_entry.id));
-
-    diesel::update(users.filter(id.eq(chatroom_request.user_session.user_id)))
-        .set(chatrooms_joined.eq(user_account.chatrooms_joined))
-        .get_result::<UserAccountEntry>(&mut pg_connection)
-        .map_err(|err| {
-            error!(
-                "An error occured while fetching user account with id {}: {}",
-                chatroom_request.user_session.user_id, err
-            );
-
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-    Ok(Json(FetchChatroomResponse {
-        chatroom_uid: chatroom_entry.id,
-        chatroom_id: chatroom_entry.chatroom_id,
-        chatroom_name: chatroom_entry.chatroom_name,
-        participants: chatroom_entry.participants,
-        is_direct_message: chatroom_entry.is_direct_message,
-        last_message_id: chatroom_entry.last_message_id,
-    }))
+        .collect()
 }
 
 pub fn generate_session_token() -> [u8; 32] {
@@ -464,9 +584,3 @@ pub fn generate_session_token() -> [u8; 32] {
 
     custom_identifier
 }
-
-pub async fn handle_incoming_chatroom_message(
-    State(state): State<ServerState>,
-    Json(chatroom_request): Json<ChatMessage>,
-) {
-}
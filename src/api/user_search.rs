@@ -0,0 +1,321 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use axum::{Json, extract::State, http::StatusCode};
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, IntoSql, NullableExpressionMethods, PgConnection,
+    PgSortExpressionMethods, PgTextExpressionMethods, QueryDsl, RunQueryDsl,
+    pg::Pg,
+    sql_types::{Float4, Nullable, Text},
+};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use utoipa::ToSchema;
+use whatssock_lib::UserSession;
+
+use crate::{
+    ServerState,
+    api::{
+        contacts::blocked_user_ids,
+        error::{ApiError, ApiErrorResponse},
+        openapi::schemas,
+        user_account_control::verify_user_session,
+    },
+    monitoring::RATE_LIMITED_REQUESTS_TOTAL,
+    schema::{contacts, user_blocks, user_profiles, users},
+};
+
+/// Shorter queries match too many users to be useful, and make enumerating the directory cheap.
+const QUERY_MIN_CHARS: usize = 2;
+
+const QUERY_MAX_CHARS: usize = 64;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+
+const MAX_PAGE_SIZE: i64 = 50;
+
+diesel::define_sql_function! {
+    /// pg_trgm's similarity of two strings, from 0 (nothing in common) to 1 (equal).
+    fn similarity(text: Nullable<Text>, query: Text) -> Nullable<Float4>;
+}
+
+diesel::define_sql_function! {
+    /// Ignores `NULL`s, unlike most functions.
+    fn greatest(a: Nullable<Float4>, b: Nullable<Float4>) -> Nullable<Float4>;
+}
+
+// pg_trgm's operator matching strings more similar than `pg_trgm.similarity_threshold` (0.3 by default), it is served by the trigram indexes
+diesel::infix_operator!(TrigramMatches, " % ", backend: Pg);
+
+/// Who can find the user in the directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Discoverability {
+    Everyone,
+    /// Only users who have accepted the user as a contact.
+    Contacts,
+    /// Nobody, the user can still be found through the chatrooms they share with others.
+    Hidden,
+}
+
+impl Discoverability {
+    /// The value stored in `users.discoverability`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Everyone => "everyone",
+            Self::Contacts => "contacts",
+            Self::Hidden => "hidden",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateDiscoverabilityRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    pub discoverability: Discoverability,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchUsersRequest {
+    #[schema(value_type = schemas::UserSession)]
+    pub user_session: UserSession,
+    /// Matched against the beginning of usernames and display names, and fuzzily against the whole of them.
+    pub query: String,
+    /// The number of results to skip, pass the `next_offset` of the previous page.
+    #[serde(default)]
+    pub offset: i64,
+    /// At most 50, 20 if not set.
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserSearchResult {
+    pub user_id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchUsersResponse {
+    /// The best matches first.
+    pub results: Vec<UserSearchResult>,
+    /// The offset of the next page, `None` if this is the last one.
+    pub next_offset: Option<i64>,
+}
+
+/// Sets who can find the user in the directory.
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/discoverability",
+    tag = "contacts",
+    request_body = UpdateDiscoverabilityRequest,
+    responses(
+        (status = 204, description = "The setting has been changed."),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+    )
+)]
+pub async fn update_discoverability(
+    State(state): State<ServerState>,
+    Json(request): Json<UpdateDiscoverabilityRequest>,
+) -> Result<StatusCode, ApiError> {
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            info_span!("db", query = "update_discoverability")
+                .in_scope(|| {
+                    diesel::update(users::table.find(request.user_session.user_id))
+                        .set(users::discoverability.eq(request.discoverability.as_str()))
+                        .execute(pg_connection)
+                })
+                .context("An error occured while updating the user's discoverability")?;
+
+            Ok(StatusCode::NO_CONTENT)
+        })
+        .await
+}
+
+/// Searches the user directory by username and display name.
+/// Users who are hidden, who only show up for their contacts, or who have blocked the user (or have been blocked by them) are left out.
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/search",
+    tag = "contacts",
+    request_body = SearchUsersRequest,
+    responses(
+        (status = 200, body = SearchUsersResponse),
+        (status = 400, description = "The query is too short or too long, or the page is out of bounds.", body = ApiErrorResponse),
+        (status = 401, description = "The session is invalid.", body = ApiErrorResponse),
+        (status = 429, description = "Too many searches, see the `Retry-After` header.", body = ApiErrorResponse),
+    )
+)]
+pub async fn search_users(
+    State(state): State<ServerState>,
+    Json(request): Json<SearchUsersRequest>,
+) -> Result<Json<SearchUsersResponse>, ApiError> {
+    let query = request.query.trim().to_string();
+    let query_chars = query.chars().count();
+
+    if !(QUERY_MIN_CHARS..=QUERY_MAX_CHARS).contains(&query_chars) {
+        return Err(ApiError::InvalidInput {
+            message: "The search query has to be between 2 and 64 characters long.",
+        });
+    }
+
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) || request.offset < 0 {
+        return Err(ApiError::InvalidInput {
+            message: "The limit has to be between 1 and 50, and the offset can not be negative.",
+        });
+    }
+
+    let auth_limits = state.auth_limits.clone();
+
+    state
+        .run_query(move |pg_connection| {
+            verify_user_session(pg_connection, &request.user_session)?;
+
+            let user_id = request.user_session.user_id;
+
+            // Checked after the session, so that the limit can not be used to lock out other users
+            if let Err(retry_after) = auth_limits.user_search.check(&user_id.to_string()) {
+                counter!(RATE_LIMITED_REQUESTS_TOTAL, "limit" => "search").increment(1);
+
+                return Err(ApiError::RateLimited { retry_after });
+            }
+
+            let contact_ids = accepted_contact_ids(pg_connection, user_id)?;
+
+            let mut excluded_ids = blocked_user_ids(pg_connection, user_id)?;
+            excluded_ids.extend(blocker_ids(pg_connection, user_id)?);
+            excluded_ids.insert(user_id);
+
+            let prefix_pattern = format!("{}%", escape_like(&query));
+
+            let prefix_match = users::username
+                .ilike(prefix_pattern.clone())
+                .nullable()
+                .or(user_profiles::display_name.ilike(prefix_pattern));
+
+            // One more row than requested tells whether there is a next page
+            let mut rows = info_span!("db", query = "search_users")
+                .in_scope(|| {
+                    users::table
+                        .left_join(user_profiles::table)
+                        .filter(users::id.ne_all(excluded_ids))
+                        .filter(
+                            users::discoverability
+                                .eq(Discoverability::Everyone.as_str())
+                                .or(users::discoverability
+                                    .eq(Discoverability::Contacts.as_str())
+                                    .and(users::id.eq_any(contact_ids))),
+                        )
+                        .filter(
+                            prefix_match
+                                .clone()
+                                .or(TrigramMatches::new(
+                                    users::username,
+                                    query.clone().into_sql::<Text>(),
+                                )
+                                .nullable())
+                                .or(TrigramMatches::new(
+                                    user_profiles::display_name,
+                                    query.clone().into_sql::<Text>(),
+                                )
+                                .nullable()),
+                        )
+                        .order((
+                            prefix_match.desc().nulls_last(),
+                            greatest(
+                                similarity(users::username.nullable(), query.clone()),
+                                similarity(user_profiles::display_name, query),
+                            )
+                            .desc()
+                            .nulls_last(),
+                            users::username,
+                        ))
+                        .offset(request.offset)
+                        .limit(limit + 1)
+                        .select((
+                            users::id,
+                            users::username,
+                            user_profiles::display_name.nullable(),
+                        ))
+                        .load::<(i32, String, Option<String>)>(pg_connection)
+                })
+                .context("An error occured while searching for users")?;
+
+            let next_offset = (rows.len() as i64 > limit).then(|| request.offset + limit);
+
+            rows.truncate(limit as usize);
+
+            Ok(Json(SearchUsersResponse {
+                results: rows
+                    .into_iter()
+                    .map(|(user_id, username, display_name)| UserSearchResult {
+                        user_id,
+                        username,
+                        display_name,
+                    })
+                    .collect(),
+                next_offset,
+            }))
+        })
+        .await
+}
+
+/// The ids of the users who have accepted `user_id` as a contact.
+fn accepted_contact_ids(
+    pg_connection: &mut PgConnection,
+    user_id: i32,
+) -> Result<Vec<i32>, ApiError> {
+    let pairs = info_span!("db", query = "find_accepted_contacts")
+        .in_scope(|| {
+            contacts::table
+                .filter(
+                    contacts::requester_id
+                        .eq(user_id)
+                        .or(contacts::addressee_id.eq(user_id)),
+                )
+                .filter(contacts::accepted_at.is_not_null())
+                .select((contacts::requester_id, contacts::addressee_id))
+                .load::<(i32, i32)>(pg_connection)
+        })
+        .context("An error occured while fetching the user's contacts")?;
+
+    Ok(pairs
+        .into_iter()
+        .map(|(requester_id, addressee_id)| {
+            if requester_id == user_id {
+                addressee_id
+            } else {
+                requester_id
+            }
+        })
+        .collect())
+}
+
+/// The ids of the users who have blocked `user_id`.
+fn blocker_ids(pg_connection: &mut PgConnection, user_id: i32) -> Result<HashSet<i32>, ApiError> {
+    Ok(info_span!("db", query = "find_blockers")
+        .in_scope(|| {
+            user_blocks::table
+                .filter(user_blocks::blocked_id.eq(user_id))
+                .select(user_blocks::blocker_id)
+                .load::<i32>(pg_connection)
+        })
+        .context("An error occured while fetching the users who have blocked the user")?
+        .into_iter()
+        .collect())
+}
+
+/// Escapes the wildcards of `LIKE` patterns, so that they match literally.
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
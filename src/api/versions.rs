@@ -0,0 +1,51 @@
+use axum::Json;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Every version of the API the server speaks, newest first.
+pub const API_VERSIONS: [ApiVersion; 2] = [
+    ApiVersion {
+        version: "v1",
+        base_path: "/api/v1",
+        status: ApiVersionStatus::Current,
+    },
+    ApiVersion {
+        version: "legacy",
+        base_path: "/api",
+        status: ApiVersionStatus::Deprecated,
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiVersionStatus {
+    /// The version new clients should target.
+    Current,
+    /// Still served, but will be removed in a future release.
+    /// Responses of deprecated routes carry a `Deprecation` header, and a `Link` header pointing at their successor.
+    Deprecated,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+pub struct ApiVersion {
+    pub version: &'static str,
+    /// Every route of the version is nested under this path.
+    pub base_path: &'static str,
+    pub status: ApiVersionStatus,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiVersionsResponse {
+    pub versions: Vec<ApiVersion>,
+    /// The version of whatssock-lib, which defines the types of the API.
+    pub protocol_version: &'static str,
+}
+
+/// Lists the versions of the API the server speaks, clients should pick the newest one they support.
+#[utoipa::path(get, path = "/api/versions", tag = "operations", responses((status = 200, body = ApiVersionsResponse)))]
+pub async fn api_versions() -> Json<ApiVersionsResponse> {
+    Json(ApiVersionsResponse {
+        versions: API_VERSIONS.to_vec(),
+        protocol_version: env!("WHATSSOCK_LIB_VERSION"),
+    })
+}
@@ -0,0 +1,159 @@
+use std::{env, fmt::Display, net::IpAddr, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{Context, anyhow, bail, ensure};
+use lettre::message::Mailbox;
+
+use crate::{api::personal_data::DeletedUserMessages, logging::LogFormat, mailer::MailerConfig};
+
+/// Runtime configuration of the server.
+/// Every value is read from the environment (or the `.env` file) and falls back to a sane default if it is not set.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Connection string of the PostgreSQL database.
+    pub database_url: String,
+    /// How long in-flight requests are allowed to run after a shutdown signal has been received.
+    /// Connections still open after this deadline are dropped.
+    pub shutdown_grace_period: Duration,
+    /// The format the logs are written in.
+    pub log_format: LogFormat,
+    /// The maximum number of connections the database pool opens.
+    pub database_pool_size: u32,
+    /// How long a request waits for a connection from the pool before giving up.
+    pub database_checkout_timeout: Duration,
+    /// Requests per minute a single IP may send to the authentication endpoints.
+    pub auth_rate_limit_per_ip: u32,
+    /// The addresses of the reverse proxies (load balancers) in front of the server.
+    /// Requests coming from them are attributed to the client named in their `X-Forwarded-For` header, the header is ignored on any other request.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Requests per minute the authentication endpoints accept for a single username or chatroom.
+    pub auth_rate_limit_per_target: u32,
+    /// Searches per minute a single user may run in the user directory.
+    pub search_rate_limit_per_user: u32,
+    /// Failed logins after which the account is locked.
+    /// Before reaching this every failure blocks the next login for an exponentially growing delay.
+    pub login_lockout_threshold: u32,
+    /// How long an account stays locked.
+    pub login_lockout_duration: Duration,
+    /// The address the server is reachable at from the outside, the links emailed to users point here.
+    pub public_url: String,
+    pub mailer: MailerConfig,
+    /// The sender of every email.
+    pub mail_from: Mailbox,
+    /// The key the emailed links are signed with.
+    /// If it is not set a random key is used, which invalidates the links every time the server restarts.
+    pub link_signing_secret: Option<String>,
+    /// Whether admins have to use two-factor authentication.
+    /// Admins who have not set it up yet are made to enrol an authenticator the next time they log in.
+    pub require_admin_two_factor: bool,
+    /// What happens to the messages of users who delete their account.
+    pub deleted_user_messages: DeletedUserMessages,
+    /// The largest avatar or chatroom icon users can upload, in bytes.
+    pub avatar_max_size: usize,
+    /// Whether direct messages can only be opened with users who have accepted a contact request.
+    pub direct_messages_require_contact: bool,
+    /// The most messages a chatroom can have pinned at once, 0 turns pinning off.
+    pub max_pinned_messages: usize,
+}
+
+impl ServerConfig {
+    /// Reads the configuration from the environment.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let config = Self {
+            database_url: env::var("DATABASE_URL").context("DATABASE_URL must be set")?,
+            shutdown_grace_period: Duration::from_secs(env_or("SHUTDOWN_GRACE_PERIOD_SECS", 30)?),
+            log_format: env_or("LOG_FORMAT", LogFormat::Pretty)?,
+            database_pool_size: env_or("DATABASE_POOL_SIZE", 10)?,
+            database_checkout_timeout: Duration::from_secs(env_or(
+                "DATABASE_CHECKOUT_TIMEOUT_SECS",
+                5,
+            )?),
+            auth_rate_limit_per_ip: env_or("AUTH_RATE_LIMIT_PER_IP", 30)?,
+            trusted_proxies: trusted_proxies_from_env()?,
+            auth_rate_limit_per_target: env_or("AUTH_RATE_LIMIT_PER_TARGET", 10)?,
+            search_rate_limit_per_user: env_or("SEARCH_RATE_LIMIT_PER_USER", 30)?,
+            login_lockout_threshold: env_or("LOGIN_LOCKOUT_THRESHOLD", 5)?,
+            login_lockout_duration: Duration::from_secs(env_or("LOGIN_LOCKOUT_SECS", 900)?),
+            public_url: env_or("PUBLIC_URL", String::from("http://localhost:3004"))?
+                .trim_end_matches('/')
+                .to_string(),
+            mailer: mailer_from_env()?,
+            mail_from: env_or(
+                "MAIL_FROM",
+                "Whatssock <no-reply@localhost>".parse().unwrap(),
+            )?,
+            link_signing_secret: env::var("LINK_SIGNING_SECRET").ok(),
+            require_admin_two_factor: env_or("REQUIRE_ADMIN_TWO_FACTOR", true)?,
+            deleted_user_messages: env_or("DELETED_USER_MESSAGES", DeletedUserMessages::Anonymize)?,
+            avatar_max_size: env_or("AVATAR_MAX_BYTES", 256 * 1024)?,
+            direct_messages_require_contact: env_or("DIRECT_MESSAGES_REQUIRE_CONTACT", false)?,
+            max_pinned_messages: env_or("MAX_PINNED_MESSAGES", 3)?,
+        };
+
+        ensure!(
+            config.database_pool_size > 0,
+            "DATABASE_POOL_SIZE must be at least 1"
+        );
+        ensure!(
+            !config.database_checkout_timeout.is_zero(),
+            "DATABASE_CHECKOUT_TIMEOUT_SECS must be at least 1"
+        );
+        ensure!(
+            config.auth_rate_limit_per_ip > 0 && config.auth_rate_limit_per_target > 0,
+            "AUTH_RATE_LIMIT_PER_IP and AUTH_RATE_LIMIT_PER_TARGET must be at least 1"
+        );
+        ensure!(
+            config.search_rate_limit_per_user > 0,
+            "SEARCH_RATE_LIMIT_PER_USER must be at least 1"
+        );
+        ensure!(
+            config.login_lockout_threshold > 0,
+            "LOGIN_LOCKOUT_THRESHOLD must be at least 1"
+        );
+
+        Ok(config)
+    }
+}
+
+fn mailer_from_env() -> anyhow::Result<MailerConfig> {
+    match env_or("MAILER", String::from("file"))?.as_str() {
+        "smtp" => Ok(MailerConfig::Smtp {
+            url: env::var("SMTP_URL").context("SMTP_URL must be set if MAILER is smtp")?,
+        }),
+        "file" => Ok(MailerConfig::File {
+            dir: env_or("MAIL_DROP_DIR", PathBuf::from("mail"))?,
+        }),
+        mailer => bail!("Invalid value for MAILER ({mailer}): expected smtp or file"),
+    }
+}
+
+/// Parses the comma separated addresses of `TRUSTED_PROXIES`.
+fn trusted_proxies_from_env() -> anyhow::Result<Vec<IpAddr>> {
+    let Ok(value) = env::var("TRUSTED_PROXIES") else {
+        return Ok(Vec::new());
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|err| anyhow!("Invalid address in TRUSTED_PROXIES ({address}): {err}"))
+        })
+        .collect()
+}
+
+/// Parses the environment variable `key`, returns `default` if it is not present.
+fn env_or<T>(key: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map_err(|err| anyhow!("Invalid value for {key} ({value}): {err}")),
+        Err(_) => Ok(default),
+    }
+}
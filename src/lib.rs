@@ -1,12 +1,119 @@
+use std::sync::Arc;
+
+use anyhow::Context;
 use diesel::{PgConnection, r2d2::ConnectionManager};
+use tokio::{sync::watch, task::spawn_blocking};
+use tracing::{Span, warn};
+
+use crate::{
+    api::error::ApiError,
+    config::ServerConfig,
+    mailer::{Email, Mailer},
+    monitoring::PoolMetrics,
+    rate_limit::AuthLimits,
+    signed_links::LinkSigner,
+};
 
 pub mod api;
+pub mod config;
+pub mod logging;
+pub mod mailer;
+pub mod migrations;
 pub mod models;
+pub mod monitoring;
+pub mod rate_limit;
 pub mod schema;
+pub mod shutdown;
+pub mod signed_links;
 
 pub type PgPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
 #[derive(Debug, Clone)]
 pub struct ServerState {
     pub pg_pool: PgPool,
+    /// Flips to `true` once the server has started shutting down.
+    /// See [`shutdown::wait_for_shutdown`].
+    pub shutdown_listener: watch::Receiver<bool>,
+    pub auth_limits: Arc<AuthLimits>,
+    pub config: Arc<ServerConfig>,
+    pub mailer: Arc<dyn Mailer>,
+    pub link_signer: LinkSigner,
+}
+
+impl ServerState {
+    /// Establishes connection with the PostgreSQL database, and sets up the mailer of the configuration.
+    pub fn establish(
+        config: &ServerConfig,
+        shutdown_listener: watch::Receiver<bool>,
+    ) -> anyhow::Result<Self> {
+        let mailer = config.mailer.build(config.mail_from.clone())?;
+
+        Self::establish_with_mailer(config, shutdown_listener, mailer.into())
+    }
+
+    /// Like [`ServerState::establish`], but sends the emails with `mailer`.
+    pub fn establish_with_mailer(
+        config: &ServerConfig,
+        shutdown_listener: watch::Receiver<bool>,
+        mailer: Arc<dyn Mailer>,
+    ) -> anyhow::Result<Self> {
+        let pg_pool = r2d2::Builder::new()
+            .max_size(config.database_pool_size)
+            .connection_timeout(config.database_checkout_timeout)
+            .event_handler(Box::new(PoolMetrics))
+            .build(ConnectionManager::new(&config.database_url))?;
+
+        let link_signer = match &config.link_signing_secret {
+            Some(secret) => LinkSigner::new(secret.as_bytes()),
+            None => {
+                warn!(
+                    "LINK_SIGNING_SECRET is not set, the emailed links become invalid once the server restarts"
+                );
+
+                LinkSigner::random()
+            }
+        };
+
+        Ok(Self {
+            pg_pool,
+            shutdown_listener,
+            auth_limits: Arc::new(AuthLimits::new(config)),
+            config: Arc::new(config.clone()),
+            mailer,
+            link_signer,
+        })
+    }
+
+    /// Sends the email on tokio's blocking thread pool, see [`Mailer`].
+    pub async fn send_email(&self, email: Email) -> anyhow::Result<()> {
+        let mailer = self.mailer.clone();
+
+        spawn_blocking(move || mailer.send(&email))
+            .await
+            .context("A mailer task has panicked")?
+    }
+
+    /// Checks out a connection from the pool and runs `query` with it on tokio's blocking thread pool.
+    /// Diesel is synchronous, every database access must go through this so that it does not stall the async worker threads.
+    /// `query` runs inside the span of the calling request.
+    pub async fn run_query<T, F>(&self, query: F) -> Result<T, ApiError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PgConnection) -> Result<T, ApiError> + Send + 'static,
+    {
+        let pg_pool = self.pg_pool.clone();
+        let span = Span::current();
+
+        spawn_blocking(move || {
+            span.in_scope(|| {
+                let mut pg_connection = pg_pool
+                    .get()
+                    .context("An error occured while checking out a db connection")?;
+
+                query(&mut pg_connection)
+            })
+        })
+        .await
+        .context("A database task has panicked")?
+    }
 }
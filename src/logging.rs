@@ -0,0 +1,105 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http,
+    middleware::Next,
+    response::Response,
+};
+use tower_http::request_id::RequestId;
+use tracing::{Span, field, info_span};
+use tracing_subscriber::EnvFilter;
+
+/// The format every log line is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable, multi-line output for local development.
+    Pretty,
+    /// One JSON object per line for log aggregators.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown log format `{value}`, expected `pretty` or `json`"
+            )),
+        }
+    }
+}
+
+/// Installs the global tracing subscriber.
+/// The verbosity is controlled by `RUST_LOG`, and defaults to `info`.
+pub fn init_logging(format: LogFormat) {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(env_filter);
+
+    match format {
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Creates the span every request is handled in.
+/// `user_id` is empty until the handler has authenticated the user, see [`record_user_id`].
+pub fn make_request_span<B>(request: &http::Request<B>) -> Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok())
+        .unwrap_or_default();
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_default();
+
+    info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        route,
+        user_id = field::Empty,
+    )
+}
+
+/// Attaches the authenticated user's id to the span of the current request.
+pub fn record_user_id(user_id: i32) {
+    Span::current().record("user_id", user_id);
+}
+
+/// Middleware making the request id available to the handler through [`current_request_id`].
+/// This must run after the request id has been set on the request.
+pub async fn scope_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|request_id| request_id.header_value().to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    REQUEST_ID.scope(request_id, next.run(request)).await
+}
+
+/// Returns the id of the request currently being handled, if there is one.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID
+        .try_with(|request_id| request_id.clone())
+        .ok()
+        .filter(|request_id| !request_id.is_empty())
+}
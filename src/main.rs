@@ -1,60 +1,181 @@
-use std::env;
+use std::net::SocketAddr;
 
-use axum::{Router, routing::post, serve};
-use diesel::{
-    PgConnection,
-    r2d2::{self, ConnectionManager},
-};
+use axum::serve;
+use clap::{Parser, Subcommand};
+use diesel::{Connection, PgConnection};
 use dotenvy::dotenv;
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, sync::watch, time::timeout};
+use tracing::{info, warn};
 use whatssock_server::{
     ServerState,
-    api::user_account_control::{
-        create_chatroom, fetch_known_chatrooms, fetch_login, fetch_session_token,
-        fetch_unknown_chatroom, handle_logout_request, register_user,
+    api::router,
+    config::ServerConfig,
+    logging::init_logging,
+    migrations::{
+        migration_statuses, prepare_schema, revert_last_migration, run_pending_migrations,
     },
+    monitoring::prometheus_handle,
+    shutdown::{shutdown_signal, wait_for_shutdown},
 };
 
+#[derive(Debug, Parser)]
+#[command(version, about = "The backend of the Whatssock chat service.")]
+struct Cli {
+    /// Apply pending migrations before starting the server, instead of refusing to start.
+    #[arg(long, env = "MIGRATE_ON_STARTUP")]
+    migrate: bool,
+
+    /// Runs the server if no command is given.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Inspect and manage the database migrations embedded into the server.
+    #[command(subcommand)]
+    Migrations(MigrationsCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum MigrationsCommand {
+    /// List every migration, and whether it has been applied.
+    List,
+    /// Apply every pending migration.
+    Run,
+    /// Revert the most recently applied migration.
+    Revert {
+        /// Revert every applied migration instead of only the last one.
+        #[arg(long)]
+        all: bool,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    // Read the .env file before anything else looks at the environment
+    dotenv().ok();
+
+    let cli = Cli::parse();
+
+    let config = ServerConfig::from_env()?;
+
+    init_logging(config.log_format);
+
+    match cli.command {
+        Some(Command::Migrations(migrations_command)) => {
+            run_migrations_command(&config, migrations_command)
+        }
+        None => run_server(config, cli.migrate).await,
+    }
+}
+
+async fn run_server(config: ServerConfig, migrate: bool) -> anyhow::Result<()> {
+    // Install the metrics recorder before anything could record a metric
+    prometheus_handle();
+
+    // Handlers and long-lived connections get notified about the shutdown through this channel
+    let (shutdown_sender, shutdown_listener) = watch::channel(false);
 
     // Establish connection with the database
-    let servere_state = establish_state()?;
+    let servere_state = ServerState::establish(&config, shutdown_listener.clone())?;
+
+    // Make sure the schema is up to date before serving any requests
+    let mut pg_connection = servere_state.pg_pool.get()?;
+
+    prepare_schema(&mut pg_connection, migrate)?;
+
+    drop(pg_connection);
 
     // Start up the webserver
-    let router = Router::new()
-        .route("/api/register", post(register_user))
-        .route("/api/login", post(fetch_login))
-        .route("/api/session", post(fetch_session_token))
-        .route("/api/logout", post(handle_logout_request))
-        .route(
-            "/api/request_unknown_chatroom",
-            post(fetch_unknown_chatroom),
-        )
-        .route("/api/request_known_chatroom", post(fetch_known_chatrooms))
-        .route("/api/chatroom_new", post(create_chatroom))
-        .route("/api/chatroom_send_message", post(create_chatroom))
-        .with_state(servere_state);
+    let router = router(servere_state.clone());
 
     let listener = TcpListener::bind("[::1]:3004").await?;
 
-    serve(listener, router).await?;
+    // Once a shutdown is requested the server stops accepting new connections, and waits for the in-flight ones to finish
+    let mut server = tokio::spawn(
+        // The rate limits are keyed by the address of the client
+        serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(wait_for_shutdown(shutdown_listener))
+        .into_future(),
+    );
+
+    tokio::select! {
+        // The server can only stop on its own if it has run into an error
+        result = &mut server => return Ok(result??),
+        _ = shutdown_signal() => {},
+    }
+
+    info!(
+        "Shutdown signal received, draining connections for at most {:?}",
+        config.shutdown_grace_period
+    );
+
+    shutdown_sender.send_replace(true);
+
+    match timeout(config.shutdown_grace_period, &mut server).await {
+        Ok(result) => result??,
+        Err(_) => {
+            warn!("Shutdown deadline elapsed, dropping the remaining connections");
+
+            server.abort();
+        }
+    }
+
+    // The pool closes its connections once the last reference to it is dropped
+    drop(servere_state);
+
+    info!("Server has been shut down");
 
     Ok(())
 }
 
-/// Establishes connection with the PostgreSQL database.
-pub fn establish_state() -> anyhow::Result<ServerState> {
-    // Read the database url from the .env
-    dotenv().ok();
+fn run_migrations_command(
+    config: &ServerConfig,
+    migrations_command: MigrationsCommand,
+) -> anyhow::Result<()> {
+    let mut pg_connection = PgConnection::establish(&config.database_url)?;
 
-    // Fetch the DATABASE URL
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    match migrations_command {
+        MigrationsCommand::List => {
+            for migration in migration_statuses(&mut pg_connection)? {
+                let marker = if migration.applied { "[x]" } else { "[ ]" };
 
-    // Establish connection with the database
-    let pg_pool: r2d2::Pool<ConnectionManager<PgConnection>> =
-        r2d2::Builder::new().build(ConnectionManager::new(database_url))?;
+                println!("{marker} {}", migration.name);
+            }
+        }
+        MigrationsCommand::Run => {
+            let applied_versions = run_pending_migrations(&mut pg_connection)?;
+
+            if applied_versions.is_empty() {
+                println!("There are no pending migrations");
+            }
+
+            for version in applied_versions {
+                println!("Applied {version}");
+            }
+        }
+        MigrationsCommand::Revert { all } => loop {
+            let has_applied_migration = migration_statuses(&mut pg_connection)?
+                .iter()
+                .any(|migration| migration.applied);
+
+            if !has_applied_migration {
+                println!("There are no applied migrations");
 
-    Ok(ServerState { pg_pool })
+                break;
+            }
+
+            println!("Reverted {}", revert_last_migration(&mut pg_connection)?);
+
+            if !all {
+                break;
+            }
+        },
+    }
+
+    Ok(())
 }
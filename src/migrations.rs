@@ -0,0 +1,84 @@
+use anyhow::{anyhow, bail};
+use diesel::{PgConnection, migration::MigrationSource, pg::Pg};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use tracing::info;
+
+/// Every migration found in the `migrations` directory, embedded into the binary at compile time.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// An embedded migration, and whether it has been applied to the database.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub name: String,
+    pub applied: bool,
+}
+
+/// Lists every embedded migration in the order they are applied.
+pub fn migration_statuses(
+    pg_connection: &mut PgConnection,
+) -> anyhow::Result<Vec<MigrationStatus>> {
+    let applied_versions = pg_connection
+        .applied_migrations()
+        .map_err(|err| anyhow!("Failed to fetch the applied migrations: {err}"))?;
+
+    let mut migrations = MigrationSource::<Pg>::migrations(&MIGRATIONS)
+        .map_err(|err| anyhow!("Failed to read the embedded migrations: {err}"))?;
+
+    migrations.sort_unstable_by(|a, b| a.name().version().cmp(&b.name().version()));
+
+    Ok(migrations
+        .iter()
+        .map(|migration| MigrationStatus {
+            name: migration.name().to_string(),
+            applied: applied_versions.contains(&migration.name().version()),
+        })
+        .collect())
+}
+
+/// Applies every pending migration, returns the names of the applied ones.
+pub fn run_pending_migrations(pg_connection: &mut PgConnection) -> anyhow::Result<Vec<String>> {
+    let applied_versions = pg_connection
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|err| anyhow!("Failed to apply the pending migrations: {err}"))?;
+
+    Ok(applied_versions
+        .iter()
+        .map(|version| version.to_string())
+        .collect())
+}
+
+/// Reverts the most recently applied migration, returns its version.
+pub fn revert_last_migration(pg_connection: &mut PgConnection) -> anyhow::Result<String> {
+    let reverted_version = pg_connection
+        .revert_last_migration(MIGRATIONS)
+        .map_err(|err| anyhow!("Failed to revert the last migration: {err}"))?;
+
+    Ok(reverted_version.to_string())
+}
+
+/// Makes sure the database schema matches the embedded migrations before the server starts.
+/// If `apply_pending` is set the pending migrations are applied, otherwise the server refuses to start if there are any.
+pub fn prepare_schema(pg_connection: &mut PgConnection, apply_pending: bool) -> anyhow::Result<()> {
+    let pending_migrations: Vec<String> = migration_statuses(pg_connection)?
+        .into_iter()
+        .filter(|migration| !migration.applied)
+        .map(|migration| migration.name)
+        .collect();
+
+    if pending_migrations.is_empty() {
+        return Ok(());
+    }
+
+    if !apply_pending {
+        bail!(
+            "The database schema is behind, pending migrations: {}. Start the server with --migrate (or MIGRATE_ON_STARTUP=true) to apply them.",
+            pending_migrations.join(", ")
+        );
+    }
+
+    for version in run_pending_migrations(pg_connection)? {
+        info!("Applied migration {version}");
+    }
+
+    Ok(())
+}
@@ -15,6 +15,12 @@ pub struct UserAccountEntry {
     pub email: String,
     pub chatrooms_joined: Vec<Option<i32>>,
     pub created_at: chrono::NaiveDate,
+    /// `None` until the user has opened the link of the verification email.
+    pub email_verified_at: Option<chrono::NaiveDateTime>,
+    /// Admins may be required to use two-factor authentication, see [`crate::config::ServerConfig::require_admin_two_factor`].
+    pub is_admin: bool,
+    /// One of `everyone`, `contacts` or `hidden`, see [`crate::api::user_search::Discoverability`].
+    pub discoverability: String,
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -55,6 +61,14 @@ pub struct ChatroomEntry {
     pub participants: Vec<Option<i32>>,
     pub is_direct_message: bool,
     pub last_message_id: Option<i32>,
+    /// `None` for direct messages, and for chatrooms whose owner has deleted their account.
+    pub owner_user_id: Option<i32>,
+    /// Listed in the chatroom directory.
+    pub is_public: bool,
+    pub description: Option<String>,
+    pub topic_tags: Vec<Option<String>>,
+    /// The participants who can change the chatroom's settings, besides its owner.
+    pub admin_user_ids: Vec<Option<i32>>,
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -67,4 +81,139 @@ pub struct NewChatroom {
     pub participants: Vec<i32>,
     pub is_direct_message: bool,
     pub last_message_id: Option<i32>,
+    pub owner_user_id: Option<i32>,
+}
+
+/// The settings of a chatroom changed by its admins, `None` fields are left as they are.
+#[derive(Debug, Clone, Default, AsChangeset)]
+#[diesel(table_name = crate::schema::chatrooms)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ChatroomSettingsChangeset {
+    pub chatroom_name: Option<String>,
+    pub chatroom_password: Option<Option<String>>,
+    pub description: Option<Option<String>>,
+}
+
+#[derive(Debug, Clone, Selectable, QueryableByName, Queryable)]
+#[diesel(table_name = crate::schema::messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MessageEntry {
+    pub id: i32,
+    pub parent_chatroom_id: i32,
+    pub owner_user_id: i32,
+    pub send_date: chrono::NaiveDateTime,
+    /// The `ChatMessage`, serialized with rmp_serde. `None` for system messages.
+    pub raw_message: Option<Vec<u8>>,
+    /// The kind of the `SystemEvent` of system messages.
+    pub event_kind: Option<String>,
+    /// The `SystemEvent` of system messages.
+    pub event_payload: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewMessage {
+    pub parent_chatroom_id: i32,
+    pub owner_user_id: i32,
+    /// The `ChatMessage`, serialized with rmp_serde.
+    pub raw_message: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewSystemMessage {
+    pub parent_chatroom_id: i32,
+    /// The user who has made the change.
+    pub owner_user_id: i32,
+    pub event_kind: String,
+    pub event_payload: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Selectable, QueryableByName, Queryable)]
+#[diesel(table_name = crate::schema::password_reset_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PasswordResetTokenEntry {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: Vec<u8>,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::password_reset_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewPasswordResetToken {
+    pub user_id: i32,
+    pub token_hash: Vec<u8>,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Selectable, QueryableByName, Queryable)]
+#[diesel(table_name = crate::schema::user_totp)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserTotpEntry {
+    pub user_id: i32,
+    pub secret: Vec<u8>,
+    /// `None` while the enrolment has not been confirmed with a code yet.
+    pub confirmed_at: Option<chrono::NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::user_totp)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewUserTotp {
+    pub user_id: i32,
+    pub secret: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::recovery_codes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewRecoveryCode {
+    pub user_id: i32,
+    pub code_hash: Vec<u8>,
+}
+
+/// The profile of a user, without the bytes of their avatar.
+#[derive(Debug, Clone, Selectable, QueryableByName, Queryable)]
+#[diesel(table_name = crate::schema::user_profiles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserProfileEntry {
+    pub user_id: i32,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    /// Set together with the avatar, `None` if the user has no avatar.
+    pub avatar_content_type: Option<String>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::user_profiles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+// Clearing a field of the profile has to overwrite the stored value
+#[diesel(treat_none_as_null = true)]
+pub struct NewUserProfile {
+    pub user_id: i32,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Selectable, QueryableByName, Queryable)]
+#[diesel(table_name = crate::schema::contacts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ContactEntry {
+    pub requester_id: i32,
+    pub addressee_id: i32,
+    pub created_at: chrono::NaiveDateTime,
+    /// `None` while the addressee has not accepted the request yet.
+    pub accepted_at: Option<chrono::NaiveDateTime>,
 }
@@ -0,0 +1,130 @@
+use std::{sync::OnceLock, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics::{Unit, counter, describe_counter, describe_gauge, describe_histogram, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use r2d2::{
+    HandleEvent,
+    event::{CheckoutEvent, TimeoutEvent},
+};
+
+pub const HTTP_REQUESTS_TOTAL: &str = "whatssock_http_requests_total";
+pub const HTTP_REQUEST_DURATION_SECONDS: &str = "whatssock_http_request_duration_seconds";
+pub const DB_POOL_CONNECTIONS: &str = "whatssock_db_pool_connections";
+pub const DB_POOL_IDLE_CONNECTIONS: &str = "whatssock_db_pool_idle_connections";
+pub const DB_POOL_MAX_SIZE: &str = "whatssock_db_pool_max_size";
+pub const DB_POOL_CHECKOUT_WAIT_SECONDS: &str = "whatssock_db_pool_checkout_wait_seconds";
+pub const DB_POOL_CHECKOUT_TIMEOUTS_TOTAL: &str = "whatssock_db_pool_checkout_timeouts_total";
+pub const ACTIVE_SESSIONS: &str = "whatssock_active_sessions";
+pub const MESSAGES_SENT_TOTAL: &str = "whatssock_messages_sent_total";
+pub const RATE_LIMITED_REQUESTS_TOTAL: &str = "whatssock_rate_limited_requests_total";
+
+/// Histogram buckets (in seconds) used for every latency metric.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static PROMETHEUS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Returns the handle of the global Prometheus recorder, installing the recorder on the first call.
+pub fn prometheus_handle() -> PrometheusHandle {
+    PROMETHEUS_HANDLE
+        .get_or_init(|| {
+            let handle = PrometheusBuilder::new()
+                .set_buckets_for_metric(Matcher::Suffix(String::from("_seconds")), LATENCY_BUCKETS)
+                .expect("The latency buckets must not be empty")
+                .install_recorder()
+                .expect("Failed to install the Prometheus recorder");
+
+            describe_metrics();
+
+            handle
+        })
+        .clone()
+}
+
+fn describe_metrics() {
+    describe_counter!(
+        HTTP_REQUESTS_TOTAL,
+        "Requests handled, by route and status."
+    );
+    describe_histogram!(
+        HTTP_REQUEST_DURATION_SECONDS,
+        Unit::Seconds,
+        "Time taken to handle a request, by route."
+    );
+    describe_gauge!(
+        DB_POOL_CONNECTIONS,
+        "Connections currently managed by the database pool."
+    );
+    describe_gauge!(
+        DB_POOL_IDLE_CONNECTIONS,
+        "Connections currently idle in the database pool."
+    );
+    describe_gauge!(
+        DB_POOL_MAX_SIZE,
+        "Maximum number of connections the database pool may open."
+    );
+    describe_histogram!(
+        DB_POOL_CHECKOUT_WAIT_SECONDS,
+        Unit::Seconds,
+        "Time spent waiting for a connection from the database pool."
+    );
+    describe_counter!(
+        DB_POOL_CHECKOUT_TIMEOUTS_TOTAL,
+        "Checkouts which gave up waiting for a connection from the database pool."
+    );
+    describe_gauge!(ACTIVE_SESSIONS, "Session tokens currently issued.");
+    describe_counter!(
+        MESSAGES_SENT_TOTAL,
+        "Chat messages sent by users and stored."
+    );
+    describe_counter!(
+        RATE_LIMITED_REQUESTS_TOTAL,
+        "Requests rejected with 429, by the limit they have hit."
+    );
+}
+
+/// Middleware recording the count and the latency of every request.
+/// Requests are labelled with the route they have matched instead of their raw path, so that path parameters do not blow up the cardinality.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let started_at = Instant::now();
+
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_else(|| String::from("unmatched"));
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+
+    counter!(HTTP_REQUESTS_TOTAL, "method" => method.clone(), "route" => route.clone(), "status" => status)
+        .increment(1);
+    histogram!(HTTP_REQUEST_DURATION_SECONDS, "method" => method, "route" => route)
+        .record(started_at.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Records how long it takes to check out connections from the database pool.
+/// This should be passed to [`r2d2::Builder::event_handler`].
+#[derive(Debug)]
+pub struct PoolMetrics;
+
+impl HandleEvent for PoolMetrics {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        histogram!(DB_POOL_CHECKOUT_WAIT_SECONDS).record(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        histogram!(DB_POOL_CHECKOUT_WAIT_SECONDS).record(event.timeout().as_secs_f64());
+        counter!(DB_POOL_CHECKOUT_TIMEOUTS_TOTAL).increment(1);
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::counter;
+use serde_json::Value;
+
+use crate::{
+    ServerState, api::error::ApiError, config::ServerConfig,
+    monitoring::RATE_LIMITED_REQUESTS_TOTAL,
+};
+
+/// The largest body the rate limiting middleware buffers to look up the target of a request.
+/// Every authentication request is a small JSON object, anything bigger is rejected before it reaches the handler.
+const AUTH_BODY_LIMIT: usize = 16 * 1024;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Entries are only pruned once a map grows this big, so that the common case does not pay for it.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// The delay after the first failed login, it doubles with every further failure.
+const LOGIN_BACKOFF_BASE: Duration = Duration::from_secs(1);
+
+/// Every limit guarding the authentication endpoints.
+/// The state is kept in memory, thus it is per server instance, and is lost on restart.
+#[derive(Debug)]
+pub struct AuthLimits {
+    /// Keyed by the IP address of the client.
+    pub per_ip: RateLimiter,
+    /// Keyed by the username, the chatroom or the email address the request targets.
+    pub per_target: RateLimiter,
+    pub failed_logins: LoginThrottle,
+    /// Keyed by the id of the user, so that the user directory can not be scraped.
+    pub user_search: RateLimiter,
+}
+
+impl AuthLimits {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            per_ip: RateLimiter::new(config.auth_rate_limit_per_ip),
+            per_target: RateLimiter::new(config.auth_rate_limit_per_target),
+            failed_logins: LoginThrottle::new(
+                config.login_lockout_threshold,
+                config.login_lockout_duration,
+            ),
+            user_search: RateLimiter::new(config.search_rate_limit_per_user),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// A token bucket per key, which allows bursts of up to `per_minute` requests and refills continuously.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of `key`, or returns how long it takes until the next one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let capacity = f64::from(self.per_minute);
+        let refill_per_sec = capacity / 60.;
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // A bucket which would have refilled completely is the same as a missing one
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated_at).as_secs_f64() * refill_per_sec
+                    < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated_at).as_secs_f64() * refill_per_sec)
+            .min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1. - bucket.tokens) / refill_per_sec,
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FailedLogins {
+    count: u32,
+    blocked_until: Instant,
+}
+
+/// Tracks failed logins per username.
+/// Every failure blocks further attempts for an exponentially growing delay, until `threshold` failures lock the account for `lockout_duration`.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    threshold: u32,
+    lockout_duration: Duration,
+    failures: Mutex<HashMap<String, FailedLogins>>,
+}
+
+impl LoginThrottle {
+    pub fn new(threshold: u32, lockout_duration: Duration) -> Self {
+        Self {
+            threshold,
+            lockout_duration,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns how long the user has to wait before trying to log in again, if they are blocked.
+    pub fn check(&self, username: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        match self.failures.lock().unwrap().get(username) {
+            Some(failed_logins) if failed_logins.blocked_until > now => {
+                Err(failed_logins.blocked_until - now)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, username: &str) {
+        let now = Instant::now();
+
+        let mut failures = self.failures.lock().unwrap();
+
+        if failures.len() >= PRUNE_THRESHOLD {
+            failures.retain(|_, failed_logins| {
+                now.duration_since(failed_logins.blocked_until) < self.lockout_duration
+            });
+        }
+
+        let failed_logins = failures
+            .entry(username.to_string())
+            .or_insert(FailedLogins {
+                count: 0,
+                blocked_until: now,
+            });
+
+        // Failures are forgotten after a quiet period as long as a lockout
+        if now.duration_since(failed_logins.blocked_until) >= self.lockout_duration {
+            failed_logins.count = 0;
+        }
+
+        failed_logins.count += 1;
+
+        let delay = if failed_logins.count >= self.threshold {
+            self.lockout_duration
+        } else {
+            LOGIN_BACKOFF_BASE
+                .saturating_mul(2_u32.saturating_pow(failed_logins.count - 1))
+                .min(self.lockout_duration)
+        };
+
+        failed_logins.blocked_until = now + delay;
+    }
+
+    pub fn record_success(&self, username: &str) {
+        self.failures.lock().unwrap().remove(username);
+    }
+}
+
+/// Middleware limiting the requests of the authentication endpoints per client IP, and per target username, chatroom or email address.
+pub async fn limit_auth_requests(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (request, target) = match buffer_target(request).await {
+        Ok(buffered) => buffered,
+        Err(response) => return response,
+    };
+
+    if let Err(retry_after) = state
+        .auth_limits
+        .per_ip
+        .check(&client_ip(&request, &state.config.trusted_proxies))
+    {
+        return reject(retry_after, "ip");
+    }
+
+    // Usernames and chatroom ids are limited separately, even if they happen to be the same string
+    if let Some((field, value)) = target
+        && let Err(retry_after) = state
+            .auth_limits
+            .per_target
+            .check(&format!("{field}:{value}"))
+    {
+        return reject(retry_after, "target");
+    }
+
+    next.run(request).await
+}
+
+/// Middleware blocking logins to an account after failed attempts, see [`LoginThrottle`].
+pub async fn throttle_failed_logins(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (request, target) = match buffer_target(request).await {
+        Ok(buffered) => buffered,
+        Err(response) => return response,
+    };
+
+    // Malformed requests are rejected by the handler
+    let Some(("username", username)) = target else {
+        return next.run(request).await;
+    };
+
+    if let Err(retry_after) = state.auth_limits.failed_logins.check(&username) {
+        return reject(retry_after, "login");
+    }
+
+    let response = next.run(request).await;
+
+    match response.status() {
+        StatusCode::OK => state.auth_limits.failed_logins.record_success(&username),
+        // The login handler only answers with 401 if the credentials are incorrect
+        StatusCode::UNAUTHORIZED => state.auth_limits.failed_logins.record_failure(&username),
+        _ => (),
+    }
+
+    response
+}
+
+/// Buffers the body of the request, and looks up the username, the chatroom id or the email address it targets.
+/// The target is returned together with the name of the field it was found in.
+async fn buffer_target(
+    request: Request,
+) -> Result<(Request, Option<(&'static str, String)>), Response> {
+    let (parts, body) = request.into_parts();
+
+    let body = to_bytes(body, AUTH_BODY_LIMIT)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge.into_response())?;
+
+    let target = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|value| {
+            ["username", "chatroom_id", "email"]
+                .iter()
+                .find_map(|&field| Some((field, value.get(field)?.as_str()?.to_string())))
+        });
+
+    Ok((Request::from_parts(parts, Body::from(body)), target))
+}
+
+/// The IP of the client, taken from the socket the request has arrived on.
+/// If the socket belongs to one of the trusted proxies, the client is the last address of `X-Forwarded-For` which is not one of them: the proxies append the address they have received the request from, anything before it may be forged by the client.
+fn client_ip(request: &Request, trusted_proxies: &[IpAddr]) -> String {
+    let Some(ConnectInfo(address)) = request.extensions().get::<ConnectInfo<SocketAddr>>() else {
+        return String::from("unknown");
+    };
+
+    let mut client_ip = address.ip();
+
+    if !trusted_proxies.contains(&client_ip) {
+        return client_ip.to_string();
+    }
+
+    let forwarded_ips = request
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .rev();
+
+    for forwarded_ip in forwarded_ips {
+        // A malformed entry can not be trusted, the last proxy is blamed for it
+        let Ok(forwarded_ip) = forwarded_ip.trim().parse::<IpAddr>() else {
+            break;
+        };
+
+        client_ip = forwarded_ip;
+
+        if !trusted_proxies.contains(&client_ip) {
+            break;
+        }
+    }
+
+    client_ip.to_string()
+}
+
+fn reject(retry_after: Duration, limit: &'static str) -> Response {
+    counter!(RATE_LIMITED_REQUESTS_TOTAL, "limit" => limit).increment(1);
+
+    ApiError::RateLimited { retry_after }.into_response()
+}
@@ -1,5 +1,13 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    chatroom_icons (chatroom_uid) {
+        chatroom_uid -> Int4,
+        icon -> Bytea,
+        content_type -> Varchar,
+    }
+}
+
 diesel::table! {
     chatrooms (id) {
         id -> Int4,
@@ -9,6 +17,20 @@ diesel::table! {
         participants -> Array<Nullable<Int4>>,
         is_direct_message -> Bool,
         last_message_id -> Nullable<Int4>,
+        owner_user_id -> Nullable<Int4>,
+        is_public -> Bool,
+        description -> Nullable<Text>,
+        topic_tags -> Array<Nullable<Text>>,
+        admin_user_ids -> Array<Nullable<Int4>>,
+    }
+}
+
+diesel::table! {
+    contacts (requester_id, addressee_id) {
+        requester_id -> Int4,
+        addressee_id -> Int4,
+        created_at -> Timestamp,
+        accepted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -18,16 +40,58 @@ diesel::table! {
         parent_chatroom_id -> Int4,
         owner_user_id -> Int4,
         send_date -> Timestamp,
-        raw_message -> Bytea,
+        raw_message -> Nullable<Bytea>,
+        event_kind -> Nullable<Varchar>,
+        event_payload -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Bytea,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    pinned_messages (message_id) {
+        message_id -> Int4,
+        chatroom_uid -> Int4,
+        pinned_by -> Nullable<Int4>,
+        pinned_at -> Timestamp,
     }
 }
 
 diesel::table! {
-    posts (id) {
+    recovery_codes (id) {
         id -> Int4,
-        title -> Varchar,
-        body -> Text,
-        published -> Bool,
+        user_id -> Int4,
+        code_hash -> Bytea,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_blocks (blocker_id, blocked_id) {
+        blocker_id -> Int4,
+        blocked_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_profiles (user_id) {
+        user_id -> Int4,
+        display_name -> Nullable<Varchar>,
+        bio -> Nullable<Text>,
+        status_text -> Nullable<Varchar>,
+        status_emoji -> Nullable<Varchar>,
+        avatar -> Nullable<Bytea>,
+        avatar_content_type -> Nullable<Varchar>,
+        updated_at -> Timestamp,
     }
 }
 
@@ -39,6 +103,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    user_totp (user_id) {
+        user_id -> Int4,
+        secret -> Bytea,
+        confirmed_at -> Nullable<Timestamp>,
+        last_used_step -> Nullable<Int8>,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -47,13 +120,36 @@ diesel::table! {
         email -> Varchar,
         chatrooms_joined -> Array<Nullable<Int4>>,
         created_at -> Date,
+        email_verified_at -> Nullable<Timestamp>,
+        is_admin -> Bool,
+        discoverability -> Varchar,
     }
 }
 
+diesel::joinable!(chatroom_icons -> chatrooms (chatroom_uid));
+diesel::joinable!(chatrooms -> users (owner_user_id));
+diesel::joinable!(messages -> chatrooms (parent_chatroom_id));
+diesel::joinable!(messages -> users (owner_user_id));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(pinned_messages -> chatrooms (chatroom_uid));
+diesel::joinable!(pinned_messages -> messages (message_id));
+diesel::joinable!(pinned_messages -> users (pinned_by));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(user_profiles -> users (user_id));
+diesel::joinable!(user_signin_tokens -> users (user_id));
+diesel::joinable!(user_totp -> users (user_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    chatroom_icons,
     chatrooms,
+    contacts,
     messages,
-    posts,
+    password_reset_tokens,
+    pinned_messages,
+    recovery_codes,
+    user_blocks,
+    user_profiles,
     user_signin_tokens,
+    user_totp,
     users,
 );
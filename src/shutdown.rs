@@ -0,0 +1,36 @@
+use tokio::{signal, sync::watch};
+
+/// Completes once the process has been asked to stop, either via SIGINT (Ctrl+C) or SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("Failed to install the Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    // There is no SIGTERM on other platforms, Ctrl+C is the only way to stop the server.
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Completes once a shutdown has been requested through the [`watch`] channel stored in the [`crate::ServerState`].
+/// Long-lived connections (like WebSockets) should select on this, and send a close frame to the client when it completes.
+pub async fn wait_for_shutdown(mut shutdown_listener: watch::Receiver<bool>) {
+    // If the sender has been dropped the server is going down anyway.
+    let _ = shutdown_listener
+        .wait_for(|is_shutting_down| *is_shutting_down)
+        .await;
+}
@@ -0,0 +1,253 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use whatssock_lib::{
+    client::{LoginRequest, UserInformation},
+    server::LoginResponse,
+};
+use whatssock_server::api::{
+    account_settings::{ChangeEmailRequest, ChangePasswordRequest, ChangeUsernameRequest},
+    email_verification::EmailVerifiedResponse,
+    error::ErrorCode,
+};
+
+fn change_password(
+    login: &LoginResponse,
+    current_password: &str,
+    revoke_other_sessions: bool,
+) -> ChangePasswordRequest {
+    ChangePasswordRequest {
+        user_session: session_of(login),
+        current_password: current_password.to_string(),
+        new_password: String::from("correct horse"),
+        revoke_other_sessions,
+    }
+}
+
+#[tokio::test]
+async fn password_change_requires_the_current_password() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/password",
+            &change_password(&login, "hunter2", false),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // Logging in replaces the session
+    let login: LoginResponse = app
+        .post(
+            "/api/v1/sessions",
+            &LoginRequest {
+                username: "alice".to_string(),
+                password: "correct horse".to_string(),
+            },
+        )
+        .await;
+
+    // The old password is not the current one anymore
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/password",
+            &change_password(&login, "hunter2", false),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidCredentials);
+}
+
+#[tokio::test]
+async fn password_change_can_revoke_other_sessions() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let other_session = session_of(
+        &app.post::<LoginResponse>(
+            "/api/v1/sessions",
+            &LoginRequest {
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+            },
+        )
+        .await,
+    );
+
+    let _: UserInformation = app.post("/api/v1/sessions/current", &other_session).await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/password",
+            &change_password(&login, "hunter2", true),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, error) = app
+        .post_err("/api/v1/sessions/current", &other_session)
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+
+    let _: UserInformation = app
+        .post("/api/v1/sessions/current", &session_of(&login))
+        .await;
+}
+
+#[tokio::test]
+async fn username_change_requires_the_password_and_checks_uniqueness() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+    app.register("bob", "hunter2").await;
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/username",
+            &ChangeUsernameRequest {
+                user_session: session_of(&login),
+                current_password: "hunter2".to_string(),
+                new_username: "bob".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error.code, ErrorCode::UsernameTaken);
+
+    for new_username in ["   ".to_string(), "a".repeat(33)] {
+        let (_, error) = app
+            .send_err(
+                Method::PUT,
+                "/api/v1/users/username",
+                &ChangeUsernameRequest {
+                    user_session: session_of(&login),
+                    current_password: "hunter2".to_string(),
+                    new_username,
+                },
+            )
+            .await;
+
+        assert_eq!(error.code, ErrorCode::InvalidInput);
+    }
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/username",
+            &ChangeUsernameRequest {
+                user_session: session_of(&login),
+                current_password: "hunter2".to_string(),
+                new_username: " carol ".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let user_information: UserInformation = app
+        .post("/api/v1/sessions/current", &session_of(&login))
+        .await;
+
+    assert_eq!(user_information.username, "carol");
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/username",
+            &ChangeUsernameRequest {
+                user_session: session_of(&login),
+                current_password: "wrong".to_string(),
+                new_username: "dave".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidCredentials);
+}
+
+#[tokio::test]
+async fn email_change_requires_verifying_the_new_address() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+    app.register("bob", "hunter2").await;
+    app.verify_email("alice").await;
+
+    let change_email = |new_email: &str| ChangeEmailRequest {
+        user_session: session_of(&login),
+        current_password: String::from("hunter2"),
+        new_email: new_email.to_string(),
+        revoke_other_sessions: false,
+    };
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/email",
+            &change_email("bob@example.com"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error.code, ErrorCode::EmailTaken);
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/email",
+            &change_email("alice.example.org"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/email",
+            &change_email("alice@example.org"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // The address is unverified again, so asking for a new link is allowed
+    let (status, _, _) = app
+        .send_raw(
+            Method::POST,
+            "/api/v1/users/verification",
+            &session_of(&login),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let verified: EmailVerifiedResponse =
+        app.get(&app.last_emailed_link("alice@example.org")).await;
+
+    assert_eq!(verified.email, "alice@example.org");
+}
@@ -0,0 +1,457 @@
+mod common;
+
+use axum::http::{Method, StatusCode, header};
+use common::{TestApp, session_of};
+use whatssock_lib::{
+    CreateChatroomRequest, FetchChatroomResponse, FetchKnownChatroomResponse, FetchKnownChatrooms,
+    FetchUnknownChatroom, UserSession,
+    client::{LoginRequest, RegisterRequest, UserInformation},
+    server::{LoginResponse, LogoutResponse},
+};
+use whatssock_server::api::error::ErrorCode;
+
+#[tokio::test]
+async fn register_issues_a_working_session() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    assert!(login.chatrooms_joined.is_empty());
+
+    let user_information: UserInformation = app
+        .post("/api/v1/sessions/current", &session_of(&login))
+        .await;
+
+    assert_eq!(user_information.username, "alice");
+}
+
+#[tokio::test]
+async fn register_rejects_taken_username_and_email() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/users",
+            &RegisterRequest {
+                username: "alice".to_string(),
+                password: "hunter3".to_string(),
+                email: "another@example.com".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error.code, ErrorCode::UsernameTaken);
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/users",
+            &RegisterRequest {
+                username: "bob".to_string(),
+                password: "hunter3".to_string(),
+                email: "alice@example.com".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error.code, ErrorCode::EmailTaken);
+}
+
+#[tokio::test]
+async fn register_rejects_malformed_email_addresses() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    for email in [
+        "",
+        "alice",
+        "alice@",
+        "@example.com",
+        "alice example@example.com",
+    ] {
+        let (status, error) = app
+            .post_err(
+                "/api/v1/users",
+                &RegisterRequest {
+                    username: "alice".to_string(),
+                    password: "hunter2".to_string(),
+                    email: email.to_string(),
+                },
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{email}");
+        assert_eq!(error.code, ErrorCode::InvalidInput);
+    }
+
+    assert!(app.mailer.sent().is_empty());
+}
+
+#[tokio::test]
+async fn register_trims_and_bounds_usernames() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let register = |username: &str, email: &str| RegisterRequest {
+        username: username.to_string(),
+        password: "hunter2".to_string(),
+        email: email.to_string(),
+    };
+
+    for username in ["", "   ", &"a".repeat(33)] {
+        let (status, error) = app
+            .post_err("/api/v1/users", &register(username, "alice@example.com"))
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{username:?}");
+        assert_eq!(error.code, ErrorCode::InvalidInput);
+    }
+
+    let _: LoginResponse = app
+        .post("/api/v1/users", &register("  alice ", "alice@example.com"))
+        .await;
+
+    let _: LoginResponse = app
+        .post(
+            "/api/v1/sessions",
+            &LoginRequest {
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+            },
+        )
+        .await;
+
+    let (status, error) = app
+        .post_err("/api/v1/users", &register("alice ", "another@example.com"))
+        .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error.code, ErrorCode::UsernameTaken);
+}
+
+#[tokio::test]
+async fn logins_keep_the_sessions_of_other_devices() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let registration = app.register("alice", "hunter2").await;
+
+    let login: LoginResponse = app
+        .post(
+            "/api/v1/sessions",
+            &LoginRequest {
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(login.user_id, registration.user_id);
+    assert_ne!(login.session_token, registration.session_token);
+
+    for session in [&login, &registration] {
+        let _: UserInformation = app
+            .post("/api/v1/sessions/current", &session_of(session))
+            .await;
+    }
+
+    // Logging out ends the presented session only
+    let _: LogoutResponse = app
+        .send(
+            Method::DELETE,
+            "/api/v1/sessions",
+            &session_of(&registration),
+        )
+        .await;
+
+    let (status, error) = app
+        .post_err("/api/v1/sessions/current", &session_of(&registration))
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+
+    let _: UserInformation = app
+        .post("/api/v1/sessions/current", &session_of(&login))
+        .await;
+}
+
+#[tokio::test]
+async fn login_rejects_wrong_password() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/sessions",
+            &LoginRequest {
+                username: "alice".to_string(),
+                password: "hunter3".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidCredentials);
+}
+
+#[tokio::test]
+async fn session_rejects_forged_token() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/sessions/current",
+            &UserSession {
+                user_id: login.user_id,
+                session_token: vec![0; 32],
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+}
+
+#[tokio::test]
+async fn logout_revokes_the_session() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let _: LogoutResponse = app
+        .send(Method::DELETE, "/api/v1/sessions", &session_of(&login))
+        .await;
+
+    let (status, error) = app
+        .post_err("/api/v1/sessions/current", &session_of(&login))
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+}
+
+#[tokio::test]
+async fn created_chatroom_can_be_fetched() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let chatroom: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms",
+            &CreateChatroomRequest {
+                user_session: session_of(&login),
+                chatroom_name: "general".to_string(),
+                chatroom_passw: Some("secret".to_string()),
+            },
+        )
+        .await;
+
+    assert_eq!(chatroom.chatroom_name, "general");
+    assert_eq!(chatroom.participants, vec![Some(login.user_id)]);
+    assert!(!chatroom.is_direct_message);
+
+    // The new chatroom is added to the joined chatrooms of its creator
+    let user_information: UserInformation = app
+        .post("/api/v1/sessions/current", &session_of(&login))
+        .await;
+
+    assert_eq!(
+        user_information.chatrooms_joined,
+        vec![Some(chatroom.chatroom_uid)]
+    );
+
+    let known_chatrooms: FetchKnownChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms/known",
+            &FetchKnownChatrooms {
+                user_session: session_of(&login),
+                chatroom_uids: vec![chatroom.chatroom_uid],
+            },
+        )
+        .await;
+
+    assert_eq!(known_chatrooms.chatrooms.len(), 1);
+    assert_eq!(
+        known_chatrooms.chatrooms[0].chatroom_id,
+        chatroom.chatroom_id
+    );
+
+    let unknown_chatroom: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms/lookup",
+            &FetchUnknownChatroom {
+                chatroom_id: chatroom.chatroom_id.clone(),
+                password: Some("secret".to_string()),
+            },
+        )
+        .await;
+
+    assert_eq!(unknown_chatroom.chatroom_uid, chatroom.chatroom_uid);
+}
+
+#[tokio::test]
+async fn unknown_chatroom_requires_its_password() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let chatroom: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms",
+            &CreateChatroomRequest {
+                user_session: session_of(&login),
+                chatroom_name: "general".to_string(),
+                chatroom_passw: Some("secret".to_string()),
+            },
+        )
+        .await;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/chatrooms/lookup",
+            &FetchUnknownChatroom {
+                chatroom_id: chatroom.chatroom_id,
+                password: Some("guess".to_string()),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::ChatroomNotFound);
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/chatrooms/lookup",
+            &FetchUnknownChatroom {
+                chatroom_id: "does not exist".to_string(),
+                password: None,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::ChatroomNotFound);
+}
+
+#[tokio::test]
+async fn known_chatrooms_are_limited_to_participants() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    app.verify_email("alice").await;
+
+    let chatroom: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms",
+            &CreateChatroomRequest {
+                user_session: session_of(&alice),
+                chatroom_name: "general".to_string(),
+                chatroom_passw: None,
+            },
+        )
+        .await;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/chatrooms/known",
+            &FetchKnownChatrooms {
+                user_session: session_of(&bob),
+                chatroom_uids: vec![chatroom.chatroom_uid],
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomMember);
+
+    // Somebody else's session token is rejected before anything is looked up
+    let (status, error) = app
+        .post_err(
+            "/api/v1/chatrooms/known",
+            &FetchKnownChatrooms {
+                user_session: UserSession {
+                    user_id: alice.user_id,
+                    session_token: bob.session_token.to_vec(),
+                },
+                chatroom_uids: vec![chatroom.chatroom_uid],
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+}
+
+#[tokio::test]
+async fn legacy_routes_point_at_their_successor() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let (status, headers, _) = app
+        .send_raw(
+            Method::POST,
+            "/api/register",
+            &RegisterRequest {
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+                email: "alice@example.com".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["deprecation"], "true");
+    assert_eq!(
+        headers[header::LINK],
+        "</api/v1/users>; rel=\"successor-version\""
+    );
+
+    // The legacy logout route is a POST, unlike its successor
+    let login: LoginResponse = app
+        .post(
+            "/api/login",
+            &LoginRequest {
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+            },
+        )
+        .await;
+
+    let _: LogoutResponse = app.post("/api/logout", &session_of(&login)).await;
+
+    let (status, _) = app
+        .post_err("/api/v1/sessions/current", &session_of(&login))
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
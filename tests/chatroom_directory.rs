@@ -0,0 +1,272 @@
+mod common;
+
+use axum::http::{Method, StatusCode, header};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use common::{TestApp, session_of};
+use diesel::{RunQueryDsl, sql_query};
+use whatssock_lib::{FetchChatroomResponse, server::LoginResponse};
+use whatssock_server::api::{
+    chatroom_directory::{
+        ChatroomDirectoryRequest, ChatroomDirectoryResponse, DirectorySort, PublicChatroom,
+        UpdateListingRequest, UploadChatroomIconRequest,
+    },
+    contacts::UserTargetRequest,
+    error::ErrorCode,
+};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n0000";
+
+fn listing(login: &LoginResponse, description: &str, topic_tags: &[&str]) -> UpdateListingRequest {
+    UpdateListingRequest {
+        user_session: session_of(login),
+        is_public: true,
+        description: Some(description.to_string()),
+        topic_tags: topic_tags.iter().map(|tag| tag.to_string()).collect(),
+    }
+}
+
+async fn publish(
+    app: &TestApp,
+    chatroom: &FetchChatroomResponse,
+    request: &UpdateListingRequest,
+) -> PublicChatroom {
+    app.send(
+        Method::PUT,
+        &format!("/api/v1/chatrooms/{}/listing", chatroom.chatroom_uid),
+        request,
+    )
+    .await
+}
+
+fn directory_request(login: &LoginResponse) -> ChatroomDirectoryRequest {
+    ChatroomDirectoryRequest {
+        user_session: session_of(login),
+        query: None,
+        tag: None,
+        sort: DirectorySort::Members,
+        offset: 0,
+        limit: None,
+    }
+}
+
+async fn directory(app: &TestApp, request: &ChatroomDirectoryRequest) -> Vec<String> {
+    app.post::<ChatroomDirectoryResponse>("/api/v1/chatrooms/directory", request)
+        .await
+        .chatrooms
+        .into_iter()
+        .map(|chatroom| chatroom.chatroom_name)
+        .collect()
+}
+
+#[tokio::test]
+async fn only_owners_can_list_chatrooms_without_password() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    app.verify_email("alice").await;
+
+    let open_chatroom = app.create_chatroom(&alice, "Rust fans", None).await;
+    let protected_chatroom = app.create_chatroom(&alice, "Secret", Some("secret")).await;
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{}/listing", open_chatroom.chatroom_uid),
+            &listing(&bob, "Mine now", &[]),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomOwner);
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &format!(
+                "/api/v1/chatrooms/{}/listing",
+                protected_chatroom.chatroom_uid
+            ),
+            &listing(&alice, "Nothing to see", &[]),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+
+    // Tags are normalized
+    let listed = publish(
+        &app,
+        &open_chatroom,
+        &listing(&alice, "All things Rust", &["Rust", " programming", "rust"]),
+    )
+    .await;
+
+    assert_eq!(listed.topic_tags, vec!["rust", "programming"]);
+    assert_eq!(listed.member_count, 1);
+
+    let (status, _) = app
+        .send_err(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{}/listing", open_chatroom.chatroom_uid),
+            &listing(&alice, "Too many tags", &["a", "b", "c", "d", "e", "f"]),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Direct messages have no owner to list them
+    let direct_message: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms/direct",
+            &UserTargetRequest {
+                user_session: session_of(&alice),
+                user_id: bob.user_id,
+            },
+        )
+        .await;
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{}/listing", direct_message.chatroom_uid),
+            &listing(&alice, "Our chat", &[]),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomOwner);
+
+    assert_eq!(
+        directory(&app, &directory_request(&bob)).await,
+        vec!["Rust fans"]
+    );
+}
+
+#[tokio::test]
+async fn directory_is_searched_and_sorted() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    app.verify_email("alice").await;
+
+    let rust_chatroom = app.create_chatroom(&alice, "Rust fans", None).await;
+    let go_chatroom = app.create_chatroom(&alice, "Gophers", None).await;
+    app.create_chatroom(&alice, "Unlisted", None).await;
+
+    publish(
+        &app,
+        &rust_chatroom,
+        &listing(&alice, "Crabs welcome", &["rust"]),
+    )
+    .await;
+    publish(
+        &app,
+        &go_chatroom,
+        &listing(&alice, "Go and nothing else", &["go"]),
+    )
+    .await;
+
+    let mut pg_connection = app.database.connect();
+
+    sql_query(format!(
+        "UPDATE chatrooms SET participants = array_append(participants, {}) WHERE id = {}",
+        bob.user_id, go_chatroom.chatroom_uid
+    ))
+    .execute(&mut pg_connection)
+    .unwrap();
+
+    assert_eq!(
+        directory(&app, &directory_request(&bob)).await,
+        vec!["Gophers", "Rust fans"]
+    );
+
+    // The most recent message moves a chatroom to the top
+    app.send_message(&rust_chatroom, &alice).await;
+
+    let by_activity = ChatroomDirectoryRequest {
+        sort: DirectorySort::Activity,
+        ..directory_request(&bob)
+    };
+
+    assert_eq!(
+        directory(&app, &by_activity).await,
+        vec!["Rust fans", "Gophers"]
+    );
+
+    let by_description = ChatroomDirectoryRequest {
+        query: Some(String::from("CRAB")),
+        ..directory_request(&bob)
+    };
+
+    assert_eq!(directory(&app, &by_description).await, vec!["Rust fans"]);
+
+    let by_tag = ChatroomDirectoryRequest {
+        tag: Some(String::from("Go")),
+        ..directory_request(&bob)
+    };
+
+    assert_eq!(directory(&app, &by_tag).await, vec!["Gophers"]);
+
+    let first_page: ChatroomDirectoryResponse = app
+        .post(
+            "/api/v1/chatrooms/directory",
+            &ChatroomDirectoryRequest {
+                limit: Some(1),
+                ..directory_request(&bob)
+            },
+        )
+        .await;
+
+    assert_eq!(first_page.chatrooms.len(), 1);
+    assert_eq!(first_page.next_offset, Some(1));
+}
+
+#[tokio::test]
+async fn icons_of_public_chatrooms_are_visible_to_everyone() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    app.verify_email("alice").await;
+
+    let chatroom = app.create_chatroom(&alice, "Rust fans", None).await;
+    let icon_path = format!("/api/v1/chatrooms/{}/icon", chatroom.chatroom_uid);
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            &icon_path,
+            &UploadChatroomIconRequest {
+                user_session: session_of(&alice),
+                image: BASE64.encode(PNG),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, error) = app.post_err(&icon_path, &session_of(&bob)).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::ChatroomNotFound);
+
+    let listed = publish(&app, &chatroom, &listing(&alice, "All things Rust", &[])).await;
+
+    assert!(listed.has_icon);
+
+    let (status, headers, body) = app
+        .send_raw(Method::POST, &icon_path, &session_of(&bob))
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+    assert_eq!(body, PNG);
+}
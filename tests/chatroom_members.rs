@@ -0,0 +1,330 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use whatssock_lib::{FetchChatroomResponse, client::UserInformation, server::LoginResponse};
+use whatssock_server::api::{
+    chatroom_history::{ChatroomHistoryRequest, ChatroomHistoryResponse, TimelineContent},
+    chatroom_members::JoinChatroomRequest,
+    chatroom_settings::UpdateChatroomSettingsRequest,
+    error::ErrorCode,
+    system_messages::{ChatroomRole, SystemEvent},
+};
+
+async fn join(
+    app: &TestApp,
+    chatroom: &FetchChatroomResponse,
+    login: &LoginResponse,
+) -> FetchChatroomResponse {
+    app.post(
+        "/api/v1/chatrooms/join",
+        &JoinChatroomRequest {
+            user_session: session_of(login),
+            chatroom_id: chatroom.chatroom_id.clone(),
+            password: Some("secret".to_string()),
+        },
+    )
+    .await
+}
+
+async fn history(
+    app: &TestApp,
+    chatroom: &FetchChatroomResponse,
+    request: &ChatroomHistoryRequest,
+) -> ChatroomHistoryResponse {
+    app.post(
+        &format!("/api/v1/chatrooms/{}/history", chatroom.chatroom_uid),
+        request,
+    )
+    .await
+}
+
+fn history_request(login: &LoginResponse) -> ChatroomHistoryRequest {
+    ChatroomHistoryRequest {
+        user_session: session_of(login),
+        before_message_id: None,
+        limit: None,
+    }
+}
+
+/// The system events of the history as `(user_id, event)`, with `None` in place of user messages.
+fn events_of(history: ChatroomHistoryResponse) -> Vec<Option<(i32, SystemEvent)>> {
+    history
+        .entries
+        .into_iter()
+        .map(|entry| match entry.content {
+            TimelineContent::System { event } => Some((entry.user_id, event)),
+            TimelineContent::Message { .. } => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn membership_and_role_changes_are_recorded_in_the_history() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+    let uid = chatroom.chatroom_uid;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/chatrooms/join",
+            &JoinChatroomRequest {
+                user_session: session_of(&bob),
+                chatroom_id: chatroom.chatroom_id.clone(),
+                password: None,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::ChatroomNotFound);
+
+    let joined = join(&app, &chatroom, &bob).await;
+    assert_eq!(
+        joined.participants,
+        vec![Some(alice.user_id), Some(bob.user_id)]
+    );
+
+    // Joining again changes nothing
+    join(&app, &chatroom, &bob).await;
+    join(&app, &chatroom, &carol).await;
+
+    // Only admins remove participants, and only the owner removes admins
+    let (status, error) = app
+        .send_err(
+            Method::DELETE,
+            &format!("/api/v1/chatrooms/{uid}/participants/{}", carol.user_id),
+            &session_of(&bob),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomAdmin);
+
+    let admin_path = format!("/api/v1/chatrooms/{uid}/admins/{}", bob.user_id);
+    app.send_no_content(Method::PUT, &admin_path, &alice).await;
+
+    let _: FetchChatroomResponse = app
+        .send(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{uid}/settings"),
+            &UpdateChatroomSettingsRequest {
+                user_session: session_of(&bob),
+                chatroom_name: Some("bob's".to_string()),
+                chatroom_password: None,
+                remove_password: false,
+                description: None,
+            },
+        )
+        .await;
+
+    app.send_no_content(
+        Method::DELETE,
+        &format!("/api/v1/chatrooms/{uid}/participants/{}", carol.user_id),
+        &bob,
+    )
+    .await;
+    app.send_no_content(Method::DELETE, &admin_path, &alice)
+        .await;
+
+    let (status, error) = app
+        .send_err(
+            Method::POST,
+            &format!("/api/v1/chatrooms/{uid}/leave"),
+            &session_of(&alice),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+
+    app.send_no_content(
+        Method::POST,
+        &format!("/api/v1/chatrooms/{uid}/leave"),
+        &bob,
+    )
+    .await;
+
+    // Only participants read the history
+    let (status, error) = app
+        .post_err(
+            &format!("/api/v1/chatrooms/{uid}/history"),
+            &history_request(&carol),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomMember);
+
+    assert_eq!(
+        events_of(history(&app, &chatroom, &history_request(&alice)).await),
+        vec![
+            Some((bob.user_id, SystemEvent::MemberJoined)),
+            Some((carol.user_id, SystemEvent::MemberJoined)),
+            Some((
+                alice.user_id,
+                SystemEvent::RoleChanged {
+                    user_id: bob.user_id,
+                    role: ChatroomRole::Admin,
+                }
+            )),
+            Some((
+                bob.user_id,
+                SystemEvent::ChatroomRenamed {
+                    previous_name: "general".to_string(),
+                    chatroom_name: "bob's".to_string(),
+                }
+            )),
+            Some((
+                bob.user_id,
+                SystemEvent::MemberKicked {
+                    user_id: carol.user_id,
+                }
+            )),
+            Some((
+                alice.user_id,
+                SystemEvent::RoleChanged {
+                    user_id: bob.user_id,
+                    role: ChatroomRole::Member,
+                }
+            )),
+            Some((bob.user_id, SystemEvent::MemberLeft)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn history_is_paged_from_the_most_recent() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+
+    app.send_message(&chatroom, &alice).await;
+    join(&app, &chatroom, &bob).await;
+    app.send_message(&chatroom, &bob).await;
+
+    let latest = history(
+        &app,
+        &chatroom,
+        &ChatroomHistoryRequest {
+            limit: Some(2),
+            ..history_request(&alice)
+        },
+    )
+    .await;
+
+    let next_before_message_id = latest.next_before_message_id;
+
+    assert!(next_before_message_id.is_some());
+    assert_eq!(
+        events_of(latest),
+        vec![Some((bob.user_id, SystemEvent::MemberJoined)), None]
+    );
+
+    let older = history(
+        &app,
+        &chatroom,
+        &ChatroomHistoryRequest {
+            limit: Some(2),
+            before_message_id: next_before_message_id,
+            ..history_request(&alice)
+        },
+    )
+    .await;
+
+    assert_eq!(older.next_before_message_id, None);
+    assert_eq!(older.entries.len(), 1);
+    assert_eq!(older.entries[0].user_id, alice.user_id);
+}
+
+#[tokio::test]
+async fn messages_of_blocked_users_are_left_out_of_the_history() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+
+    join(&app, &chatroom, &bob).await;
+    app.send_message(&chatroom, &bob).await;
+    app.send_message(&chatroom, &alice).await;
+
+    app.send_no_content(
+        Method::PUT,
+        &format!("/api/v1/users/blocks/{}", bob.user_id),
+        &alice,
+    )
+    .await;
+
+    let entries = history(&app, &chatroom, &history_request(&alice))
+        .await
+        .entries;
+
+    // The join of the blocked user is part of the chatroom's history, their message is not
+    assert_eq!(
+        entries
+            .iter()
+            .map(|entry| entry.user_id)
+            .collect::<Vec<_>>(),
+        vec![bob.user_id, alice.user_id]
+    );
+    assert!(matches!(entries[0].content, TimelineContent::System { .. }));
+
+    assert_eq!(
+        history(&app, &chatroom, &history_request(&bob))
+            .await
+            .entries
+            .len(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn concurrent_joins_add_the_user_once() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+
+    tokio::join!(
+        join(&app, &chatroom, &bob),
+        join(&app, &chatroom, &bob),
+        join(&app, &chatroom, &bob),
+    );
+
+    let joined = join(&app, &chatroom, &bob).await;
+
+    assert_eq!(
+        joined.participants,
+        vec![Some(alice.user_id), Some(bob.user_id)]
+    );
+
+    let user_information: UserInformation = app
+        .post("/api/v1/sessions/current", &session_of(&bob))
+        .await;
+
+    assert_eq!(
+        user_information.chatrooms_joined,
+        vec![Some(chatroom.chatroom_uid)]
+    );
+
+    assert_eq!(
+        events_of(history(&app, &chatroom, &history_request(&alice)).await),
+        vec![Some((bob.user_id, SystemEvent::MemberJoined))]
+    );
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use whatssock_lib::{
+    FetchChatroomResponse, FetchKnownChatrooms, UserSession, server::LoginResponse,
+};
+use whatssock_server::api::{
+    chatroom_history::{
+        ChatroomHistoryRequest, ChatroomHistoryResponse, TimelineContent, TimelineEntry,
+    },
+    chatroom_messages::SendMessageRequest,
+    contacts::UserTargetRequest,
+    error::ErrorCode,
+    pinned_messages::KnownChatroomsResponse,
+};
+
+fn message_request(login: &LoginResponse, message: &str) -> SendMessageRequest {
+    SendMessageRequest {
+        user_session: session_of(login),
+        message: message.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn participants_send_messages_to_the_history() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+    let path = format!("/api/v1/chatrooms/{}/messages", chatroom.chatroom_uid);
+
+    let (status, error) = app
+        .post_err(
+            &path,
+            &SendMessageRequest {
+                user_session: UserSession {
+                    user_id: alice.user_id,
+                    session_token: vec![0; 32],
+                },
+                message: "hello".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+
+    let (status, error) = app.post_err(&path, &message_request(&bob, "hello")).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomMember);
+
+    let (status, error) = app.post_err(&path, &message_request(&alice, "  ")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+
+    let sent: TimelineEntry = app.post(&path, &message_request(&alice, "hello")).await;
+
+    assert_eq!(sent.user_id, alice.user_id);
+
+    let known: KnownChatroomsResponse = app
+        .post(
+            "/api/v1/chatrooms/known",
+            &FetchKnownChatrooms {
+                user_session: session_of(&alice),
+                chatroom_uids: vec![chatroom.chatroom_uid],
+            },
+        )
+        .await;
+
+    assert_eq!(
+        known.chatrooms[0].chatroom.last_message_id,
+        Some(sent.message_id)
+    );
+
+    let history: ChatroomHistoryResponse = app
+        .post(
+            &format!("/api/v1/chatrooms/{}/history", chatroom.chatroom_uid),
+            &ChatroomHistoryRequest {
+                user_session: session_of(&alice),
+                before_message_id: None,
+                limit: None,
+            },
+        )
+        .await;
+
+    assert_eq!(history.entries.len(), 1);
+    assert_eq!(history.entries[0].message_id, sent.message_id);
+
+    let TimelineContent::Message {
+        message: Some(message),
+    } = &history.entries[0].content
+    else {
+        panic!("The message could not be read back");
+    };
+
+    assert_eq!(message.message, "hello");
+    assert_eq!(message.owner_user_id, alice.user_id);
+}
+
+#[tokio::test]
+async fn blocks_close_direct_messages() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    let direct_message: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms/direct",
+            &UserTargetRequest {
+                user_session: session_of(&alice),
+                user_id: bob.user_id,
+            },
+        )
+        .await;
+    let path = format!("/api/v1/chatrooms/{}/messages", direct_message.chatroom_uid);
+
+    let _: TimelineEntry = app.post(&path, &message_request(&bob, "hi")).await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            &format!("/api/v1/users/blocks/{}", alice.user_id),
+            &session_of(&bob),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    for sender in [&alice, &bob] {
+        let (status, error) = app.post_err(&path, &message_request(sender, "hi")).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(error.code, ErrorCode::UserBlocked);
+    }
+}
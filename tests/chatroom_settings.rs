@@ -0,0 +1,266 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, sql_query};
+use whatssock_lib::{FetchChatroomResponse, server::LoginResponse};
+use whatssock_server::{
+    api::{
+        chatroom_directory::UpdateListingRequest, chatroom_settings::UpdateChatroomSettingsRequest,
+        contacts::UserTargetRequest, error::ErrorCode, system_messages::SystemEvent,
+    },
+    schema::messages,
+};
+
+fn settings(login: &LoginResponse) -> UpdateChatroomSettingsRequest {
+    UpdateChatroomSettingsRequest {
+        user_session: session_of(login),
+        chatroom_name: None,
+        chatroom_password: None,
+        remove_password: false,
+        description: None,
+    }
+}
+
+fn join(app: &TestApp, chatroom: &FetchChatroomResponse, login: &LoginResponse) {
+    sql_query(format!(
+        "UPDATE chatrooms SET participants = array_append(participants, {}) WHERE id = {}",
+        login.user_id, chatroom.chatroom_uid
+    ))
+    .execute(&mut app.database.connect())
+    .unwrap();
+}
+
+/// The system events recorded in the chatroom, oldest first.
+fn system_events(app: &TestApp, chatroom: &FetchChatroomResponse) -> Vec<(i32, SystemEvent)> {
+    messages::table
+        .filter(messages::parent_chatroom_id.eq(chatroom.chatroom_uid))
+        .filter(messages::event_payload.is_not_null())
+        .order(messages::id)
+        .select((messages::owner_user_id, messages::event_payload))
+        .load::<(i32, Option<serde_json::Value>)>(&mut app.database.connect())
+        .unwrap()
+        .into_iter()
+        .map(|(user_id, payload)| (user_id, serde_json::from_value(payload.unwrap()).unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn admins_change_settings_and_every_change_is_recorded() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+    let settings_path = format!("/api/v1/chatrooms/{}/settings", chatroom.chatroom_uid);
+
+    join(&app, &chatroom, &bob);
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &settings_path,
+            &UpdateChatroomSettingsRequest {
+                chatroom_name: Some("bob's".to_string()),
+                ..settings(&bob)
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomAdmin);
+
+    let updated: FetchChatroomResponse = app
+        .send(
+            Method::PUT,
+            &settings_path,
+            &UpdateChatroomSettingsRequest {
+                chatroom_name: Some(" Rustaceans ".to_string()),
+                chatroom_password: Some("hunter3".to_string()),
+                description: Some("All things Rust".to_string()),
+                ..settings(&alice)
+            },
+        )
+        .await;
+
+    assert_eq!(updated.chatroom_name, "Rustaceans");
+    assert!(updated.last_message_id.is_some());
+
+    // Removing the password is not for throwaway accounts
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &settings_path,
+            &UpdateChatroomSettingsRequest {
+                remove_password: true,
+                ..settings(&alice)
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::EmailNotVerified);
+
+    app.verify_email("alice").await;
+
+    // Unchanged settings are not recorded
+    let _: FetchChatroomResponse = app
+        .send(
+            Method::PUT,
+            &settings_path,
+            &UpdateChatroomSettingsRequest {
+                chatroom_name: Some("Rustaceans".to_string()),
+                remove_password: true,
+                description: Some(String::new()),
+                ..settings(&alice)
+            },
+        )
+        .await;
+
+    assert_eq!(
+        system_events(&app, &chatroom),
+        vec![
+            (
+                alice.user_id,
+                SystemEvent::ChatroomRenamed {
+                    previous_name: "general".to_string(),
+                    chatroom_name: "Rustaceans".to_string(),
+                }
+            ),
+            (alice.user_id, SystemEvent::PasswordSet),
+            (
+                alice.user_id,
+                SystemEvent::DescriptionChanged {
+                    description: Some("All things Rust".to_string()),
+                }
+            ),
+            (alice.user_id, SystemEvent::PasswordRemoved),
+            (
+                alice.user_id,
+                SystemEvent::DescriptionChanged { description: None }
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn public_chatrooms_can_not_get_a_password() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    app.verify_email("alice").await;
+
+    let chatroom = app.create_chatroom(&alice, "general", None).await;
+
+    let _: serde_json::Value = app
+        .send(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{}/listing", chatroom.chatroom_uid),
+            &UpdateListingRequest {
+                user_session: session_of(&alice),
+                is_public: true,
+                description: None,
+                topic_tags: Vec::new(),
+            },
+        )
+        .await;
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{}/settings", chatroom.chatroom_uid),
+            &UpdateChatroomSettingsRequest {
+                chatroom_password: Some("secret".to_string()),
+                ..settings(&alice)
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+    assert!(system_events(&app, &chatroom).is_empty());
+}
+
+#[tokio::test]
+async fn owners_transfer_their_chatroom_to_a_participant() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+    let owner_path = format!("/api/v1/chatrooms/{}/owner", chatroom.chatroom_uid);
+
+    join(&app, &chatroom, &bob);
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &owner_path,
+            &UserTargetRequest {
+                user_session: session_of(&alice),
+                user_id: carol.user_id,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            &owner_path,
+            &UserTargetRequest {
+                user_session: session_of(&alice),
+                user_id: bob.user_id,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &owner_path,
+            &UserTargetRequest {
+                user_session: session_of(&alice),
+                user_id: bob.user_id,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomOwner);
+
+    // The previous owner stays an admin
+    let updated: FetchChatroomResponse = app
+        .send(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{}/settings", chatroom.chatroom_uid),
+            &UpdateChatroomSettingsRequest {
+                chatroom_name: Some("handed over".to_string()),
+                ..settings(&alice)
+            },
+        )
+        .await;
+
+    assert_eq!(updated.chatroom_name, "handed over");
+    assert_eq!(
+        system_events(&app, &chatroom)[0],
+        (
+            alice.user_id,
+            SystemEvent::OwnershipTransferred {
+                previous_owner_user_id: alice.user_id,
+                owner_user_id: bob.user_id,
+            }
+        )
+    );
+}
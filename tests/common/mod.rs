@@ -0,0 +1,366 @@
+#![allow(dead_code)]
+
+use std::{env, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{HeaderMap, Method, Request, StatusCode, header},
+};
+use diesel::{Connection, PgConnection, RunQueryDsl, sql_query};
+use diesel_migrations::MigrationHarness;
+use rand::{Rng, rng};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::sync::watch;
+use tower::ServiceExt;
+use whatssock_lib::{
+    CreateChatroomRequest, FetchChatroomResponse, UserSession, client::RegisterRequest,
+    server::LoginResponse,
+};
+use whatssock_server::{
+    ServerState,
+    api::{
+        chatroom_history::TimelineEntry, chatroom_messages::SendMessageRequest,
+        error::ApiErrorResponse, personal_data::DeletedUserMessages, router,
+    },
+    config::ServerConfig,
+    logging::LogFormat,
+    mailer::{Email, InMemoryMailer, MailerConfig},
+    migrations::MIGRATIONS,
+};
+
+/// A freshly created and migrated database, dropped together with this value.
+pub struct TestDatabase {
+    /// Connection string of the test database.
+    pub url: String,
+    admin_url: String,
+    name: String,
+}
+
+impl TestDatabase {
+    /// Creates a new database on the server `TEST_DATABASE_URL` points at, and applies every migration to it.
+    /// Returns `None` if `TEST_DATABASE_URL` is not set, so that the tests needing a database can be skipped.
+    pub fn create() -> Option<Self> {
+        let Ok(admin_url) = env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping the test");
+
+            return None;
+        };
+
+        let name = format!("whatssock_test_{:016x}", rng().random::<u64>());
+
+        let mut admin_connection = PgConnection::establish(&admin_url)
+            .expect("Failed to connect to the database at TEST_DATABASE_URL");
+
+        sql_query(format!("CREATE DATABASE {name}"))
+            .execute(&mut admin_connection)
+            .expect("Failed to create the test database");
+
+        // Swap the database name at the end of the connection string
+        let (server_url, _) = admin_url
+            .rsplit_once('/')
+            .expect("TEST_DATABASE_URL must include a database name");
+
+        let test_database = Self {
+            url: format!("{server_url}/{name}"),
+            admin_url,
+            name,
+        };
+
+        test_database
+            .connect()
+            .run_pending_migrations(MIGRATIONS)
+            .expect("Failed to migrate the test database");
+
+        Some(test_database)
+    }
+
+    pub fn connect(&self) -> PgConnection {
+        PgConnection::establish(&self.url).expect("Failed to connect to the test database")
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        // Failing to clean up should not hide the result of the test
+        if let Ok(mut admin_connection) = PgConnection::establish(&self.admin_url) {
+            sql_query(format!(
+                "DROP DATABASE IF EXISTS {} WITH (FORCE)",
+                self.name
+            ))
+            .execute(&mut admin_connection)
+            .ok();
+        }
+    }
+}
+
+/// The server's router running in-process on top of its own [`TestDatabase`].
+pub struct TestApp {
+    router: Router,
+    /// Every email the server has sent.
+    pub mailer: Arc<InMemoryMailer>,
+    // Kept alive so that the handlers never see the server shutting down
+    _shutdown_sender: watch::Sender<bool>,
+    pub database: TestDatabase,
+}
+
+impl TestApp {
+    /// Returns `None` if `TEST_DATABASE_URL` is not set, see [`TestDatabase::create`].
+    pub fn spawn() -> Option<Self> {
+        Self::spawn_with(|_| ())
+    }
+
+    /// Like [`TestApp::spawn`], but lets the test adjust the configuration of the server.
+    pub fn spawn_with(configure: impl FnOnce(&mut ServerConfig)) -> Option<Self> {
+        let database = TestDatabase::create()?;
+
+        let mut config = ServerConfig {
+            database_url: database.url.clone(),
+            shutdown_grace_period: Duration::from_secs(1),
+            log_format: LogFormat::Pretty,
+            database_pool_size: 2,
+            database_checkout_timeout: Duration::from_secs(5),
+            // Every request of the tests comes from the same address, only the tests of the limits should run into them
+            auth_rate_limit_per_ip: 1000,
+            trusted_proxies: Vec::new(),
+            auth_rate_limit_per_target: 1000,
+            search_rate_limit_per_user: 1000,
+            login_lockout_threshold: 5,
+            login_lockout_duration: Duration::from_secs(900),
+            public_url: String::from("http://whatssock.test"),
+            mailer: MailerConfig::File {
+                dir: PathBuf::from("unused"),
+            },
+            mail_from: "Whatssock <no-reply@whatssock.test>".parse().unwrap(),
+            link_signing_secret: Some(String::from("test secret")),
+            require_admin_two_factor: true,
+            deleted_user_messages: DeletedUserMessages::Anonymize,
+            avatar_max_size: 1024,
+            direct_messages_require_contact: false,
+            max_pinned_messages: 3,
+        };
+
+        configure(&mut config);
+
+        let (shutdown_sender, shutdown_listener) = watch::channel(false);
+
+        let mailer = Arc::new(InMemoryMailer::default());
+
+        let state = ServerState::establish_with_mailer(&config, shutdown_listener, mailer.clone())
+            .expect("Failed to create the state of the test server");
+
+        Some(Self {
+            router: router(state),
+            mailer,
+            _shutdown_sender: shutdown_sender,
+            database,
+        })
+    }
+
+    /// Sends a JSON request, returns the status code, the headers and the raw body of the response.
+    pub async fn send_raw(
+        &self,
+        method: Method,
+        path: &str,
+        body: &impl Serialize,
+    ) -> (StatusCode, HeaderMap, Vec<u8>) {
+        self.send_raw_with_headers(method, path, &[], body).await
+    }
+
+    /// Like [`TestApp::send_raw`], with extra headers.
+    /// Every request arrives from `127.0.0.1`.
+    pub async fn send_raw_with_headers(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &impl Serialize,
+    ) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let request = request
+            .body(Body::from(serde_json::to_vec(body).unwrap()))
+            .unwrap();
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, headers, body.to_vec())
+    }
+
+    /// Sends a JSON request which is expected to succeed.
+    pub async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: &impl Serialize,
+    ) -> T {
+        let (status, _, body) = self.send_raw(method.clone(), path, body).await;
+
+        assert_eq!(
+            status,
+            StatusCode::OK,
+            "{method} {path} has failed: {}",
+            String::from_utf8_lossy(&body)
+        );
+
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Sends a JSON request which is expected to fail, returns the status code and the error body.
+    pub async fn send_err(
+        &self,
+        method: Method,
+        path: &str,
+        body: &impl Serialize,
+    ) -> (StatusCode, ApiErrorResponse) {
+        let (status, _, body) = self.send_raw(method.clone(), path, body).await;
+
+        assert!(
+            !status.is_success(),
+            "{method} {path} was expected to fail: {}",
+            String::from_utf8_lossy(&body)
+        );
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> T {
+        self.send(Method::GET, path, &()).await
+    }
+
+    pub async fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> T {
+        self.send(Method::POST, path, body).await
+    }
+
+    pub async fn post_err(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> (StatusCode, ApiErrorResponse) {
+        self.send_err(Method::POST, path, body).await
+    }
+
+    /// Registers a new account, the email address is derived from `username`.
+    pub async fn register(&self, username: &str, password: &str) -> LoginResponse {
+        self.post(
+            "/api/v1/users",
+            &RegisterRequest {
+                username: username.to_string(),
+                password: password.to_string(),
+                email: format!("{username}@example.com"),
+            },
+        )
+        .await
+    }
+
+    /// The path (with the query) of the last link emailed to `to`.
+    pub fn last_emailed_link(&self, to: &str) -> String {
+        let email = self
+            .mailer
+            .sent()
+            .into_iter()
+            .rev()
+            .find(|email| email.to == to)
+            .unwrap_or_else(|| panic!("No email has been sent to {to}"));
+
+        let link = email
+            .body
+            .split_whitespace()
+            .find_map(|word| word.strip_prefix("http://whatssock.test"))
+            .expect("The email does not contain a link");
+
+        link.to_string()
+    }
+
+    /// Waits until `count` emails have been sent, the emails sent in the background arrive after the response.
+    pub async fn wait_for_emails(&self, count: usize) -> Vec<Email> {
+        for _ in 0..100 {
+            let sent = self.mailer.sent();
+
+            if sent.len() >= count {
+                return sent;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!(
+            "Only {} of {count} emails have been sent",
+            self.mailer.sent().len()
+        );
+    }
+
+    /// Opens the link of the last verification email sent to the user registered with [`TestApp::register`].
+    pub async fn verify_email(&self, username: &str) {
+        let link = self.last_emailed_link(&format!("{username}@example.com"));
+
+        let _: serde_json::Value = self.get(&link).await;
+    }
+
+    /// Creates a chatroom owned by the user.
+    pub async fn create_chatroom(
+        &self,
+        login: &LoginResponse,
+        name: &str,
+        password: Option<&str>,
+    ) -> FetchChatroomResponse {
+        self.post(
+            "/api/v1/chatrooms",
+            &CreateChatroomRequest {
+                user_session: session_of(login),
+                chatroom_name: name.to_string(),
+                chatroom_passw: password.map(str::to_string),
+            },
+        )
+        .await
+    }
+
+    /// Sends a message of the user to the chatroom, and returns its id.
+    pub async fn send_message(
+        &self,
+        chatroom: &FetchChatroomResponse,
+        login: &LoginResponse,
+    ) -> i32 {
+        self.post::<TimelineEntry>(
+            &format!("/api/v1/chatrooms/{}/messages", chatroom.chatroom_uid),
+            &SendMessageRequest {
+                user_session: session_of(login),
+                message: String::from("hello"),
+            },
+        )
+        .await
+        .message_id
+    }
+
+    /// Sends a request of the user answered with `204 No Content`.
+    pub async fn send_no_content(&self, method: Method, path: &str, login: &LoginResponse) {
+        let (status, _, body) = self.send_raw(method, path, &session_of(login)).await;
+
+        assert_eq!(
+            status,
+            StatusCode::NO_CONTENT,
+            "{path} has failed: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+}
+
+/// The session a successful login or registration has issued.
+pub fn session_of(login: &LoginResponse) -> UserSession {
+    UserSession {
+        user_id: login.user_id,
+        session_token: login.session_token.to_vec(),
+    }
+}
@@ -0,0 +1,256 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use whatssock_lib::{FetchChatroomResponse, FetchUnknownChatroom, server::LoginResponse};
+use whatssock_server::api::{
+    contacts::{
+        Contact, ContactListResponse, ContactRequestResponse, ContactStatus, UserTargetRequest,
+    },
+    error::ErrorCode,
+};
+
+fn target(login: &LoginResponse, other: &LoginResponse) -> UserTargetRequest {
+    UserTargetRequest {
+        user_session: session_of(login),
+        user_id: other.user_id,
+    }
+}
+
+async fn send_request(app: &TestApp, from: &LoginResponse, to: &LoginResponse) -> ContactStatus {
+    let response: ContactRequestResponse = app
+        .post("/api/v1/users/contacts/requests", &target(from, to))
+        .await;
+
+    response.status
+}
+
+async fn contact_list(app: &TestApp, login: &LoginResponse) -> ContactListResponse {
+    app.post("/api/v1/users/contacts", &session_of(login)).await
+}
+
+fn usernames(contacts: &[Contact]) -> Vec<&str> {
+    contacts
+        .iter()
+        .map(|contact| contact.username.as_str())
+        .collect()
+}
+
+#[tokio::test]
+async fn contact_requests_can_be_accepted_and_declined() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+
+    assert_eq!(
+        send_request(&app, &alice, &bob).await,
+        ContactStatus::Pending
+    );
+    assert_eq!(
+        send_request(&app, &alice, &carol).await,
+        ContactStatus::Pending
+    );
+
+    let list = contact_list(&app, &bob).await;
+
+    assert_eq!(usernames(&list.incoming_requests), vec!["alice"]);
+    assert!(list.contacts.is_empty());
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::POST,
+            &format!("/api/v1/users/contacts/requests/{}/accept", alice.user_id),
+            &session_of(&bob),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // Carol answers with a request of her own, which accepts alice's
+    assert_eq!(
+        send_request(&app, &carol, &alice).await,
+        ContactStatus::Accepted
+    );
+
+    let list = contact_list(&app, &alice).await;
+
+    assert_eq!(usernames(&list.contacts), vec!["bob", "carol"]);
+    assert!(list.outgoing_requests.is_empty());
+
+    let remove_path = format!("/api/v1/users/contacts/{}", bob.user_id);
+
+    let (status, _, _) = app
+        .send_raw(Method::DELETE, &remove_path, &session_of(&alice))
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(usernames(&contact_list(&app, &bob).await.contacts).is_empty());
+
+    // Bob changes his mind and withdraws his new request
+    send_request(&app, &bob, &alice).await;
+
+    let decline_path = format!("/api/v1/users/contacts/requests/{}", alice.user_id);
+
+    let (status, _, _) = app
+        .send_raw(Method::DELETE, &decline_path, &session_of(&bob))
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, error) = app
+        .send_err(Method::DELETE, &decline_path, &session_of(&bob))
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::ContactNotFound);
+
+    let (status, error) = app
+        .post_err("/api/v1/users/contacts/requests", &target(&alice, &alice))
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+}
+
+#[tokio::test]
+async fn blocks_prevent_contact_requests_and_direct_messages() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    send_request(&app, &alice, &bob).await;
+
+    let block_path = format!("/api/v1/users/blocks/{}", bob.user_id);
+
+    let (status, _, _) = app
+        .send_raw(Method::PUT, &block_path, &session_of(&alice))
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // The pending request is gone together with the block
+    let list = contact_list(&app, &alice).await;
+
+    assert!(list.outgoing_requests.is_empty());
+    assert_eq!(usernames(&list.blocked_users), vec!["bob"]);
+
+    let (status, error) = app
+        .post_err("/api/v1/users/contacts/requests", &target(&bob, &alice))
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::UserBlocked);
+
+    for (from, to) in [(&bob, &alice), (&alice, &bob)] {
+        let (status, error) = app
+            .post_err("/api/v1/chatrooms/direct", &target(from, to))
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(error.code, ErrorCode::UserBlocked);
+    }
+
+    let (status, _, _) = app
+        .send_raw(Method::DELETE, &block_path, &session_of(&alice))
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let _: FetchChatroomResponse = app
+        .post("/api/v1/chatrooms/direct", &target(&bob, &alice))
+        .await;
+}
+
+#[tokio::test]
+async fn direct_messages_are_reused_and_hidden_from_lookups() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    let chatroom: FetchChatroomResponse = app
+        .post("/api/v1/chatrooms/direct", &target(&alice, &bob))
+        .await;
+
+    assert!(chatroom.is_direct_message);
+    assert_eq!(chatroom.chatroom_name, "alice, bob");
+    assert_eq!(
+        chatroom.participants,
+        vec![Some(alice.user_id), Some(bob.user_id)]
+    );
+
+    let reopened: FetchChatroomResponse = app
+        .post("/api/v1/chatrooms/direct", &target(&bob, &alice))
+        .await;
+
+    assert_eq!(reopened.chatroom_uid, chatroom.chatroom_uid);
+
+    let (status, _) = app
+        .post_err(
+            "/api/v1/chatrooms/lookup",
+            &FetchUnknownChatroom {
+                chatroom_id: chatroom.chatroom_id,
+                password: None,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn concurrent_opens_create_a_single_direct_message() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    let (to_bob, to_alice) = (target(&alice, &bob), target(&bob, &alice));
+
+    for _ in 0..5 {
+        let (from_alice, from_bob): (FetchChatroomResponse, FetchChatroomResponse) = tokio::join!(
+            app.post("/api/v1/chatrooms/direct", &to_bob),
+            app.post("/api/v1/chatrooms/direct", &to_alice),
+        );
+
+        assert_eq!(from_alice.chatroom_uid, from_bob.chatroom_uid);
+    }
+}
+
+#[tokio::test]
+async fn direct_messages_can_require_a_contact() {
+    let Some(app) = TestApp::spawn_with(|config| config.direct_messages_require_contact = true)
+    else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    let (status, error) = app
+        .post_err("/api/v1/chatrooms/direct", &target(&alice, &bob))
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::ContactRequired);
+
+    send_request(&app, &alice, &bob).await;
+    send_request(&app, &bob, &alice).await;
+
+    let chatroom: FetchChatroomResponse = app
+        .post("/api/v1/chatrooms/direct", &target(&alice, &bob))
+        .await;
+
+    assert!(chatroom.is_direct_message);
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use whatssock_lib::{CreateChatroomRequest, FetchChatroomResponse};
+use whatssock_server::api::{email_verification::EmailVerifiedResponse, error::ErrorCode};
+
+fn public_chatroom(login: &whatssock_lib::server::LoginResponse) -> CreateChatroomRequest {
+    CreateChatroomRequest {
+        user_session: session_of(login),
+        chatroom_name: "general".to_string(),
+        chatroom_passw: None,
+    }
+}
+
+#[tokio::test]
+async fn registration_sends_a_working_verification_link() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    let link = app.last_emailed_link("alice@example.com");
+
+    let verified: EmailVerifiedResponse = app.get(&link).await;
+
+    assert_eq!(verified.email, "alice@example.com");
+
+    // Opening the link again is harmless
+    let _: EmailVerifiedResponse = app.get(&link).await;
+}
+
+#[tokio::test]
+async fn tampered_verification_link_is_rejected() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    // Alice's signature does not cover Bob's account
+    let link = app.last_emailed_link("alice@example.com").replacen(
+        &format!("token={}.", alice.user_id),
+        &format!("token={}.", bob.user_id),
+        1,
+    );
+
+    let (status, error) = app.send_err(Method::GET, &link, &()).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidToken);
+
+    let (status, _) = app
+        .send_err(Method::GET, "/api/v1/users/verification?token=garbage", &())
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn public_chatrooms_require_a_verified_email() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let (status, error) = app
+        .post_err("/api/v1/chatrooms", &public_chatroom(&login))
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::EmailNotVerified);
+
+    // Password protected chatrooms are not restricted
+    let _: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms",
+            &CreateChatroomRequest {
+                chatroom_passw: Some("secret".to_string()),
+                ..public_chatroom(&login)
+            },
+        )
+        .await;
+
+    app.verify_email("alice").await;
+
+    let _: FetchChatroomResponse = app
+        .post("/api/v1/chatrooms", &public_chatroom(&login))
+        .await;
+}
+
+#[tokio::test]
+async fn verification_email_can_be_resent() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::POST,
+            "/api/v1/users/verification",
+            &session_of(&login),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(app.mailer.sent().len(), 2);
+
+    app.verify_email("alice").await;
+
+    let (status, error) = app
+        .post_err("/api/v1/users/verification", &session_of(&login))
+        .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error.code, ErrorCode::EmailAlreadyVerified);
+}
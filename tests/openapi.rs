@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::OpenApi;
+use whatssock_lib::{
+    CreateChatroomRequest, FetchChatroomResponse, FetchKnownChatroomResponse, FetchKnownChatrooms,
+    FetchUnknownChatroom, UserSession,
+    client::{LoginRequest, RegisterRequest, UserInformation},
+    server::{LoginResponse, LogoutResponse},
+};
+use whatssock_server::api::openapi::ApiDoc;
+
+/// The fields `value` is serialized with.
+fn serialized_fields(value: impl Serialize) -> BTreeSet<String> {
+    match serde_json::to_value(value).unwrap() {
+        Value::Object(fields) => fields.keys().cloned().collect(),
+        value => panic!("Expected an object, got {value}"),
+    }
+}
+
+/// The properties the component schema `name` documents.
+fn documented_fields(name: &str) -> BTreeSet<String> {
+    let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+    let schema = &openapi["components"]["schemas"][name];
+
+    assert!(
+        !schema.is_null(),
+        "{name} is missing from the specification"
+    );
+
+    schema["properties"]
+        .as_object()
+        .map(|properties| properties.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn user_session() -> UserSession {
+    UserSession {
+        user_id: 1,
+        session_token: vec![0; 32],
+    }
+}
+
+fn chatroom() -> FetchChatroomResponse {
+    FetchChatroomResponse {
+        chatroom_uid: 1,
+        chatroom_id: String::new(),
+        chatroom_name: String::new(),
+        participants: vec![],
+        is_direct_message: false,
+        last_message_id: None,
+    }
+}
+
+#[test]
+fn schemas_match_the_shared_types() {
+    let cases = [
+        (
+            "RegisterRequest",
+            serialized_fields(RegisterRequest {
+                username: String::new(),
+                password: String::new(),
+                email: String::new(),
+            }),
+        ),
+        (
+            "LoginRequest",
+            serialized_fields(LoginRequest {
+                username: String::new(),
+                password: String::new(),
+            }),
+        ),
+        (
+            "LoginResponse",
+            serialized_fields(LoginResponse {
+                user_id: 1,
+                session_token: [0; 32],
+                chatrooms_joined: vec![],
+            }),
+        ),
+        ("LogoutResponse", serialized_fields(LogoutResponse {})),
+        ("UserSession", serialized_fields(user_session())),
+        (
+            "UserInformation",
+            serialized_fields(UserInformation {
+                username: String::new(),
+                chatrooms_joined: vec![],
+            }),
+        ),
+        (
+            "CreateChatroomRequest",
+            serialized_fields(CreateChatroomRequest {
+                user_session: user_session(),
+                chatroom_name: String::new(),
+                chatroom_passw: None,
+            }),
+        ),
+        (
+            "FetchUnknownChatroom",
+            serialized_fields(FetchUnknownChatroom {
+                chatroom_id: String::new(),
+                password: None,
+            }),
+        ),
+        (
+            "FetchKnownChatrooms",
+            serialized_fields(FetchKnownChatrooms {
+                user_session: user_session(),
+                chatroom_uids: vec![],
+            }),
+        ),
+        ("FetchChatroomResponse", serialized_fields(chatroom())),
+        // The server answers with the shared type, extended with the number of pinned messages of every chatroom
+        (
+            "KnownChatroomsResponse",
+            serialized_fields(FetchKnownChatroomResponse {
+                chatrooms: vec![chatroom()],
+            }),
+        ),
+    ];
+
+    for (name, fields) in cases {
+        assert_eq!(
+            documented_fields(name),
+            fields,
+            "The schema of {name} in src/api/openapi.rs has drifted from whatssock-lib"
+        );
+    }
+}
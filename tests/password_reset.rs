@@ -0,0 +1,171 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use diesel::{RunQueryDsl, sql_query};
+use whatssock_lib::{client::LoginRequest, server::LoginResponse};
+use whatssock_server::api::{
+    error::ErrorCode,
+    password_reset::{ConfirmPasswordReset, PasswordResetRequest},
+};
+
+/// Requests a password reset for the account, returns the token emailed to it.
+async fn request_reset(app: &TestApp, email: &str) -> String {
+    let sent = app.mailer.sent().len();
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::POST,
+            "/api/v1/password_reset",
+            &PasswordResetRequest {
+                email: email.to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let email = app.wait_for_emails(sent + 1).await.pop().unwrap();
+
+    email
+        .body
+        .split_whitespace()
+        .find(|word| word.len() == 64 && word.chars().all(|c| c.is_ascii_hexdigit()))
+        .expect("The email does not contain a reset token")
+        .to_string()
+}
+
+async fn confirm_reset(app: &TestApp, token: &str, new_password: &str) -> StatusCode {
+    let (status, _, _) = app
+        .send_raw(
+            Method::POST,
+            "/api/v1/password_reset/confirm",
+            &ConfirmPasswordReset {
+                token: token.to_string(),
+                new_password: new_password.to_string(),
+            },
+        )
+        .await;
+
+    status
+}
+
+fn login_request(password: &str) -> LoginRequest {
+    LoginRequest {
+        username: "alice".to_string(),
+        password: password.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn reset_changes_the_password_and_revokes_sessions() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let token = request_reset(&app, "alice@example.com").await;
+
+    assert_eq!(
+        confirm_reset(&app, &token, "correct horse").await,
+        StatusCode::NO_CONTENT
+    );
+
+    let (status, error) = app
+        .post_err("/api/v1/sessions/current", &session_of(&login))
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidSession);
+
+    let _: LoginResponse = app
+        .post("/api/v1/sessions", &login_request("correct horse"))
+        .await;
+
+    let (status, _) = app
+        .post_err("/api/v1/sessions", &login_request("hunter2"))
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn reset_tokens_are_single_use() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    let first_token = request_reset(&app, "alice@example.com").await;
+    let second_token = request_reset(&app, "alice@example.com").await;
+
+    assert_eq!(
+        confirm_reset(&app, &second_token, "correct horse").await,
+        StatusCode::NO_CONTENT
+    );
+
+    // Completing a reset uses up every other outstanding token too
+    for token in [&second_token, &first_token] {
+        let (status, error) = app
+            .post_err(
+                "/api/v1/password_reset/confirm",
+                &ConfirmPasswordReset {
+                    token: token.clone(),
+                    new_password: "battery staple".to_string(),
+                },
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code, ErrorCode::InvalidToken);
+    }
+}
+
+#[tokio::test]
+async fn expired_reset_token_is_rejected() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    let token = request_reset(&app, "alice@example.com").await;
+
+    sql_query("UPDATE password_reset_tokens SET expires_at = NOW() - INTERVAL '1 minute'")
+        .execute(&mut app.database.connect())
+        .unwrap();
+
+    assert_eq!(
+        confirm_reset(&app, &token, "correct horse").await,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn unknown_email_is_indistinguishable() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let (status, _, body) = app
+        .send_raw(
+            Method::POST,
+            "/api/v1/password_reset",
+            &PasswordResetRequest {
+                email: "nobody@example.com".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(body.is_empty());
+
+    // The emails are sent in the background, give a mistakenly sent one the time to arrive
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert!(app.mailer.sent().is_empty());
+}
@@ -0,0 +1,364 @@
+mod common;
+
+use std::io::{Cursor, Read};
+
+use axum::http::{Method, StatusCode, header};
+use common::{TestApp, session_of};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde_json::Value;
+use whatssock_lib::{FetchChatroomResponse, client::LoginRequest, server::LoginResponse};
+use whatssock_server::{
+    api::{
+        chatroom_history::{ChatroomHistoryRequest, ChatroomHistoryResponse, TimelineContent},
+        chatroom_members::JoinChatroomRequest,
+        contacts::{ContactRequestResponse, UserTargetRequest},
+        personal_data::{DeleteAccountRequest, DeletedUserMessages, TOMBSTONE_USERNAME},
+        system_messages::SystemEvent,
+    },
+    schema::{chatrooms, messages, users},
+};
+use zip::ZipArchive;
+
+/// Creates a chatroom for the user, and sends a message of theirs to it.
+async fn chatroom_with_message(app: &TestApp, login: &LoginResponse) -> FetchChatroomResponse {
+    let chatroom = app.create_chatroom(login, "general", Some("secret")).await;
+
+    app.send_message(&chatroom, login).await;
+
+    chatroom
+}
+
+/// Reads a JSON file of the export archive of the user.
+async fn exported_json(app: &TestApp, login: &LoginResponse, name: &str) -> Value {
+    let (status, _, body) = app
+        .send_raw(Method::POST, "/api/v1/users/export", &session_of(login))
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+
+    let mut contents = String::new();
+
+    ZipArchive::new(Cursor::new(body))
+        .unwrap()
+        .by_name(name)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+
+    serde_json::from_str(&contents).unwrap()
+}
+
+fn delete_request(login: &LoginResponse, current_password: &str) -> DeleteAccountRequest {
+    DeleteAccountRequest {
+        user_session: session_of(login),
+        current_password: current_password.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn export_contains_the_users_data() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+    let chatroom = chatroom_with_message(&app, &login).await;
+
+    let (status, headers, body) = app
+        .send_raw(Method::POST, "/api/v1/users/export", &session_of(&login))
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "application/zip");
+    assert_eq!(
+        headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"whatssock-alice.zip\""
+    );
+
+    let mut archive = ZipArchive::new(Cursor::new(body)).unwrap();
+
+    let mut read_json = |name: &str| -> Value {
+        let mut contents = String::new();
+
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+
+        serde_json::from_str(&contents).unwrap()
+    };
+
+    let account = read_json("account.json");
+
+    assert_eq!(account["username"], "alice");
+    assert_eq!(account["email"], "alice@example.com");
+    assert!(account.get("passw").is_none());
+
+    assert_eq!(read_json("sessions.json").as_array().unwrap().len(), 1);
+
+    let memberships = read_json("chatrooms.json");
+
+    assert_eq!(memberships[0]["chatroom_id"], chatroom.chatroom_id);
+
+    let own_messages = read_json("messages.json");
+
+    assert_eq!(own_messages.as_array().unwrap().len(), 1);
+    assert_eq!(own_messages[0]["message"]["message"], "hello");
+    assert!(own_messages[0]["raw_message"].is_null());
+}
+
+#[tokio::test]
+async fn export_contains_the_contacts_and_blocks() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+    let dave = app.register("dave", "hunter2").await;
+    let erin = app.register("erin", "hunter2").await;
+
+    let request = |from: &LoginResponse, to: &LoginResponse| UserTargetRequest {
+        user_session: session_of(from),
+        user_id: to.user_id,
+    };
+
+    // A request sent back accepts the first one
+    for (from, to) in [
+        (&alice, &bob),
+        (&bob, &alice),
+        (&carol, &alice),
+        (&alice, &dave),
+    ] {
+        let _: ContactRequestResponse = app
+            .post("/api/v1/users/contacts/requests", &request(from, to))
+            .await;
+    }
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            &format!("/api/v1/users/blocks/{}", erin.user_id),
+            &session_of(&alice),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let contacts = exported_json(&app, &alice, "contacts.json").await;
+
+    assert_eq!(contacts["contacts"][0]["user_id"], bob.user_id);
+    assert!(!contacts["contacts"][0]["accepted_at"].is_null());
+    assert_eq!(contacts["incoming_requests"][0]["user_id"], carol.user_id);
+    assert_eq!(contacts["outgoing_requests"][0]["user_id"], dave.user_id);
+
+    let blocks = exported_json(&app, &alice, "blocks.json").await;
+
+    assert_eq!(blocks.as_array().unwrap().len(), 1);
+    assert_eq!(blocks[0]["user_id"], erin.user_id);
+}
+
+#[tokio::test]
+async fn deletion_anonymizes_the_messages() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+    let chatroom = chatroom_with_message(&app, &login).await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::DELETE,
+            "/api/v1/users",
+            &delete_request(&login, "hunter2"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = app
+        .post_err("/api/v1/sessions/current", &session_of(&login))
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app
+        .post_err(
+            "/api/v1/sessions",
+            &LoginRequest {
+                username: "alice".to_string(),
+                password: "hunter2".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let mut pg_connection = app.database.connect();
+
+    let tombstone_id: i32 = users::table
+        .filter(users::username.eq(TOMBSTONE_USERNAME))
+        .select(users::id)
+        .first(&mut pg_connection)
+        .unwrap();
+
+    let owners: Vec<(i32, Option<String>)> = messages::table
+        .order(messages::id)
+        .select((messages::owner_user_id, messages::event_kind))
+        .load(&mut pg_connection)
+        .unwrap();
+
+    assert_eq!(
+        owners,
+        vec![
+            (tombstone_id, None),
+            (tombstone_id, Some("member_left".to_string()))
+        ]
+    );
+
+    let participants: Vec<Option<i32>> = chatrooms::table
+        .find(chatroom.chatroom_uid)
+        .select(chatrooms::participants)
+        .first(&mut pg_connection)
+        .unwrap();
+
+    assert!(participants.is_empty());
+}
+
+#[tokio::test]
+async fn deletion_hands_owned_chatrooms_over() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+    let chatroom = chatroom_with_message(&app, &alice).await;
+    let uid = chatroom.chatroom_uid;
+
+    for login in [&bob, &carol] {
+        let _: FetchChatroomResponse = app
+            .post(
+                "/api/v1/chatrooms/join",
+                &JoinChatroomRequest {
+                    user_session: session_of(login),
+                    chatroom_id: chatroom.chatroom_id.clone(),
+                    password: Some("secret".to_string()),
+                },
+            )
+            .await;
+    }
+
+    // The admin is preferred to the longest-standing participant
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{uid}/admins/{}", carol.user_id),
+            &session_of(&alice),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::DELETE,
+            "/api/v1/users",
+            &delete_request(&alice, "hunter2"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (owner, admins): (Option<i32>, Vec<Option<i32>>) = chatrooms::table
+        .find(uid)
+        .select((chatrooms::owner_user_id, chatrooms::admin_user_ids))
+        .first(&mut app.database.connect())
+        .unwrap();
+
+    assert_eq!(owner, Some(carol.user_id));
+    assert!(admins.is_empty());
+
+    let history: ChatroomHistoryResponse = app
+        .post(
+            &format!("/api/v1/chatrooms/{uid}/history"),
+            &ChatroomHistoryRequest {
+                user_session: session_of(&bob),
+                before_message_id: None,
+                limit: Some(2),
+            },
+        )
+        .await;
+    let tombstone_id = history.entries[1].user_id;
+
+    let events: Vec<(i32, SystemEvent)> = history
+        .entries
+        .into_iter()
+        .filter_map(|entry| match entry.content {
+            TimelineContent::System { event } => Some((entry.user_id, event)),
+            TimelineContent::Message { .. } => None,
+        })
+        .collect();
+
+    assert_eq!(
+        events,
+        vec![
+            (
+                tombstone_id,
+                SystemEvent::OwnershipTransferred {
+                    previous_owner_user_id: tombstone_id,
+                    owner_user_id: carol.user_id,
+                }
+            ),
+            (tombstone_id, SystemEvent::MemberLeft),
+        ]
+    );
+
+    // The new owner can hand the chatroom over in turn
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            &format!("/api/v1/chatrooms/{uid}/owner"),
+            &UserTargetRequest {
+                user_session: session_of(&carol),
+                user_id: bob.user_id,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn deletion_can_delete_the_messages() {
+    let Some(app) =
+        TestApp::spawn_with(|config| config.deleted_user_messages = DeletedUserMessages::Delete)
+    else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+    chatroom_with_message(&app, &login).await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::DELETE,
+            "/api/v1/users",
+            &delete_request(&login, "hunter2"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // Only the system message recording the departure of the user is left
+    let remaining: Vec<Option<String>> = messages::table
+        .select(messages::event_kind)
+        .load(&mut app.database.connect())
+        .unwrap();
+
+    assert_eq!(remaining, vec![Some("member_left".to_string())]);
+}
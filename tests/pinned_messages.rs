@@ -0,0 +1,190 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use whatssock_lib::{FetchChatroomResponse, FetchKnownChatrooms, server::LoginResponse};
+use whatssock_server::api::{
+    chatroom_history::{ChatroomHistoryRequest, ChatroomHistoryResponse, TimelineContent},
+    chatroom_members::JoinChatroomRequest,
+    error::ErrorCode,
+    pinned_messages::{KnownChatroomsResponse, PinnedMessagesResponse},
+    system_messages::SystemEvent,
+};
+
+fn pin_path(chatroom: &FetchChatroomResponse, message_id: i32) -> String {
+    format!(
+        "/api/v1/chatrooms/{}/pins/{message_id}",
+        chatroom.chatroom_uid
+    )
+}
+
+async fn pinned_ids(
+    app: &TestApp,
+    chatroom: &FetchChatroomResponse,
+    login: &LoginResponse,
+) -> Vec<i32> {
+    app.post::<PinnedMessagesResponse>(
+        &format!("/api/v1/chatrooms/{}/pins", chatroom.chatroom_uid),
+        &session_of(login),
+    )
+    .await
+    .pinned_messages
+    .into_iter()
+    .map(|pinned| pinned.message.message_id)
+    .collect()
+}
+
+#[tokio::test]
+async fn admins_pin_messages_up_to_the_limit() {
+    let Some(app) = TestApp::spawn_with(|config| config.max_pinned_messages = 2) else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+
+    let _: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms/join",
+            &JoinChatroomRequest {
+                user_session: session_of(&bob),
+                chatroom_id: chatroom.chatroom_id.clone(),
+                password: Some("secret".to_string()),
+            },
+        )
+        .await;
+
+    let first = app.send_message(&chatroom, &bob).await;
+    let second = app.send_message(&chatroom, &alice).await;
+    let third = app.send_message(&chatroom, &bob).await;
+
+    let (status, error) = app
+        .send_err(Method::PUT, &pin_path(&chatroom, first), &session_of(&bob))
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::NotChatroomAdmin);
+
+    app.send_no_content(Method::PUT, &pin_path(&chatroom, first), &alice)
+        .await;
+    app.send_no_content(Method::PUT, &pin_path(&chatroom, second), &alice)
+        .await;
+    // Pinning again changes nothing
+    app.send_no_content(Method::PUT, &pin_path(&chatroom, second), &alice)
+        .await;
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &pin_path(&chatroom, third),
+            &session_of(&alice),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error.code, ErrorCode::PinLimitReached);
+
+    assert_eq!(pinned_ids(&app, &chatroom, &bob).await, vec![second, first]);
+
+    let known: KnownChatroomsResponse = app
+        .post(
+            "/api/v1/chatrooms/known",
+            &FetchKnownChatrooms {
+                user_session: session_of(&bob),
+                chatroom_uids: vec![chatroom.chatroom_uid],
+            },
+        )
+        .await;
+
+    assert_eq!(known.chatrooms[0].chatroom.chatroom_name, "general");
+    assert_eq!(known.chatrooms[0].pinned_message_count, 2);
+
+    app.send_no_content(Method::DELETE, &pin_path(&chatroom, first), &alice)
+        .await;
+    app.send_no_content(Method::PUT, &pin_path(&chatroom, third), &alice)
+        .await;
+
+    assert_eq!(pinned_ids(&app, &chatroom, &bob).await, vec![third, second]);
+
+    let history: ChatroomHistoryResponse = app
+        .post(
+            &format!("/api/v1/chatrooms/{}/history", chatroom.chatroom_uid),
+            &ChatroomHistoryRequest {
+                user_session: session_of(&bob),
+                before_message_id: None,
+                limit: None,
+            },
+        )
+        .await;
+
+    let pin_events: Vec<SystemEvent> = history
+        .entries
+        .into_iter()
+        .filter_map(|entry| match entry.content {
+            TimelineContent::System { event } if event != SystemEvent::MemberJoined => Some(event),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(
+        pin_events,
+        vec![
+            SystemEvent::MessagePinned { message_id: first },
+            SystemEvent::MessagePinned { message_id: second },
+            SystemEvent::MessageUnpinned { message_id: first },
+            SystemEvent::MessagePinned { message_id: third },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn only_messages_of_the_chatroom_can_be_pinned() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+    let other_chatroom = app.create_chatroom(&alice, "general", Some("secret")).await;
+
+    let other_message = app.send_message(&other_chatroom, &alice).await;
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &pin_path(&chatroom, other_message),
+            &session_of(&alice),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::MessageNotFound);
+
+    // System messages are not pinned either
+    let message = app.send_message(&chatroom, &alice).await;
+    app.send_no_content(Method::PUT, &pin_path(&chatroom, message), &alice)
+        .await;
+
+    let known: KnownChatroomsResponse = app
+        .post(
+            "/api/v1/chatrooms/known",
+            &FetchKnownChatrooms {
+                user_session: session_of(&alice),
+                chatroom_uids: vec![chatroom.chatroom_uid],
+            },
+        )
+        .await;
+    let system_message = known.chatrooms[0].chatroom.last_message_id.unwrap();
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            &pin_path(&chatroom, system_message),
+            &session_of(&alice),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::MessageNotFound);
+}
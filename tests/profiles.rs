@@ -0,0 +1,263 @@
+mod common;
+
+use axum::http::{Method, StatusCode, header};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use common::{TestApp, session_of};
+use whatssock_lib::{FetchChatroomResponse, server::LoginResponse};
+use whatssock_server::api::{
+    chatroom_members::JoinChatroomRequest,
+    error::ErrorCode,
+    profiles::{
+        FetchProfilesRequest, FetchProfilesResponse, UpdateProfileRequest, UploadAvatarRequest,
+        UserProfile,
+    },
+};
+
+/// The smallest valid PNG signature followed by some filler bytes.
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n0000";
+
+fn update_request(login: &LoginResponse, display_name: &str) -> UpdateProfileRequest {
+    UpdateProfileRequest {
+        user_session: session_of(login),
+        display_name: Some(display_name.to_string()),
+        bio: Some(String::from("Hello there")),
+        status_text: Some(String::from("Out for lunch")),
+        status_emoji: Some(String::from("🍜")),
+    }
+}
+
+/// Puts both users into a new chatroom of `owner`.
+async fn share_chatroom(app: &TestApp, owner: &LoginResponse, other: &LoginResponse) {
+    let chatroom = app.create_chatroom(owner, "general", Some("secret")).await;
+
+    let _: FetchChatroomResponse = app
+        .post(
+            "/api/v1/chatrooms/join",
+            &JoinChatroomRequest {
+                user_session: session_of(other),
+                chatroom_id: chatroom.chatroom_id,
+                password: Some("secret".to_string()),
+            },
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn profile_can_be_updated_and_cleared() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let login = app.register("alice", "hunter2").await;
+
+    let (status, _, body) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/profile",
+            &update_request(&login, "Alice"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+
+    let profile: UserProfile = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(profile.username, "alice");
+    assert_eq!(profile.display_name.as_deref(), Some("Alice"));
+    assert_eq!(profile.status_emoji.as_deref(), Some("🍜"));
+    assert!(!profile.has_avatar);
+
+    // Empty fields clear the stored value
+    let (_, _, body) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/profile",
+            &UpdateProfileRequest {
+                user_session: session_of(&login),
+                display_name: Some(String::from("  ")),
+                bio: None,
+                status_text: None,
+                status_emoji: None,
+            },
+        )
+        .await;
+
+    let profile: UserProfile = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(profile.display_name, None);
+    assert_eq!(profile.bio, None);
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/profile",
+            &update_request(&login, &"a".repeat(65)),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+
+    // Emoji made of several code points are single emoji as well, and so are the symbols which have an emoji form
+    for status_emoji in ["🇫🇷", "👍🏽", "❤️", "👩‍💻", "1️⃣", "↔️", "▶️", "⭐"]
+    {
+        let profile: UserProfile = app
+            .send(
+                Method::PUT,
+                "/api/v1/users/profile",
+                &UpdateProfileRequest {
+                    status_emoji: Some(status_emoji.to_string()),
+                    ..update_request(&login, "Alice")
+                },
+            )
+            .await;
+
+        assert_eq!(profile.status_emoji.as_deref(), Some(status_emoji));
+    }
+
+    // Arrows, shapes and skin tones are not emoji on their own
+    for status_emoji in ["ok", "🍜🍜", "🍜a", "👩‍", "→", "■", "\u{1F3FD}"] {
+        let (status, error) = app
+            .send_err(
+                Method::PUT,
+                "/api/v1/users/profile",
+                &UpdateProfileRequest {
+                    status_emoji: Some(status_emoji.to_string()),
+                    ..update_request(&login, "Alice")
+                },
+            )
+            .await;
+
+        assert_eq!(
+            status,
+            StatusCode::BAD_REQUEST,
+            "{status_emoji} was accepted"
+        );
+        assert_eq!(error.code, ErrorCode::InvalidInput);
+    }
+}
+
+#[tokio::test]
+async fn profiles_are_only_visible_to_chatroom_participants() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+
+    share_chatroom(&app, &alice, &bob).await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/profile",
+            &update_request(&bob, "Bob"),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+
+    // Carol does not share a chatroom with alice, so she is left out
+    let response: FetchProfilesResponse = app
+        .post(
+            "/api/v1/users/profiles",
+            &FetchProfilesRequest {
+                user_session: session_of(&alice),
+                user_ids: vec![bob.user_id, carol.user_id, alice.user_id],
+            },
+        )
+        .await;
+
+    let profiles: Vec<(&str, Option<&str>)> = response
+        .profiles
+        .iter()
+        .map(|profile| (profile.username.as_str(), profile.display_name.as_deref()))
+        .collect();
+
+    assert_eq!(profiles, vec![("bob", Some("Bob")), ("alice", None)]);
+}
+
+#[tokio::test]
+async fn avatars_are_served_with_their_detected_type() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+
+    share_chatroom(&app, &alice, &bob).await;
+
+    let (status, error) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/profile/avatar",
+            &UploadAvatarRequest {
+                user_session: session_of(&bob),
+                image: BASE64.encode(b"<svg></svg>"),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+
+    // The configured limit of the tests is 1 KiB
+    let (status, _) = app
+        .send_err(
+            Method::PUT,
+            "/api/v1/users/profile/avatar",
+            &UploadAvatarRequest {
+                user_session: session_of(&bob),
+                image: BASE64.encode([PNG, &[0; 1024]].concat()),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/profile/avatar",
+            &UploadAvatarRequest {
+                user_session: session_of(&bob),
+                image: BASE64.encode(PNG),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let avatar_path = format!("/api/v1/users/{}/avatar", bob.user_id);
+
+    let (status, headers, body) = app
+        .send_raw(Method::POST, &avatar_path, &session_of(&alice))
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+    assert_eq!(body, PNG);
+
+    let (status, error) = app.post_err(&avatar_path, &session_of(&carol)).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error.code, ErrorCode::UserNotFound);
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::DELETE,
+            "/api/v1/users/profile/avatar",
+            &session_of(&bob),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = app.post_err(&avatar_path, &session_of(&alice)).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
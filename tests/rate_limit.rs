@@ -0,0 +1,199 @@
+mod common;
+
+use axum::http::{Method, StatusCode, header};
+use common::TestApp;
+use whatssock_lib::{
+    FetchUnknownChatroom,
+    client::{LoginRequest, RegisterRequest},
+    server::LoginResponse,
+};
+use whatssock_server::api::error::{ApiErrorResponse, ErrorCode};
+
+fn login_request(password: &str) -> LoginRequest {
+    LoginRequest {
+        username: "alice".to_string(),
+        password: password.to_string(),
+    }
+}
+
+/// Looks up a chatroom through a proxy at `127.0.0.1`, which has forwarded the request for `forwarded_for`.
+async fn lookup_from(app: &TestApp, forwarded_for: &str) -> StatusCode {
+    let lookup = FetchUnknownChatroom {
+        chatroom_id: "general".to_string(),
+        password: None,
+    };
+
+    app.send_raw_with_headers(
+        Method::POST,
+        "/api/v1/chatrooms/lookup",
+        &[("x-forwarded-for", forwarded_for)],
+        &lookup,
+    )
+    .await
+    .0
+}
+
+#[tokio::test]
+async fn failed_login_blocks_the_next_attempt() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    let (status, _) = app
+        .post_err("/api/v1/sessions", &login_request("hunter3"))
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Even the correct password is rejected until the backoff has elapsed
+    let (status, headers, body) = app
+        .send_raw(Method::POST, "/api/v1/sessions", &login_request("hunter2"))
+        .await;
+
+    let error: ApiErrorResponse = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error.code, ErrorCode::RateLimited);
+    assert_eq!(headers[header::RETRY_AFTER], "1");
+
+    // Other accounts are not affected
+    app.register("bob", "hunter2").await;
+
+    let _: LoginResponse = app
+        .post(
+            "/api/v1/sessions",
+            &LoginRequest {
+                username: "bob".to_string(),
+                password: "hunter2".to_string(),
+            },
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn repeated_failed_logins_lock_the_account() {
+    let Some(app) = TestApp::spawn_with(|config| config.login_lockout_threshold = 1) else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    app.post_err("/api/v1/sessions", &login_request("hunter3"))
+        .await;
+
+    let (status, headers, _) = app
+        .send_raw(Method::POST, "/api/login", &login_request("hunter2"))
+        .await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(headers[header::RETRY_AFTER], "900");
+}
+
+#[tokio::test]
+async fn chatroom_lookups_are_limited_per_chatroom() {
+    let Some(app) = TestApp::spawn_with(|config| config.auth_rate_limit_per_target = 2) else {
+        return;
+    };
+
+    let lookup = |chatroom_id: &str| FetchUnknownChatroom {
+        chatroom_id: chatroom_id.to_string(),
+        password: Some("guess".to_string()),
+    };
+
+    for _ in 0..2 {
+        let (status, _) = app
+            .post_err("/api/v1/chatrooms/lookup", &lookup("general"))
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    let (status, error) = app
+        .post_err("/api/v1/chatrooms/lookup", &lookup("general"))
+        .await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error.code, ErrorCode::RateLimited);
+
+    let (status, _) = app
+        .post_err("/api/v1/chatrooms/lookup", &lookup("random"))
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn registrations_are_limited_per_ip() {
+    let Some(app) = TestApp::spawn_with(|config| config.auth_rate_limit_per_ip = 2) else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+    app.register("bob", "hunter2").await;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/users",
+            &RegisterRequest {
+                username: "carol".to_string(),
+                password: "hunter2".to_string(),
+                email: "carol@example.com".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error.code, ErrorCode::RateLimited);
+}
+
+#[tokio::test]
+async fn clients_behind_trusted_proxies_are_limited_separately() {
+    let Some(app) = TestApp::spawn_with(|config| {
+        config.auth_rate_limit_per_ip = 2;
+        config.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    }) else {
+        return;
+    };
+
+    for _ in 0..2 {
+        let status = lookup_from(&app, "203.0.113.1").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    let status = lookup_from(&app, "203.0.113.1").await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // The address the proxy has appended is the client, the ones before it are whatever the client has sent
+    let status = lookup_from(&app, "198.51.100.7, 203.0.113.1").await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    let status = lookup_from(&app, "203.0.113.2").await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_rejected_with_an_error_body() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/users",
+            &RegisterRequest {
+                username: "a".repeat(20_000),
+                password: "hunter2".to_string(),
+                email: "alice@example.com".to_string(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(error.code, ErrorCode::PayloadTooLarge);
+}
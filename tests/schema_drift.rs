@@ -0,0 +1,100 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::TestDatabase;
+use diesel::{
+    QueryableByName, RunQueryDsl, sql_query,
+    sql_types::{Text, Varchar},
+};
+
+/// Table name -> column name -> (type, nullable)
+type Tables = BTreeMap<String, BTreeMap<String, (String, bool)>>;
+
+#[derive(QueryableByName)]
+struct ColumnRow {
+    #[diesel(sql_type = Varchar)]
+    table_name: String,
+    #[diesel(sql_type = Varchar)]
+    column_name: String,
+    #[diesel(sql_type = Varchar)]
+    udt_name: String,
+    #[diesel(sql_type = Text)]
+    is_nullable: String,
+}
+
+/// Reads the tables declared by the `table!` macros of `schema.rs`.
+fn declared_tables() -> Tables {
+    let mut tables = Tables::new();
+    let mut current_table = None;
+
+    for line in include_str!("../src/schema.rs").lines().map(str::trim) {
+        if let Some((table_name, _)) = line.split_once(" (").filter(|_| line.ends_with('{')) {
+            current_table = Some(table_name.to_string());
+            tables.insert(table_name.to_string(), BTreeMap::new());
+        } else if line == "}" {
+            current_table = None;
+        } else if let (Some(table_name), Some((column_name, sql_type))) =
+            (&current_table, line.split_once(" -> "))
+        {
+            let sql_type = sql_type.trim_end_matches(',');
+            let (sql_type, nullable) = match sql_type.strip_prefix("Nullable<") {
+                Some(inner) => (&inner[..inner.len() - 1], true),
+                None => (sql_type, false),
+            };
+
+            tables
+                .get_mut(table_name)
+                .unwrap()
+                .insert(column_name.to_string(), (udt_name(sql_type), nullable));
+        }
+    }
+
+    tables
+}
+
+/// Converts a diesel sql type to the name postgres reports in `information_schema.columns.udt_name`.
+fn udt_name(sql_type: &str) -> String {
+    match sql_type.strip_prefix("Array<") {
+        // Arrays are named after their element type with a leading underscore, the nullability of the elements is not tracked
+        Some(element_type) => {
+            let element_type = element_type.trim_end_matches('>');
+            let element_type = element_type
+                .strip_prefix("Nullable<")
+                .unwrap_or(element_type);
+
+            format!("_{}", udt_name(element_type))
+        }
+        None => sql_type.to_lowercase(),
+    }
+}
+
+#[test]
+fn schema_matches_migrations() {
+    let Some(test_database) = TestDatabase::create() else {
+        return;
+    };
+
+    let rows: Vec<ColumnRow> = sql_query(
+        "SELECT table_name::varchar, column_name::varchar, udt_name::varchar, is_nullable::text
+        FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name != '__diesel_schema_migrations'",
+    )
+    .load(&mut test_database.connect())
+    .unwrap();
+
+    let mut migrated_tables = Tables::new();
+
+    for row in rows {
+        migrated_tables
+            .entry(row.table_name)
+            .or_default()
+            .insert(row.column_name, (row.udt_name, row.is_nullable == "YES"));
+    }
+
+    assert_eq!(
+        declared_tables(),
+        migrated_tables,
+        "src/schema.rs has drifted from the migrations, regenerate it with `diesel print-schema`"
+    );
+}
@@ -0,0 +1,273 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use chrono::Utc;
+use common::{TestApp, session_of};
+use diesel::{RunQueryDsl, sql_query};
+use totp_rs::{Algorithm, Secret, TOTP};
+use whatssock_lib::{
+    UserSession,
+    client::{LoginRequest, UserInformation},
+    server::LoginResponse,
+};
+use whatssock_server::api::{
+    error::ErrorCode,
+    two_factor::{
+        CompleteLoginRequest, LoginChallenge, RecoveryCodesResponse, TotpEnrollment,
+        TwoFactorCodeRequest, TwoFactorLoginResponse,
+    },
+};
+
+/// The code the authenticator of `secret` shows `offset_secs` from now.
+fn code_of(secret: &str, offset_secs: i64) -> String {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+
+    TOTP::new_unchecked(Algorithm::SHA1, 6, 0, 30, secret, None, String::new())
+        .generate((Utc::now().timestamp() + offset_secs) as u64)
+}
+
+fn login_request(username: &str) -> LoginRequest {
+    LoginRequest {
+        username: username.to_string(),
+        password: String::from("hunter2"),
+    }
+}
+
+/// Sends the password step of a login which is expected to return a challenge.
+async fn login_challenge(app: &TestApp, username: &str) -> LoginChallenge {
+    let (status, _, body) = app
+        .send_raw(Method::POST, "/api/v1/sessions", &login_request(username))
+        .await;
+
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Registers a user and sets up two-factor authentication for them.
+/// Returns their secret and their recovery codes.
+async fn register_with_two_factor(app: &TestApp, username: &str) -> (String, Vec<String>) {
+    let login = app.register(username, "hunter2").await;
+
+    let enrollment: TotpEnrollment = app
+        .post("/api/v1/users/two_factor", &session_of(&login))
+        .await;
+
+    let RecoveryCodesResponse { recovery_codes } = app
+        .post(
+            "/api/v1/users/two_factor/confirm",
+            &TwoFactorCodeRequest {
+                user_session: session_of(&login),
+                code: code_of(&enrollment.secret, 0),
+            },
+        )
+        .await;
+
+    (enrollment.secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn login_requires_a_code_once_enrolled() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let (secret, recovery_codes) = register_with_two_factor(&app, "alice").await;
+
+    assert_eq!(recovery_codes.len(), 10);
+
+    let challenge = login_challenge(&app, "alice").await;
+
+    assert!(challenge.enrollment.is_none());
+
+    // The code of the next step, the one of the current step has been used up by the enrolment
+    let login: TwoFactorLoginResponse = app
+        .post(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge.clone(),
+                code: code_of(&secret, 30),
+            },
+        )
+        .await;
+
+    assert!(login.recovery_codes.is_none());
+
+    let user_information: UserInformation = app
+        .post("/api/v1/sessions/current", &session_of(&login.login))
+        .await;
+
+    assert_eq!(user_information.username, "alice");
+
+    // A completed challenge can not be used again
+    let (status, error) = app
+        .post_err(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge,
+                code: recovery_codes[0].clone(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidToken);
+}
+
+#[tokio::test]
+async fn recovery_codes_are_single_use() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let (_, recovery_codes) = register_with_two_factor(&app, "alice").await;
+
+    let challenge = login_challenge(&app, "alice").await;
+
+    // Codes are accepted regardless of case and separators
+    let _: TwoFactorLoginResponse = app
+        .post(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge,
+                code: recovery_codes[0].to_uppercase().replace('-', " "),
+            },
+        )
+        .await;
+
+    let challenge = login_challenge(&app, "alice").await;
+
+    let (status, error) = app
+        .post_err(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge,
+                code: recovery_codes[0].clone(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error.code, ErrorCode::InvalidTwoFactorCode);
+}
+
+#[tokio::test]
+async fn incorrect_codes_count_as_failed_logins() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let (secret, _) = register_with_two_factor(&app, "alice").await;
+
+    let challenge = login_challenge(&app, "alice").await;
+
+    let (status, _) = app
+        .post_err(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge.clone(),
+                code: String::from("000000"),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Even the correct code is refused until the backoff has passed
+    let (status, error) = app
+        .post_err(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge,
+                code: code_of(&secret, 30),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error.code, ErrorCode::RateLimited);
+}
+
+#[tokio::test]
+async fn admins_have_to_enrol_when_logging_in() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    app.register("alice", "hunter2").await;
+
+    sql_query("UPDATE users SET is_admin = TRUE WHERE username = 'alice'")
+        .execute(&mut app.database.connect())
+        .unwrap();
+
+    let challenge = login_challenge(&app, "alice").await;
+    let enrollment = challenge.enrollment.expect("The admin has to enrol");
+
+    assert!(enrollment.otpauth_url.starts_with("otpauth://totp/"));
+
+    let login: TwoFactorLoginResponse = app
+        .post(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge,
+                code: code_of(&enrollment.secret, 0),
+            },
+        )
+        .await;
+
+    let recovery_codes = login
+        .recovery_codes
+        .expect("Confirming the enrolment issues recovery codes");
+
+    let (status, error) = app
+        .send_err(
+            Method::DELETE,
+            "/api/v1/users/two_factor",
+            &TwoFactorCodeRequest {
+                user_session: session_of(&login.login),
+                code: recovery_codes[0].clone(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error.code, ErrorCode::TwoFactorRequired);
+}
+
+#[tokio::test]
+async fn disabling_two_factor_restores_password_logins() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let (_, recovery_codes) = register_with_two_factor(&app, "alice").await;
+
+    let challenge = login_challenge(&app, "alice").await;
+
+    let login: TwoFactorLoginResponse = app
+        .post(
+            "/api/v1/sessions/two_factor",
+            &CompleteLoginRequest {
+                challenge: challenge.challenge,
+                code: recovery_codes[0].clone(),
+            },
+        )
+        .await;
+
+    let user_session: UserSession = session_of(&login.login);
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::DELETE,
+            "/api/v1/users/two_factor",
+            &TwoFactorCodeRequest {
+                user_session,
+                code: recovery_codes[1].clone(),
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let _: LoginResponse = app.post("/api/v1/sessions", &login_request("alice")).await;
+}
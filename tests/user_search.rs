@@ -0,0 +1,198 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, session_of};
+use whatssock_lib::server::LoginResponse;
+use whatssock_server::api::{
+    contacts::UserTargetRequest,
+    error::ErrorCode,
+    profiles::UpdateProfileRequest,
+    user_search::{
+        Discoverability, SearchUsersRequest, SearchUsersResponse, UpdateDiscoverabilityRequest,
+    },
+};
+
+fn search_request(login: &LoginResponse, query: &str) -> SearchUsersRequest {
+    SearchUsersRequest {
+        user_session: session_of(login),
+        query: query.to_string(),
+        offset: 0,
+        limit: None,
+    }
+}
+
+async fn search(app: &TestApp, login: &LoginResponse, query: &str) -> Vec<String> {
+    let mut usernames: Vec<String> = app
+        .post::<SearchUsersResponse>("/api/v1/users/search", &search_request(login, query))
+        .await
+        .results
+        .into_iter()
+        .map(|result| result.username)
+        .collect();
+
+    usernames.sort();
+
+    usernames
+}
+
+async fn set_discoverability(
+    app: &TestApp,
+    login: &LoginResponse,
+    discoverability: Discoverability,
+) {
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/discoverability",
+            &UpdateDiscoverabilityRequest {
+                user_session: session_of(login),
+                discoverability,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn users_are_found_by_prefix_and_fuzzy_matches() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let carol = app.register("carol", "hunter2").await;
+    let alice = app.register("alice", "hunter2").await;
+    app.register("alicia", "hunter2").await;
+    app.register("al_bundy", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            "/api/v1/users/profile",
+            &UpdateProfileRequest {
+                user_session: session_of(&bob),
+                display_name: Some(String::from("Alice Cooper")),
+                bio: None,
+                status_text: None,
+                status_emoji: None,
+            },
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+
+    // Display names match as well, and the searching user is left out
+    assert_eq!(
+        search(&app, &carol, "ALI").await,
+        vec!["alice", "alicia", "bob"]
+    );
+    assert_eq!(search(&app, &carol, "xalice").await, vec!["alice"]);
+    // Wildcards of the query match literally
+    assert_eq!(search(&app, &carol, "al_").await, vec!["al_bundy"]);
+    assert_eq!(search(&app, &alice, "car").await, vec!["carol"]);
+
+    // Paging through the results one by one yields every match exactly once
+    let mut paged = Vec::new();
+    let mut offset = Some(0);
+
+    while let Some(current_offset) = offset {
+        let page: SearchUsersResponse = app
+            .post(
+                "/api/v1/users/search",
+                &SearchUsersRequest {
+                    offset: current_offset,
+                    limit: Some(1),
+                    ..search_request(&carol, "ali")
+                },
+            )
+            .await;
+
+        assert!(page.results.len() <= 1);
+
+        paged.extend(page.results.into_iter().map(|result| result.username));
+        offset = page.next_offset;
+    }
+
+    paged.sort();
+
+    assert_eq!(paged, vec!["alice", "alicia", "bob"]);
+
+    let (status, error) = app
+        .post_err("/api/v1/users/search", &search_request(&carol, " a "))
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error.code, ErrorCode::InvalidInput);
+}
+
+#[tokio::test]
+async fn privacy_settings_and_blocks_hide_users() {
+    let Some(app) = TestApp::spawn() else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+    let carol = app.register("carol", "hunter2").await;
+
+    // The tombstone of deleted accounts is never listed
+    assert!(search(&app, &bob, "[deleted]").await.is_empty());
+
+    for (from, to) in [(&alice, &bob), (&bob, &alice)] {
+        let _: serde_json::Value = app
+            .post(
+                "/api/v1/users/contacts/requests",
+                &UserTargetRequest {
+                    user_session: session_of(from),
+                    user_id: to.user_id,
+                },
+            )
+            .await;
+    }
+
+    set_discoverability(&app, &alice, Discoverability::Contacts).await;
+
+    assert_eq!(search(&app, &bob, "alice").await, vec!["alice"]);
+    assert!(search(&app, &carol, "alice").await.is_empty());
+
+    set_discoverability(&app, &alice, Discoverability::Hidden).await;
+
+    assert!(search(&app, &bob, "alice").await.is_empty());
+
+    // Blocks hide the users from each other, whoever has blocked whom
+    let (status, _, _) = app
+        .send_raw(
+            Method::PUT,
+            &format!("/api/v1/users/blocks/{}", carol.user_id),
+            &session_of(&bob),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(search(&app, &carol, "bob").await.is_empty());
+    assert!(search(&app, &bob, "carol").await.is_empty());
+}
+
+#[tokio::test]
+async fn searches_are_rate_limited_per_user() {
+    let Some(app) = TestApp::spawn_with(|config| config.search_rate_limit_per_user = 2) else {
+        return;
+    };
+
+    let alice = app.register("alice", "hunter2").await;
+    let bob = app.register("bob", "hunter2").await;
+
+    search(&app, &alice, "bob").await;
+    search(&app, &alice, "bob").await;
+
+    let (status, error) = app
+        .post_err("/api/v1/users/search", &search_request(&alice, "bob"))
+        .await;
+
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error.code, ErrorCode::RateLimited);
+
+    // Other users have their own limit
+    assert_eq!(search(&app, &bob, "alice").await, vec!["alice"]);
+}
@@ -0,0 +1,30 @@
+use axum::{
+    body::{Body, to_bytes},
+    http::{Request, StatusCode},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+use whatssock_server::api::versions::api_versions;
+
+#[tokio::test]
+async fn versions_lists_current_and_deprecated() {
+    let router = axum::Router::new().route("/api/versions", axum::routing::get(api_versions));
+
+    let response = router
+        .oneshot(Request::get("/api/versions").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body: Value =
+        serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+
+    assert_eq!(
+        body["versions"],
+        json!([
+            { "version": "v1", "base_path": "/api/v1", "status": "current" },
+            { "version": "legacy", "base_path": "/api", "status": "deprecated" },
+        ])
+    );
+}